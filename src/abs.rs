@@ -1,13 +1,11 @@
 /// make all names in a module absolute
 
 use super::ast;
-use super::parser::{emit_error, emit_warn};
+use super::parser::{emit_error, emit_warn, error};
 use std::collections::HashMap;
 use super::name::Name;
 use super::loader;
-use std::sync::atomic::{AtomicBool, Ordering};
-
-static ABORT: AtomicBool = AtomicBool::new(false);
+use super::Error;
 
 pub struct Ext {
     pub ext: HashMap<Name, ast::Local>,
@@ -43,6 +41,8 @@ struct InScope {
 #[derive(Default)]
 struct Scope {
     v: Vec<HashMap<String, InScope>>,
+    // an error was reported, but abs goes on to find more
+    abort: std::cell::Cell<bool>,
 }
 
 
@@ -75,15 +75,14 @@ impl Scope{
         None
     }
 
-    pub fn insert(&mut self, local: String, fqn: Name, loc: &ast::Location, is_module: bool, subtypes: bool) {
+    pub fn insert(&mut self, local: String, fqn: Name, loc: &ast::Location, is_module: bool, subtypes: bool) -> Result<(), Error> {
         if let Some(previous) = self.cur().get(&local) {
             if !is_module || !previous.is_module || fqn != previous.name {
 
-                emit_error(format!("conflicting local name '{}'", local), &[
+                return Err(error(format!("conflicting local name '{}'", local), &[
                        (loc.clone(), "declared here"),
                        (previous.loc.clone(), "also declared here"),
-                ]);
-                std::process::exit(9);
+                ]));
             }
         }
         trace!("  insert {} := {}", local, fqn);
@@ -93,6 +92,7 @@ impl Scope{
             is_module,
             subtypes,
         });
+        Ok(())
     }
 
     pub fn tags(&self, tags: &mut ast::Tags) {
@@ -181,13 +181,13 @@ impl Scope{
                         emit_error(format!("possibly undefined name '{}'", lhs), &[
                               (t.loc.clone(), "cannot use :: notation to reference names not tracked by zz"),
                         ]);
-                        ABORT.store(true, Ordering::Relaxed);
+                        self.abort.set(true);
                     }
                 } else {
                     emit_error(format!("undefined name '{}'", lhs), &[
                                (t.loc.clone(), "used in this scope"),
                     ]);
-                    ABORT.store(true, Ordering::Relaxed);
+                    self.abort.set(true);
                 }
             },
             Some(v) => {
@@ -195,7 +195,7 @@ impl Scope{
                     emit_error(format!("resolving '{}' as member is not possible", name), &[
                         (t.loc.clone(), format!("'{}' is not a module", lhs))
                     ]);
-                    ABORT.store(true, Ordering::Relaxed);
+                    self.abort.set(true);
                 }
 
                 /*
@@ -205,7 +205,7 @@ impl Scope{
                            (t.loc, format!("'{}' is a c header", lhs)),
                            (v.loc, format!("suggestion: add '{}' to this import", rhs.join("::")))
                            );
-                    ABORT.store(true, Ordering::Relaxed);
+                    self.abort.set(true);
                 }
                 */
 
@@ -214,7 +214,7 @@ impl Scope{
                            (t.loc.clone(), format!("cannot use module '{}' as a type", name)),
                            (v.loc.clone(), format!("if you wanted to import '{}' as a type, use ::{{{}}} here", name, name)),
                     ]);
-                    ABORT.store(true, Ordering::Relaxed);
+                    self.abort.set(true);
                 }
 
                 let mut vv = v.name.clone();
@@ -227,22 +227,22 @@ impl Scope{
 }
 
 
-fn abs_import(imported_from: &Name, import: &ast::Import, all_modules: &HashMap<Name, loader::Module>) -> Name {
+fn abs_import(imported_from: &Name, import: &ast::Import, all_modules: &HashMap<Name, loader::Module>) -> Result<Name, Error> {
     if import.name.is_absolute() {
         if all_modules.contains_key(&import.name) {
             debug!("  import abs {} => {}", import.name, import.name);
-            return import.name.clone();
+            return Ok(import.name.clone());
         }
 
         // self
         if &import.name == imported_from  {
             debug!("  import self abs {}", import.name);
-            return import.name.clone();
+            return Ok(import.name.clone());
         }
 
         if let Some("ext") = import.name.0.get(1).map(|s|s.as_str()) {
             debug!("  import ext {} ", import.name);
-            return import.name.clone();
+            return Ok(import.name.clone());
         }
 
     } else {
@@ -255,7 +255,7 @@ fn abs_import(imported_from: &Name, import: &ast::Import, all_modules: &HashMap<
         if all_modules.contains_key(&search) {
             if &search != imported_from {
                 debug!("  import rel {} => {}", import.name, search);
-                return search;
+                return Ok(search);
             }
         }
 
@@ -265,7 +265,7 @@ fn abs_import(imported_from: &Name, import: &ast::Import, all_modules: &HashMap<
         if all_modules.contains_key(&search) {
             if &search != imported_from {
                 debug!("  import aabs {} => {}", import.name, search);
-                return search;
+                return Ok(search);
             }
         }
 
@@ -275,7 +275,7 @@ fn abs_import(imported_from: &Name, import: &ast::Import, all_modules: &HashMap<
         if all_modules.contains_key(&search) {
             if &search != imported_from {
                 debug!("  import aabs/lib {} => {}", import.name, search);
-                return search;
+                return Ok(search);
             }
         }
 
@@ -285,7 +285,7 @@ fn abs_import(imported_from: &Name, import: &ast::Import, all_modules: &HashMap<
         if &search == imported_from  {
             if &search != imported_from {
                 debug!("  import self abs {} => {}", import.name, search);
-                return search;
+                return Ok(search);
             }
         }
 
@@ -299,30 +299,30 @@ fn abs_import(imported_from: &Name, import: &ast::Import, all_modules: &HashMap<
 
             if &search != imported_from {
                 debug!("  import self {} => {}", import.name, search);
-                return search;
+                return Ok(search);
             }
         }
     }
 
-    emit_error(format!("cannot find module '{}'", import.name), &[
+    Err(error(format!("cannot find module '{}'", import.name), &[
         (import.loc.clone(), "imported here"),
-    ]);
-    std::process::exit(9);
+    ]))
 }
 
 fn check_abs_available(
+    scope: &Scope,
     fqn: &mut Name,
     this_vis: &ast::Visibility,
     all_modules: &HashMap<Name, loader::Module>,
     loc: &ast::Location,
     selfname: &Name
-) {
+) -> Result<(), Error> {
     if !fqn.is_absolute() && fqn.len() > 1 {
         emit_warn(format!("relative name {} not resolved. likely due to previous error", fqn), &[
             (loc.clone(), "this type is unresolved"),
         ]);
-        ABORT.store(true, Ordering::Relaxed);
-        return;
+        scope.abort.set(true);
+        return Ok(());
     }
 
 
@@ -330,23 +330,23 @@ fn check_abs_available(
     let mut local_name = module_name.pop().unwrap();
 
     if module_name.len() < 2 {
-        return;
+        return Ok(());
     }
 
     if module_name.0[1] == "ext" {
         //TODO
-        return
+        return Ok(())
     }
 
     if &module_name == selfname {
-        return;
+        return Ok(());
     }
 
     if all_modules.get(&module_name).is_none() && module_name.0.len() > 2 {
         let mut mn2 = module_name.clone();
         let was_actually_local = mn2.pop().unwrap();
         if &mn2 == selfname {
-            return;
+            return Ok(());
         }
         if all_modules.get(&mn2).is_some() {
             module_name.pop();
@@ -356,17 +356,16 @@ fn check_abs_available(
     }
 
     if &module_name == selfname {
-        return;
+        return Ok(());
     }
 
     let module = match all_modules.get(&module_name) {
         None => {
-            emit_error(format!("cannot find module '{}' during abs of module '{}'", module_name, selfname), &[
+            return Err(error(format!("cannot find module '{}' during abs of module '{}'", module_name, selfname), &[
                    (loc.clone(), "expected to be in scope here"),
-            ]);
-            std::process::exit(9);
+            ]));
         },
-        Some(loader::Module::C(_)) => return,
+        Some(loader::Module::C(_)) => return Ok(()),
         Some(loader::Module::ZZ(v)) => v,
     };
 
@@ -377,22 +376,22 @@ fn check_abs_available(
                        (loc.clone(), "cannot use private type"),
                        (local2.loc.clone(), "add 'pub' to share this type"),
                 ]);
-                ABORT.store(true, Ordering::Relaxed);
+                scope.abort.set(true);
             }
             if this_vis == &ast::Visibility::Export && local2.vis != ast::Visibility::Export {
                 emit_error(format!("the type '{}' in '{}' is not exported", local_name, module_name), &[
                        (loc.clone(), "cannot use an unexported type here"),
                        (local2.loc.clone(), "suggestion: export this type"),
                 ]);
-                ABORT.store(true, Ordering::Relaxed);
+                scope.abort.set(true);
             }
-            return;
+            return Ok(());
         }
     };
 
 
     for import2 in &module.imports {
-        let importname = abs_import(&module.name, &import2, all_modules);
+        let importname = abs_import(&module.name, &import2, all_modules)?;
 
         if import2.vis == ast::Visibility::Object {
             continue;
@@ -402,6 +401,7 @@ fn check_abs_available(
                 if local3_as == &local_name {
                     *fqn = Name::from(&format!("{}::{}", importname, local3));
                     return check_abs_available(
+                        scope,
                         fqn,
                         this_vis,
                         all_modules,
@@ -412,6 +412,7 @@ fn check_abs_available(
             if local3 == &local_name {
                 *fqn = Name::from(&format!("{}::{}", importname, local3));
                 return check_abs_available(
+                    scope,
                     fqn,
                     this_vis,
                     all_modules,
@@ -424,8 +425,8 @@ fn check_abs_available(
     emit_error(format!("module '{}' does not contain '{}'", module_name, local_name), &[
         (loc.clone(), "imported here"),
     ]);
-    ABORT.store(true, Ordering::Relaxed);
-
+    scope.abort.set(true);
+    Ok(())
 }
 
 
@@ -435,56 +436,57 @@ fn abs_expr(
     inbody: bool,
     all_modules: &HashMap<Name, loader::Module>,
     self_md_name: &Name,
-    )
+    ) -> Result<(), Error>
 {
     match expr {
         ast::Expression::ArrayInit{fields,..} => {
             for expr in fields {
-                abs_expr(expr, scope, inbody, all_modules, self_md_name);
+                abs_expr(expr, scope, inbody, all_modules, self_md_name)?;
             }
         },
         ast::Expression::StructInit{typed, fields,..} => {
             scope.abs(typed, inbody);
             for (_, expr) in fields {
-                abs_expr(expr, scope, inbody, all_modules, self_md_name);
+                abs_expr(expr, scope, inbody, all_modules, self_md_name)?;
             }
         },
         ast::Expression::UnaryPre{expr,..} => {
-            abs_expr(expr, scope, inbody, all_modules, self_md_name);
+            abs_expr(expr, scope, inbody, all_modules, self_md_name)?;
         },
         ast::Expression::UnaryPost{expr,..} => {
-            abs_expr(expr, scope, inbody, all_modules, self_md_name);
+            abs_expr(expr, scope, inbody, all_modules, self_md_name)?;
         },
         ast::Expression::Cast{expr, into,..} => {
-            abs_expr(expr, scope, inbody, all_modules, self_md_name);
+            abs_expr(expr, scope, inbody, all_modules, self_md_name)?;
             scope.abs(into, inbody);
         }
         ast::Expression::MemberAccess{lhs,..}  => {
-            abs_expr(lhs, scope, inbody, all_modules, self_md_name);
+            abs_expr(lhs, scope, inbody, all_modules, self_md_name)?;
         }
         ast::Expression::ArrayAccess{lhs,rhs,..}  => {
-            abs_expr(lhs, scope, inbody, all_modules, self_md_name);
-            abs_expr(rhs, scope, inbody, all_modules, self_md_name);
+            abs_expr(lhs, scope, inbody, all_modules, self_md_name)?;
+            abs_expr(rhs, scope, inbody, all_modules, self_md_name)?;
         }
         ast::Expression::Name(ref mut t)  => {
             scope.abs(t, inbody);
             if let ast::Type::Other(ref mut name) = &mut t.t {
-                check_abs_available(name, &ast::Visibility::Object, all_modules, &t.loc, self_md_name);
+                check_abs_available(scope, name, &ast::Visibility::Object, all_modules, &t.loc, self_md_name)?;
             }
         },
         ast::Expression::Literal {..} | ast::Expression::LiteralString {..} | ast::Expression::LiteralChar {..} => {
         }
         ast::Expression::Call { ref mut name, args, ..} => {
-            abs_expr(name, scope, inbody, all_modules, self_md_name);
+            abs_expr(name, scope, inbody, all_modules, self_md_name)?;
             for arg in args {
-                abs_expr(arg, scope, inbody, all_modules, self_md_name);
+                abs_expr(arg, scope, inbody, all_modules, self_md_name)?;
             }
         },
         ast::Expression::Infix {lhs, rhs,.. } => {
-            abs_expr(lhs, scope, inbody, all_modules, self_md_name);
-            abs_expr(rhs, scope, inbody, all_modules, self_md_name);
+            abs_expr(lhs, scope, inbody, all_modules, self_md_name)?;
+            abs_expr(rhs, scope, inbody, all_modules, self_md_name)?;
         }
     }
+    Ok(())
 }
 
 fn abs_statement(
@@ -493,11 +495,11 @@ fn abs_statement(
     inbody: bool,
     all_modules: &HashMap<Name, loader::Module>,
     self_md_name: &Name,
-    )
+    ) -> Result<(), Error>
 {
     match stm {
         ast::Statement::Mark{lhs,..} => {
-            abs_expr(lhs, &scope, inbody, all_modules, self_md_name);
+            abs_expr(lhs, &scope, inbody, all_modules, self_md_name)?;
         },
         ast::Statement::Label{..}
         | ast::Statement::Break{..}
@@ -505,74 +507,75 @@ fn abs_statement(
         | ast::Statement::CBlock{..} => {
         }
         ast::Statement::Block(b2) => {
-            abs_block(b2, &scope, all_modules, self_md_name);
+            abs_block(b2, &scope, all_modules, self_md_name)?;
         }
         ast::Statement::Unsafe(b2) => {
-            abs_block(b2, &scope, all_modules, self_md_name);
+            abs_block(b2, &scope, all_modules, self_md_name)?;
         }
         ast::Statement::For{e1,e2,e3, body} => {
-            abs_block(body, &scope, all_modules, self_md_name);
+            abs_block(body, &scope, all_modules, self_md_name)?;
             for s in e1 {
-                abs_statement(s, scope, inbody, all_modules, self_md_name);
+                abs_statement(s, scope, inbody, all_modules, self_md_name)?;
             }
             for s in e2 {
-                abs_expr(s, scope, inbody, all_modules, self_md_name);
+                abs_expr(s, scope, inbody, all_modules, self_md_name)?;
             }
             for s in e3 {
-                abs_statement(s, scope, inbody, all_modules, self_md_name);
+                abs_statement(s, scope, inbody, all_modules, self_md_name)?;
             }
         },
         ast::Statement::While{expr, body} => {
-            abs_expr(expr, &scope, inbody, all_modules, self_md_name);
-            abs_block(body, &scope, all_modules, self_md_name);
+            abs_expr(expr, &scope, inbody, all_modules, self_md_name)?;
+            abs_block(body, &scope, all_modules, self_md_name)?;
         },
         ast::Statement::If{branches} => {
             for branch in branches {
                 if let Some(expr) = &mut branch.1{
-                    abs_expr(expr, &scope, inbody, all_modules, self_md_name);
+                    abs_expr(expr, &scope, inbody, all_modules, self_md_name)?;
                 }
-                abs_block(&mut branch.2, &scope, all_modules, self_md_name);
+                abs_block(&mut branch.2, &scope, all_modules, self_md_name)?;
             }
         }
         ast::Statement::Assign{lhs, rhs, ..}  => {
-            abs_expr(lhs, &scope, inbody, all_modules, self_md_name);
-            abs_expr(rhs, &scope, inbody, all_modules, self_md_name);
+            abs_expr(lhs, &scope, inbody, all_modules, self_md_name)?;
+            abs_expr(rhs, &scope, inbody, all_modules, self_md_name)?;
         },
         ast::Statement::Var{assign, typed, array, ..}  => {
             if let Some(assign) = assign {
-                abs_expr(assign, &scope, inbody, all_modules, self_md_name);
+                abs_expr(assign, &scope, inbody, all_modules, self_md_name)?;
             }
             if let Some(array) = array {
                 if let Some(array) = array {
-                    abs_expr(array, &scope, inbody, all_modules, self_md_name);
+                    abs_expr(array, &scope, inbody, all_modules, self_md_name)?;
                 }
             }
             scope.abs(typed, false);
             if let ast::Type::Other(ref mut name) = &mut typed.t {
-                check_abs_available(name, &ast::Visibility::Object, all_modules, &typed.loc, self_md_name);
+                check_abs_available(scope, name, &ast::Visibility::Object, all_modules, &typed.loc, self_md_name)?;
             }
         },
         ast::Statement::Expr{expr, ..} => {
-            abs_expr(expr, &scope, inbody, all_modules, self_md_name);
+            abs_expr(expr, &scope, inbody, all_modules, self_md_name)?;
         }
         ast::Statement::Return {expr, ..} => {
             if let Some(expr) = expr {
-                abs_expr(expr, &scope, inbody, all_modules, self_md_name);
+                abs_expr(expr, &scope, inbody, all_modules, self_md_name)?;
             }
         }
         ast::Statement::Switch {expr, cases, default, ..} => {
-            abs_expr(expr, &scope, inbody, all_modules, self_md_name);
+            abs_expr(expr, &scope, inbody, all_modules, self_md_name)?;
             for (conds, block) in cases {
                 for expr in conds {
-                    abs_expr(expr, &scope, inbody, all_modules, self_md_name);
+                    abs_expr(expr, &scope, inbody, all_modules, self_md_name)?;
                 }
-                abs_block(block, &scope, all_modules, self_md_name);
+                abs_block(block, &scope, all_modules, self_md_name)?;
             }
             if let Some(block) = default {
-                abs_block(block, &scope, all_modules, self_md_name);
+                abs_block(block, &scope, all_modules, self_md_name)?;
             }
         }
     }
+    Ok(())
 }
 
 fn abs_block(
@@ -580,14 +583,15 @@ fn abs_block(
     scope: &Scope,
     all_modules: &HashMap<Name, loader::Module>,
     self_md_name: &Name,
-    )
+    ) -> Result<(), Error>
 {
     for stm in &mut block.statements {
-        abs_statement(stm, scope, true, all_modules, self_md_name);
+        abs_statement(stm, scope, true, all_modules, self_md_name)?;
     }
+    Ok(())
 }
 
-pub fn abs(md: &mut ast::Module, all_modules: &HashMap<Name, loader::Module>, ext: &mut Ext) {
+pub fn abs(md: &mut ast::Module, all_modules: &HashMap<Name, loader::Module>, ext: &mut Ext) -> Result<(), Error> {
     debug!("abs {}", md.name);
 
     let mut scope = Scope::default();
@@ -596,7 +600,7 @@ pub fn abs(md: &mut ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
     let newimports = Vec::new();
     for import in &mut md.imports {
 
-        let mut fqn  = abs_import(&md.name, &import, all_modules);
+        let mut fqn  = abs_import(&md.name, &import, all_modules)?;
 
         // make ext includes absolute, so they dont conflict later
         if fqn.0[1] == "ext" {
@@ -607,10 +611,9 @@ pub fn abs(md: &mut ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
                 let path = match std::fs::canonicalize(&path) {
                     Ok(v) => v,
                     Err(e) => {
-                        emit_error(format!("path resolve error"), &[
+                        return Err(error(format!("path resolve error"), &[
                             (import.loc.clone(), format!("{} : {:?}", e, path)),
-                        ]);
-                        std::process::exit(9);
+                        ]));
                     }
                 };
                 expr = path.to_string_lossy().into();
@@ -622,14 +625,14 @@ pub fn abs(md: &mut ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
         let local_module_name = import.alias.clone().unwrap_or(import.name.0.last().unwrap().clone());
 
         if import.local.len() == 0 {
-            scope.insert(local_module_name, fqn.clone(), &import.loc, true, true);
+            scope.insert(local_module_name, fqn.clone(), &import.loc, true, true)?;
         } else {
             let mut new_import_local = Vec::new();
             for (local, import_as) in std::mem::replace(&mut import.local, Vec::new()) {
                 let mut nn_o = fqn.clone();
                 nn_o.push(local.clone());
                 let mut nn = nn_o.clone();
-                check_abs_available(&mut nn, &import.vis, all_modules, &import.loc, &md.name);
+                check_abs_available(&scope, &mut nn, &import.vis, all_modules, &import.loc, &md.name)?;
                 if nn_o == nn {
                     new_import_local.push((local.clone(), import_as.clone()));
                 } else {
@@ -645,7 +648,7 @@ pub fn abs(md: &mut ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
                 // if not self
                 if md.name.len() > nn.len() || md.name.0[..] != nn.0[..md.name.len()] {
                     // add to scope
-                    scope.insert(localname, nn, &import.loc, false, false);
+                    scope.insert(localname, nn, &import.loc, false, false)?;
                 }
 
             }
@@ -696,12 +699,12 @@ pub fn abs(md: &mut ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
                     });
                     let mut ns = md.name.clone();
                     ns.push(subname.clone());
-                    scope.insert(subname, ns, &ast.loc, false, false);
+                    scope.insert(subname, ns, &ast.loc, false, false)?;
                 }
-                scope.insert(ast.name.clone(), ns, &ast.loc, false, true);
+                scope.insert(ast.name.clone(), ns, &ast.loc, false, true)?;
             }
             _ => {
-                scope.insert(ast.name.clone(), ns, &ast.loc, false, false);
+                scope.insert(ast.name.clone(), ns, &ast.loc, false, false)?;
             }
         };
     }
@@ -712,17 +715,17 @@ pub fn abs(md: &mut ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
     for ast in &mut md.locals {
         match &mut ast.def {
             ast::Def::Static{typed,expr,..} => {
-                abs_expr(expr, &scope, false, all_modules, &md.name);
+                abs_expr(expr, &scope, false, all_modules, &md.name)?;
                 scope.abs(typed, false);
                 if let ast::Type::Other(ref mut name) = &mut typed.t{
-                    check_abs_available(name, &ast.vis, all_modules, &typed.loc, &md.name);
+                    check_abs_available(&scope, name, &ast.vis, all_modules, &typed.loc, &md.name)?;
                 }
             }
             ast::Def::Const{typed, expr,..} => {
-                abs_expr(expr, &scope, false,all_modules, &md.name);
+                abs_expr(expr, &scope, false,all_modules, &md.name)?;
                 scope.abs(typed, false);
                 if let ast::Type::Other(ref mut name) = &mut typed.t{
-                    check_abs_available(name, &ast.vis, all_modules, &typed.loc, &md.name);
+                    check_abs_available(&scope, name, &ast.vis, all_modules, &typed.loc, &md.name)?;
                 }
             }
             ast::Def::Function{ret, args, ref mut body, callassert, calleffect, ..} => {
@@ -730,45 +733,45 @@ pub fn abs(md: &mut ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
                 if let Some(ret) = ret {
                     scope.abs(&mut ret.typed, false);
                     if let ast::Type::Other(ref mut name) = &mut ret.typed.t{
-                        check_abs_available(name, &ast.vis, all_modules, &ret.typed.loc, &md.name);
+                        check_abs_available(&scope, name, &ast.vis, all_modules, &ret.typed.loc, &md.name)?;
                     }
                 }
 
 
-                abs_args(args, &mut scope, &ast.vis, all_modules, &md.name);
+                abs_args(args, &mut scope, &ast.vis, all_modules, &md.name)?;
 
                 for calleffect in calleffect {
-                    abs_expr(calleffect, &scope, true, all_modules, &md.name);
+                    abs_expr(calleffect, &scope, true, all_modules, &md.name)?;
                 }
                 for callassert in callassert {
-                    abs_expr(callassert, &scope, true, all_modules, &md.name);
+                    abs_expr(callassert, &scope, true, all_modules, &md.name)?;
                 }
-                abs_block(body, &scope,all_modules, &md.name);
+                abs_block(body, &scope,all_modules, &md.name)?;
                 scope.pop();
             }
             ast::Def::Fntype{ret, args, ..} => {
                 if let Some(ret) = ret {
                     scope.abs(&mut ret.typed, false);
                     if let ast::Type::Other(ref mut name) = &mut ret.typed.t{
-                        check_abs_available(name, &ast.vis, all_modules, &ret.typed.loc, &md.name);
+                        check_abs_available(&scope, name, &ast.vis, all_modules, &ret.typed.loc, &md.name)?;
                     }
                 }
                 scope.push();
-                abs_args(args, &mut scope, &ast.vis, all_modules, &md.name);
+                abs_args(args, &mut scope, &ast.vis, all_modules, &md.name)?;
                 scope.pop();
             }
             ast::Def::Theory{ret, args, ..} => {
                 if let Some(ret) = ret {
                     scope.abs(&mut ret.typed, false);
                     if let ast::Type::Other(ref mut name) = &mut ret.typed.t{
-                        check_abs_available(name, &ast.vis, all_modules, &ret.typed.loc, &md.name);
+                        check_abs_available(&scope, name, &ast.vis, all_modules, &ret.typed.loc, &md.name)?;
                     }
                 }
                 for arg in args {
                     scope.abs(&mut arg.typed, false);
                     scope.tags(&mut arg.tags);
                     if let ast::Type::Other(ref mut name) = &mut arg.typed.t{
-                        check_abs_available(name, &ast.vis, all_modules, &arg.typed.loc, &md.name);
+                        check_abs_available(&scope, name, &ast.vis, all_modules, &arg.typed.loc, &md.name)?;
                     }
                 }
             }
@@ -778,11 +781,11 @@ pub fn abs(md: &mut ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
                 for (i, field) in fields.iter_mut().enumerate() {
                     scope.abs(&mut field.typed, false);
                     if let ast::Type::Other(ref mut name) = &mut field.typed.t{
-                        check_abs_available(name, &ast.vis, all_modules, &field.typed.loc, &md.name);
+                        check_abs_available(&scope, name, &ast.vis, all_modules, &field.typed.loc, &md.name)?;
                     }
                     if let Some(ref mut array) = &mut field.array {
                         if let Some(array) = array {
-                            abs_expr(array, &scope, false, all_modules, &md.name);
+                            abs_expr(array, &scope, false, all_modules, &md.name)?;
                         }
                     }
 
@@ -790,10 +793,9 @@ pub fn abs(md: &mut ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
                         ast::Tail::None | ast::Tail::Static(_, _) => {},
                         ast::Tail::Bind(_,_) | ast::Tail::Dynamic => {
                             if i != fieldslen - 1 {
-                                emit_error(format!("nested tail must be last field"), &[
+                                return Err(error(format!("nested tail must be last field"), &[
                                     (field.loc.clone(), format!("field {} is non static tail, but not the last field", field.name)),
-                                ]);
-                                std::process::exit(9);
+                                ]));
                             }
                         }
                     }
@@ -802,11 +804,15 @@ pub fn abs(md: &mut ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
             ast::Def::Enum{..} => {
             }
             ast::Def::Macro{body, ..} => {
-                abs_block(body, &scope,all_modules, &md.name);
+                abs_block(body, &scope,all_modules, &md.name)?;
             }
             ast::Def::Testcase{fields, ..} => {
-                for (_, expr) in fields {
-                    abs_expr(expr, &scope, false, all_modules, &md.name);
+                for (_, _, expr) in fields {
+                    // file("snapshot") is not a call into the program
+                    if let ast::Expression::Call{..} = expr {
+                        continue;
+                    }
+                    abs_expr(expr, &scope, false, all_modules, &md.name)?;
                 }
             }
             ast::Def::Include{needs,..} => {
@@ -829,11 +835,10 @@ pub fn abs(md: &mut ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
             if let Some(previous) = ext.ext.get(&import.name) {
                 if let ast::Def::Include{inline,..} = previous.def {
                     if inline != import.inline {
-                        emit_error(format!("conflicting import modes"), &[
+                        return Err(error(format!("conflicting import modes"), &[
                             (import.loc.clone(), format!("{} here", if inline {"inlined"} else {"included"})),
                             (previous.loc.clone(), format!("previously {} here", if inline {"inlined"} else {"included"})),
-                        ]);
-                        std::process::exit(9);
+                        ]));
                     }
                 }
             }
@@ -841,11 +846,10 @@ pub fn abs(md: &mut ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
             let mut expr = import.name.0[2].clone();
             if import.inline {
                 if !expr.starts_with("\"") || !expr.ends_with("\"") || expr.len() < 3 {
-                    emit_error(
+                    return Err(error(
                         "cannot inline non-relative include",
                         &[(import.loc.clone(), format!("'{}' is not a relative include", expr))]
-                        );
-                    std::process::exit(9);
+                        ));
                 }
                 expr = (&expr[1..expr.len() - 1]).to_string();
             }
@@ -867,11 +871,11 @@ pub fn abs(md: &mut ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
 
     }
 
-    if ABORT.load(Ordering::Relaxed) {
+    if scope.abort.get() {
         warn!("exit abs due to previous errors");
-        std::process::exit(9);
+        return Err(Error::new(format!("could not resolve names in {}", md.name), Vec::new()));
     }
-
+    Ok(())
 }
 
 
@@ -881,13 +885,13 @@ fn abs_args(
     astvis: &ast::Visibility,
     all_modules: &HashMap<Name, loader::Module>,
     mdname: &Name
-) {
+) -> Result<(), Error> {
     let oargs = std::mem::replace(args, Vec::new());
     for mut arg in oargs {
         scope.abs(&mut arg.typed, false);
         scope.tags(&mut arg.tags);
         if let ast::Type::Other(ref mut name) = &mut arg.typed.t {
-            check_abs_available(name, astvis, all_modules, &arg.typed.loc, mdname);
+            check_abs_available(scope, name, astvis, all_modules, &arg.typed.loc, mdname)?;
         }
 
         args.push(arg.clone());
@@ -895,16 +899,14 @@ fn abs_args(
             ast::Tail::None => {
             },
             ast::Tail::Dynamic => {
-                emit_error(format!("missing tail binding "), &[
+                return Err(error(format!("missing tail binding "), &[
                            (arg.loc.clone(), "+ without a name makes no sense in this context"),
-                ]);
-                std::process::exit(9);
+                ]));
             },
             ast::Tail::Static(_, _) => {
-                emit_error(format!("missing tail binding "), &[
+                return Err(error(format!("missing tail binding "), &[
                            (arg.loc.clone(), "+ with static size makes no sense in this context"),
-                ]);
-                std::process::exit(9);
+                ]));
             }
             ast::Tail::Bind(s, loc) => {
                 let mut tags = ast::Tags::new();
@@ -930,8 +932,9 @@ fn abs_args(
             &arg.loc,
             false,
             false
-        );
+        )?;
     }
+    Ok(())
 }
 
 
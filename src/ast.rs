@@ -132,7 +132,7 @@ pub enum Def {
         body:       Block,
     },
     Testcase {
-        fields:     Vec<(String, Expression)>,
+        fields:     Vec<(String, InfixOperator, Expression)>,
     },
     Include {
         expr:       String,
@@ -543,6 +543,92 @@ impl Expression {
     }
 }
 
+impl std::fmt::Display for InfixOperator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            InfixOperator::Equals      =>  "==",
+            InfixOperator::Nequals     =>  "!=",
+            InfixOperator::Add         =>  "+" ,
+            InfixOperator::Subtract    =>  "-" ,
+            InfixOperator::Multiply    =>  "*" ,
+            InfixOperator::Divide      =>  "/" ,
+            InfixOperator::Bitxor      =>  "^" ,
+            InfixOperator::Booland     =>  "&&",
+            InfixOperator::Boolor      =>  "||",
+            InfixOperator::Moreeq      =>  ">=",
+            InfixOperator::Lesseq      =>  "<=",
+            InfixOperator::Lessthan    =>  "<" ,
+            InfixOperator::Morethan    =>  ">" ,
+            InfixOperator::Shiftleft   =>  "<<",
+            InfixOperator::Shiftright  =>  ">>",
+            InfixOperator::Modulo      =>  "%" ,
+            InfixOperator::Bitand      =>  "&" ,
+            InfixOperator::Bitor       =>  "|" ,
+        })
+    }
+}
+
+// renders an expression back into zz syntax, with absolute names shortened to their human name
+impl std::fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::Name(typed) => match &typed.t {
+                Type::Other(name)   => write!(f, "{}", name.human_name()),
+                _                   => write!(f, "{}", typed),
+            },
+            Expression::MemberAccess{lhs, op, rhs, ..}  => write!(f, "{}{}{}", lhs, op, rhs),
+            Expression::ArrayAccess{lhs, rhs, ..}       => write!(f, "{}[{}]", lhs, rhs),
+            Expression::LiteralString{v, ..}           => write!(f, "{:?}", String::from_utf8_lossy(v)),
+            Expression::LiteralChar{v, ..}             => write!(f, "{:?}", *v as char),
+            Expression::Literal{v, ..}                 => write!(f, "{}", v),
+            Expression::Call{name, args, ..} => {
+                write!(f, "{}(", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            },
+            Expression::Infix{lhs, rhs, op, ..}        => write!(f, "({} {} {})", lhs, op, rhs),
+            Expression::Cast{into, expr, ..}           => write!(f, "({}){}", into, expr),
+            Expression::UnaryPost{op, expr, ..} => match op {
+                PostfixOperator::Increment  => write!(f, "{}++", expr),
+                PostfixOperator::Decrement  => write!(f, "{}--", expr),
+            },
+            Expression::UnaryPre{op, expr, ..} => write!(f, "{}{}", match op {
+                PrefixOperator::Boolnot     => "!",
+                PrefixOperator::Bitnot      => "~",
+                PrefixOperator::Increment   => "++",
+                PrefixOperator::Decrement   => "--",
+                PrefixOperator::AddressOf   => "&",
+                PrefixOperator::Deref       => "*",
+            }, expr),
+            Expression::StructInit{typed, fields, ..} => {
+                write!(f, "{}{{", typed)?;
+                for (i, (name, expr)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", name, expr)?;
+                }
+                write!(f, "}}")
+            },
+            Expression::ArrayInit{fields, ..} => {
+                write!(f, "{{")?;
+                for (i, expr) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", expr)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
 
 #[derive(Clone, Debug)]
 pub enum Statement {
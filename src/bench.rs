@@ -0,0 +1,182 @@
+use super::testrunner::{self, Job, Outcome};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use super::project::target_dir;
+
+pub struct Options {
+    // runs before measuring, to warm caches and the page cache of the binary
+    pub warmup:     usize,
+    pub samples:    usize,
+    // regressions are medians slower than the baseline by more than this, in percent
+    pub threshold:  f64,
+}
+
+/// statistics over the samples of one testcase, in nanoseconds
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Stats {
+    pub samples:    usize,
+    pub mean:       f64,
+    pub median:     f64,
+    pub stddev:     f64,
+    pub min:        f64,
+    pub max:        f64,
+    // outside 1.5 and 3 times the interquartile range
+    pub mild:       usize,
+    pub severe:     usize,
+}
+
+/// artifact/case -> stats
+pub type Baseline = BTreeMap<String, Stats>;
+
+pub fn dir(root: &Path) -> std::path::PathBuf {
+    target_dir(root).join("bench")
+}
+
+/// run every case of every job, one at a time so they don't measure each other.
+/// returns None if a benchmark failed.
+pub fn run(jobs: &[Job], opts: &Options) -> Option<Baseline> {
+    let cancel = AtomicBool::new(false);
+    let mut r = Baseline::new();
+
+    for job in jobs {
+        for case in &job.cases {
+            for _ in 0..opts.warmup {
+                let run = testrunner::run_case(&job.exe, case, None, &cancel);
+                if let Outcome::Fail(why) = run.outcome {
+                    error!("FAIL {} {} {}", job.artifact, case.name, why);
+                    return None;
+                }
+            }
+
+            let mut samples = Vec::with_capacity(opts.samples);
+            for _ in 0..opts.samples.max(1) {
+                let run = testrunner::run_case(&job.exe, case, None, &cancel);
+                if let Outcome::Fail(why) = run.outcome {
+                    error!("FAIL {} {} {}", job.artifact, case.name, why);
+                    return None;
+                }
+                samples.push(run.duration.as_nanos() as f64);
+            }
+
+            let stats = Stats::new(samples);
+            info!("BENCH {} {}\n{}", job.artifact, case.name, stats);
+            r.insert(format!("{}/{}", job.artifact, case.name), stats);
+        }
+    }
+    Some(r)
+}
+
+impl Stats {
+    pub fn new(mut samples: Vec<f64>) -> Self {
+        samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let n = samples.len();
+
+        let mean = samples.iter().sum::<f64>() / n as f64;
+        let stddev = if n > 1 {
+            (samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (n - 1) as f64).sqrt()
+        } else {
+            0.0
+        };
+
+        let q1  = percentile(&samples, 0.25);
+        let q3  = percentile(&samples, 0.75);
+        let iqr = q3 - q1;
+        let mut mild   = 0;
+        let mut severe = 0;
+        for s in &samples {
+            if *s < q1 - 3.0 * iqr || *s > q3 + 3.0 * iqr {
+                severe += 1;
+            } else if *s < q1 - 1.5 * iqr || *s > q3 + 1.5 * iqr {
+                mild += 1;
+            }
+        }
+
+        Stats {
+            samples:    n,
+            mean,
+            median:     percentile(&samples, 0.5),
+            stddev,
+            min:        samples[0],
+            max:        samples[n - 1],
+            mild,
+            severe,
+        }
+    }
+}
+
+// linear interpolation between the closest ranks of sorted samples
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = p * (sorted.len() - 1) as f64;
+    let lo = rank.floor() as usize;
+    let hi = rank.ceil() as usize;
+    sorted[lo] + (sorted[hi] - sorted[lo]) * (rank - lo as f64)
+}
+
+pub fn save(root: &Path, name: &str, baseline: &Baseline) {
+    let dir = dir(root);
+    std::fs::create_dir_all(&dir).expect("create bench dir");
+    let p = dir.join(format!("{}.json", name));
+    let f = std::fs::File::create(&p).expect(&format!("cannot create {:?}", p));
+    serde_json::to_writer_pretty(f, baseline).expect(&format!("cannot write {:?}", p));
+}
+
+pub fn load(root: &Path, name: &str) -> Option<Baseline> {
+    let p = dir(root).join(format!("{}.json", name));
+    let s = std::fs::read_to_string(&p).ok()?;
+    serde_json::from_str(&s).ok()
+}
+
+/// report the change of every benchmark against the baseline.
+/// returns false if any median regressed by more than the threshold.
+pub fn compare(baseline: &Baseline, current: &Baseline, threshold: f64) -> bool {
+    let mut ok = true;
+    for (name, now) in current {
+        let then = match baseline.get(name) {
+            Some(v) => v,
+            None => {
+                info!("{}: not in baseline", name);
+                continue;
+            }
+        };
+        let change = (now.median - then.median) / then.median * 100.0;
+        if change > threshold {
+            error!("REGRESSION {}: {} -> {} ({:+.2}%)", name, Time(then.median), Time(now.median), change);
+            ok = false;
+        } else if change < -threshold {
+            info!("IMPROVED {}: {} -> {} ({:+.2}%)", name, Time(then.median), Time(now.median), change);
+        } else {
+            info!("no change {}: {} -> {} ({:+.2}%)", name, Time(then.median), Time(now.median), change);
+        }
+    }
+    ok
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "    median {}  mean {} ± {}  min {}  max {}  ({} samples)",
+            Time(self.median), Time(self.mean), Time(self.stddev), Time(self.min), Time(self.max), self.samples)?;
+        if self.mild + self.severe > 0 {
+            write!(f, "\n    {} outliers ({} mild, {} severe)", self.mild + self.severe, self.mild, self.severe)?;
+        }
+        Ok(())
+    }
+}
+
+// nanoseconds in the most readable unit
+struct Time(f64);
+impl std::fmt::Display for Time {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ns = self.0;
+        if ns < 1e3 {
+            write!(f, "{:.0}ns", ns)
+        } else if ns < 1e6 {
+            write!(f, "{:.2}µs", ns / 1e3)
+        } else if ns < 1e9 {
+            write!(f, "{:.2}ms", ns / 1e6)
+        } else {
+            write!(f, "{:.3}s", ns / 1e9)
+        }
+    }
+}
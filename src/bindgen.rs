@@ -0,0 +1,1078 @@
+// generate zz declarations from a C header
+
+use std::collections::{HashMap, HashSet, BTreeMap};
+use std::path::Path;
+use std::process::Command;
+use serde::{Serialize, Deserialize};
+use super::Error;
+
+/// user supplied contracts for an imported C function, keyed by the C name in the annotation file:
+///
+/// ```toml
+/// [puts]
+/// where   = ["nullterm(s)"]
+/// model   = ["return >= -1"]
+/// unsafe  = []
+/// ```
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Annotation {
+    #[serde(default, rename = "where")]
+    pub callassert: Vec<String>,
+    #[serde(default, rename = "model")]
+    pub calleffect: Vec<String>,
+    // pointer arguments that may be null
+    #[serde(default, rename = "unsafe")]
+    pub nullable:   Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Tok {
+    Ident(String),
+    Number(String),
+    Punct(String),
+    Other,
+}
+
+#[derive(Clone)]
+struct Token {
+    t:      Tok,
+    ours:   bool,
+}
+
+#[derive(Clone)]
+struct ZType {
+    base:   String,
+    ptr:    Vec<bool>,
+    // generated struct or union, which is a different C type than the original
+    record: bool,
+}
+
+struct Field {
+    typed:  ZType,
+    name:   String,
+    array:  Option<String>,
+}
+
+struct Function {
+    name:   String,
+    ret:    Option<ZType>,
+    args:   Vec<(ZType, String)>,
+}
+
+// name and literal value
+type Variant = (String, Option<String>);
+
+#[derive(Default)]
+struct Bindings {
+    // c type name -> zz type. "struct x" and "enum x" are keyed with their keyword
+    types:      HashMap<String, ZType>,
+    // c types imported from the header by name
+    imports:    BTreeMap<String, String>,
+    unsupported: HashSet<String>,
+
+    structs:    Vec<(String, bool, Vec<Field>)>,
+    enums:      Vec<(String, Vec<Variant>)>,
+    functions:  Vec<Function>,
+    varargs:    Vec<String>,
+    constants:  Vec<(String, String, String)>,
+    skipped:    Vec<(String, String)>,
+}
+
+const RESERVED : &[&str] = &[
+    "export", "pub", "const", "packed", "mutable", "mut", "for", "while", "if", "else", "is", "break",
+    "continue", "unsafe", "default", "switch", "where", "model", "fn", "struct", "union", "enum", "test",
+    "using", "as", "new", "let", "return", "macro", "theory", "fntype", "static", "atomic", "thread_local",
+    "inline", "len", "safe", "nullterm", "self",
+];
+
+// words that can end an unnamed parameter
+const TYPE_WORDS : &[&str] = &[
+    "void", "char", "short", "int", "long", "signed", "unsigned", "float", "double", "_Bool", "const",
+];
+
+const NOISE : &[&str] = &[
+    "extern", "__extension__", "inline", "__inline", "__inline__", "restrict", "__restrict", "__restrict__",
+    "volatile", "__volatile__", "_Noreturn", "register", "auto", "__thread", "_Thread_local",
+];
+
+pub fn bindgen(header: &str, annotations: Option<&Path>) -> Result<String, Error> {
+
+    let annotations : HashMap<String, Annotation> = match annotations {
+        None => HashMap::new(),
+        Some(p) => {
+            let s = match std::fs::read_to_string(p) {
+                Ok(s) => s,
+                Err(e) => {
+                    return Err(Error::new(format!("cannot read annotations {:?}: {}", p, e), Vec::new()));
+                }
+            };
+            match toml::from_str(&s) {
+                Ok(v) => v,
+                Err(e) => {
+                    return Err(Error::new(format!("cannot parse annotations {:?}: {}", p, e), Vec::new()));
+                }
+            }
+        }
+    };
+
+    // a path that exists is included as local header, anything else is a system header
+    let local = Path::new(header).exists();
+    let include = if local {
+        format!("\"{}\"", header)
+    } else {
+        format!("<{}>", header.trim_start_matches('<').trim_end_matches('>'))
+    };
+    let header_name = include[1..include.len()-1].to_string();
+
+    let source = preprocess(&include, local)?;
+    let tokens = tokenize(&source, &header_name);
+
+    let mut b = Bindings::default();
+    for decl in split(&tokens) {
+        if decl.first().map(|t|t.ours) != Some(true) {
+            continue;
+        }
+        b.decl(decl.into_iter().map(|t|t.t).collect());
+    }
+
+    // integer constants need the original text, macros are gone after preprocessing
+    let raw = if local {
+        std::fs::read_to_string(header).unwrap_or_default()
+    } else {
+        find_system_header(&header_name).and_then(|p|std::fs::read_to_string(p).ok()).unwrap_or_default()
+    };
+    b.defines(&raw);
+
+    Ok(b.emit(&include, &annotations))
+}
+
+// run the header through the C preprocessor, keeping line markers so we know which declarations
+// come from the header itself. without a compiler, parse the raw file.
+fn preprocess(include: &str, local: bool) -> Result<String, Error> {
+    let cc = std::env::var("TARGET_CC")
+        .or(std::env::var("CC"))
+        .unwrap_or("clang".to_string());
+
+    let src = format!("#include {}\n", include);
+    let tmp = match tempdir::TempDir::new("zzbindgen") {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(Error::new(format!("cannot create temporary directory: {}", e), Vec::new()));
+        }
+    };
+    let input = tmp.path().join("bindgen.c");
+    std::fs::write(&input, src).expect("cannot write bindgen input");
+
+    for cc in &[cc.as_str(), "cc", "gcc"] {
+        let mut cmd = Command::new(cc);
+        cmd.arg("-E");
+        if local {
+            cmd.arg("-I").arg(std::env::current_dir().unwrap());
+        }
+        if let Ok(output) = cmd.arg(&input).output() {
+            if output.status.success() {
+                return Ok(String::from_utf8_lossy(&output.stdout).into());
+            }
+        }
+    }
+
+    warn!("no working C preprocessor found, parsing {} without it", include);
+    let path = if local {
+        Some(std::path::PathBuf::from(&include[1..include.len()-1]))
+    } else {
+        find_system_header(&include[1..include.len()-1])
+    };
+    match path.and_then(|p|std::fs::read_to_string(p).ok()) {
+        Some(s) => Ok(s),
+        None => Err(Error::new(format!("cannot find header {}", include), Vec::new())),
+    }
+}
+
+fn find_system_header(name: &str) -> Option<std::path::PathBuf> {
+    for dir in &["/usr/local/include", "/usr/include"] {
+        let p = Path::new(dir).join(name);
+        if p.exists() {
+            return Some(p);
+        }
+    }
+    None
+}
+
+fn tokenize(s: &str, header: &str) -> Vec<Token> {
+    let mut r = Vec::new();
+    // if there are no line markers, everything belongs to the header
+    let mut ours = !s.lines().any(|l|l.starts_with("# "));
+    let b = s.as_bytes();
+    let mut i = 0;
+    let mut line_start = true;
+
+    while i < b.len() {
+        let c = b[i];
+        if c == b'\n' {
+            line_start = true;
+            i += 1;
+            continue;
+        }
+        if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+        if line_start && c == b'#' {
+            let end = s[i..].find('\n').map(|e|i+e).unwrap_or(b.len());
+            let line = &s[i..end];
+            // # 12 "/usr/include/stdio.h" 2
+            if let Some(q) = line.find('"') {
+                if let Some(file) = line[q+1..].split('"').next() {
+                    if line[1..].trim_start().starts_with(|c: char|c.is_ascii_digit()) {
+                        ours = file.ends_with(header);
+                    }
+                }
+            }
+            i = end;
+            continue;
+        }
+        line_start = false;
+        if s[i..].starts_with("//") {
+            i = s[i..].find('\n').map(|e|i+e).unwrap_or(b.len());
+            continue;
+        }
+        if s[i..].starts_with("/*") {
+            i = s[i+2..].find("*/").map(|e|i+e+4).unwrap_or(b.len());
+            continue;
+        }
+        if c.is_ascii_alphabetic() || c == b'_' {
+            let start = i;
+            while i < b.len() && (b[i].is_ascii_alphanumeric() || b[i] == b'_') {
+                i += 1;
+            }
+            r.push(Token{t: Tok::Ident(s[start..i].to_string()), ours});
+            continue;
+        }
+        if c.is_ascii_digit() {
+            let start = i;
+            while i < b.len() && (b[i].is_ascii_alphanumeric() || b[i] == b'_' || b[i] == b'.') {
+                i += 1;
+            }
+            r.push(Token{t: Tok::Number(s[start..i].to_string()), ours});
+            continue;
+        }
+        if c == b'"' || c == b'\'' {
+            i += 1;
+            while i < b.len() && b[i] != c {
+                if b[i] == b'\\' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            i += 1;
+            r.push(Token{t: Tok::Other, ours});
+            continue;
+        }
+        if s[i..].starts_with("...") {
+            r.push(Token{t: Tok::Punct("...".to_string()), ours});
+            i += 3;
+            continue;
+        }
+        r.push(Token{t: Tok::Punct((c as char).to_string()), ours});
+        i += 1;
+    }
+    r
+}
+
+// split into top level declarations. function definitions are dropped.
+fn split(tokens: &[Token]) -> Vec<Vec<Token>> {
+    let mut r = Vec::new();
+    let mut cur : Vec<Token> = Vec::new();
+    let mut depth = 0;
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let t = &tokens[i];
+        match &t.t {
+            Tok::Punct(p) if p == "(" || p == "[" || p == "{" => {
+                // a body directly after a parameter list is a function definition
+                if p == "{" && depth == 0 && cur.last().map(|t|&t.t) == Some(&Tok::Punct(")".to_string())) {
+                    let mut d = 0;
+                    while i < tokens.len() {
+                        match &tokens[i].t {
+                            Tok::Punct(p) if p == "{" => d += 1,
+                            Tok::Punct(p) if p == "}" => {
+                                d -= 1;
+                                if d == 0 {
+                                    break;
+                                }
+                            }
+                            _ => (),
+                        }
+                        i += 1;
+                    }
+                    cur.clear();
+                    i += 1;
+                    continue;
+                }
+                depth += 1;
+                cur.push(t.clone());
+            }
+            Tok::Punct(p) if p == ")" || p == "]" || p == "}" => {
+                depth -= 1;
+                cur.push(t.clone());
+            }
+            Tok::Punct(p) if p == ";" && depth == 0 => {
+                if !cur.is_empty() {
+                    r.push(std::mem::take(&mut cur));
+                }
+            }
+            _ => {
+                cur.push(t.clone());
+            }
+        }
+        i += 1;
+    }
+    r
+}
+
+fn is(t: Option<&Tok>, p: &str) -> bool {
+    match t {
+        Some(Tok::Punct(v)) | Some(Tok::Ident(v)) => v == p,
+        _ => false,
+    }
+}
+
+fn ident(t: Option<&Tok>) -> Option<String> {
+    match t {
+        Some(Tok::Ident(v)) => Some(v.clone()),
+        _ => None,
+    }
+}
+
+// drop attributes, asm labels and qualifiers that mean nothing to zz
+fn clean(decl: Vec<Tok>) -> Vec<Tok> {
+    let mut r = Vec::new();
+    let mut i = 0;
+    while i < decl.len() {
+        if let Tok::Ident(v) = &decl[i] {
+            if NOISE.contains(&v.as_str()) {
+                i += 1;
+                continue;
+            }
+            if v == "__attribute__" || v == "__attribute" || v == "__asm__" || v == "__asm"
+                || v == "asm" || v == "__declspec" || v == "_Alignas" || v == "__nonnull"
+            {
+                i += 1;
+                if is(decl.get(i), "(") {
+                    let mut d = 0;
+                    while i < decl.len() {
+                        if is(decl.get(i), "(") { d += 1; }
+                        if is(decl.get(i), ")") {
+                            d -= 1;
+                            if d == 0 {
+                                break;
+                            }
+                        }
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                continue;
+            }
+        }
+        r.push(decl[i].clone());
+        i += 1;
+    }
+    r
+}
+
+fn escape(name: &str) -> String {
+    if RESERVED.contains(&name) {
+        format!("{}_", name)
+    } else {
+        name.to_string()
+    }
+}
+
+impl Bindings {
+    fn decl(&mut self, decl: Vec<Tok>) {
+        let mut decl = clean(decl);
+        if decl.is_empty() {
+            return;
+        }
+
+        let typedef = is(decl.first(), "typedef");
+        if typedef {
+            decl.remove(0);
+        }
+
+        let kw = ident(decl.first()).unwrap_or_default();
+        let body = decl.iter().position(|t|t == &Tok::Punct("{".to_string()));
+
+        if let (true, Some(body)) = (kw == "struct" || kw == "union" || kw == "enum", body) {
+            let tag = if body > 1 { ident(decl.get(1)) } else { None };
+            let end = match matching(&decl, body) {
+                Some(v) => v,
+                None => return,
+            };
+            let inner = decl[body+1..end].to_vec();
+            let declarators = &decl[end+1..];
+
+            let name = if typedef {
+                match ident(declarators.first()) {
+                    Some(v) => v,
+                    None => return,
+                }
+            } else {
+                match &tag {
+                    Some(v) => v.clone(),
+                    None => return,
+                }
+            };
+
+            if kw == "enum" {
+                self.enumeration(&name, inner);
+                self.types.insert(format!("enum {}", name), ZType{base: escape(&name), ptr: Vec::new(), record: false});
+                if let Some(tag) = tag {
+                    self.types.insert(format!("enum {}", tag), ZType{base: escape(&name), ptr: Vec::new(), record: false});
+                }
+                self.types.insert(name.clone(), ZType{base: escape(&name), ptr: Vec::new(), record: false});
+            } else {
+                if self.record(&name, kw == "union", inner) {
+                    let zt = ZType{base: escape(&name), ptr: Vec::new(), record: true};
+                    if let Some(tag) = tag {
+                        self.types.insert(format!("{} {}", kw, tag), zt.clone());
+                    }
+                    if typedef {
+                        self.types.insert(name.clone(), zt);
+                    } else {
+                        self.types.insert(format!("{} {}", kw, name), zt);
+                    }
+                } else if let Some(tag) = tag {
+                    self.unsupported.insert(format!("{} {}", kw, tag));
+                    self.unsupported.insert(name);
+                } else {
+                    self.unsupported.insert(name);
+                }
+            }
+            return;
+        }
+
+        if typedef {
+            self.typedef(decl);
+            return;
+        }
+
+        // struct x;
+        if (kw == "struct" || kw == "union") && decl.len() == 2 {
+            return;
+        }
+
+        if let Some(open) = decl.iter().position(|t|t == &Tok::Punct("(".to_string())) {
+            self.function(decl.clone(), open);
+        }
+    }
+
+    fn typedef(&mut self, decl: Vec<Tok>) {
+        let name = match ident(decl.last()) {
+            Some(v) => v,
+            None => {
+                // function pointers and arrays
+                for t in &decl {
+                    if let Tok::Ident(v) = t {
+                        self.unsupported.insert(v.clone());
+                    }
+                }
+                return;
+            }
+        };
+        let def = &decl[..decl.len()-1];
+
+        // typedef struct x y;
+        if def.len() == 2 && (is(def.first(), "struct") || is(def.first(), "union")) {
+            let key = format!("{} {}", ident(def.first()).unwrap(), ident(def.get(1)).unwrap_or_default());
+            if let Some(t) = self.types.get(&key).cloned() {
+                self.types.insert(name, t);
+            } else {
+                self.imports.insert(name.clone(), name.clone());
+                self.types.insert(name.clone(), ZType{base: name.clone(), ptr: Vec::new(), record: false});
+            }
+            return;
+        }
+
+        match self.typed(def) {
+            Some(t) => {
+                self.types.insert(name, t);
+            }
+            None => {
+                self.unsupported.insert(name);
+            }
+        }
+    }
+
+    fn enumeration(&mut self, name: &str, inner: Vec<Tok>) {
+        let mut names = Vec::new();
+        for variant in inner.split(|t|t == &Tok::Punct(",".to_string())) {
+            let vname = match ident(variant.first()) {
+                Some(v) => v,
+                None => continue,
+            };
+            let value = if is(variant.get(1), "=") {
+                let mut v = String::new();
+                for t in &variant[2..] {
+                    match t {
+                        Tok::Number(n) => v.push_str(n),
+                        Tok::Punct(p) if p == "-" => v.push('-'),
+                        Tok::Punct(p) if p == "(" || p == ")" => (),
+                        _ => {
+                            self.skipped.push((format!("enum {}", name), "variant value is not a literal".to_string()));
+                            self.unsupported.insert(name.to_string());
+                            return;
+                        }
+                    }
+                }
+                match integer(&v) {
+                    Some(v) if v.starts_with('-') => {
+                        self.skipped.push((format!("enum {}", name), "zz enums cannot have negative values".to_string()));
+                        self.unsupported.insert(name.to_string());
+                        return;
+                    }
+                    Some(v) => Some(v),
+                    None => {
+                        self.skipped.push((format!("enum {}", name), "variant value is not a literal".to_string()));
+                        self.unsupported.insert(name.to_string());
+                        return;
+                    }
+                }
+            } else {
+                None
+            };
+            names.push((escape(&vname), value));
+        }
+        self.enums.push((escape(name), names));
+    }
+
+    fn record(&mut self, name: &str, union: bool, inner: Vec<Tok>) -> bool {
+        let mut fields = Vec::new();
+        for field in inner.split(|t|t == &Tok::Punct(";".to_string())) {
+            if field.is_empty() {
+                continue;
+            }
+            let field = clean(field.to_vec());
+            if field.iter().any(|t|t == &Tok::Punct("{".to_string()) || t == &Tok::Punct(":".to_string())
+                                || t == &Tok::Punct("(".to_string())) {
+                self.skipped.push((name.to_string(), "nested records, bitfields and function pointers are not supported".to_string()));
+                return false;
+            }
+
+            // int a, b;
+            let mut declarators = field.split(|t|t == &Tok::Punct(",".to_string()));
+            let first = declarators.next().unwrap().to_vec();
+            let base_end = first.iter().rposition(|t| matches!(t, Tok::Ident(_))).unwrap_or(0);
+            let base : Vec<Tok> = first[..base_end].iter().filter(|t|!is(Some(t), "*")).cloned().collect();
+
+            let mut all = vec![first[base_end..].to_vec()];
+            let mut stars = first[..base_end].iter().filter(|t|is(Some(t), "*")).count();
+            for d in declarators {
+                all.push(d.to_vec());
+            }
+
+            for d in all {
+                let mut d = d;
+                let mut t = base.clone();
+                for _ in 0..stars {
+                    t.push(Tok::Punct("*".to_string()));
+                }
+                stars = 0;
+                while is(d.first(), "*") {
+                    t.push(d.remove(0));
+                }
+                let fname = match ident(d.first()) {
+                    Some(v) => v,
+                    None => {
+                        self.skipped.push((name.to_string(), "unnamed field".to_string()));
+                        return false;
+                    }
+                };
+                let array = if is(d.get(1), "[") {
+                    let mut v = String::new();
+                    for t in &d[2..] {
+                        match t {
+                            Tok::Number(n) => v.push_str(n),
+                            Tok::Punct(p) if p == "]" => break,
+                            _ => {
+                                self.skipped.push((name.to_string(), format!("array size of {} is not a literal", fname)));
+                                return false;
+                            }
+                        }
+                    }
+                    match integer(&v) {
+                        Some(v) => Some(v),
+                        None => {
+                            self.skipped.push((name.to_string(), format!("array size of {} is not a literal", fname)));
+                            return false;
+                        }
+                    }
+                } else {
+                    None
+                };
+                let typed = match self.typed(&t) {
+                    Some(v) => v,
+                    None => {
+                        self.skipped.push((name.to_string(), format!("type of field {} is not supported", fname)));
+                        return false;
+                    }
+                };
+                fields.push(Field{typed, name: escape(&fname), array});
+            }
+        }
+        self.structs.push((escape(name), union, fields));
+        true
+    }
+
+    fn function(&mut self, decl: Vec<Tok>, open: usize) {
+        let name = match ident(decl.get(open.wrapping_sub(1))) {
+            Some(v) => v,
+            // (*fnptr)(..)
+            None => return,
+        };
+        let close = match matching(&decl, open) {
+            Some(v) => v,
+            None => return,
+        };
+        if close != decl.len() - 1 {
+            return;
+        }
+        // glibc redeclares some functions with asm labels
+        if self.varargs.contains(&name) || self.functions.iter().any(|f|f.name == name) {
+            return;
+        }
+
+        let ret = &decl[..open-1];
+        let ret = if ret.len() == 1 && is(ret.first(), "void") {
+            None
+        } else {
+            match self.typed(ret) {
+                Some(v) => Some(v),
+                None => {
+                    self.skipped.push((name, "return type is not supported".to_string()));
+                    return;
+                }
+            }
+        };
+        if let Some(ret) = &ret {
+            if ret.record && ret.ptr.is_empty() {
+                self.skipped.push((name, "records by value are not supported".to_string()));
+                return;
+            }
+        }
+
+        let params = &decl[open+1..close];
+        let mut args = Vec::new();
+        let mut vararg = false;
+        if !params.is_empty() && !(params.len() == 1 && is(params.first(), "void")) {
+            for (i, p) in params.split(|t|t == &Tok::Punct(",".to_string())).enumerate() {
+                if is(p.first(), "...") {
+                    vararg = true;
+                    continue;
+                }
+                let mut p = p.to_vec();
+                if p.iter().any(|t|t == &Tok::Punct("(".to_string())) {
+                    self.skipped.push((name, "function pointer arguments are not supported".to_string()));
+                    return;
+                }
+                // char buf[] is char *buf
+                if let Some(b) = p.iter().position(|t|t == &Tok::Punct("[".to_string())) {
+                    p.truncate(b);
+                    p.insert(p.len() - 1, Tok::Punct("*".to_string()));
+                }
+
+                let named = match ident(p.last()) {
+                    Some(n) => {
+                        let l = p.len();
+                        l > 1
+                            && !TYPE_WORDS.contains(&n.as_str())
+                            && !is(p.get(l-2), "struct") && !is(p.get(l-2), "union") && !is(p.get(l-2), "enum")
+                            && p[..l-1].iter().any(|t| match t { Tok::Ident(v) => v != "const", _ => false })
+                    }
+                    None => false,
+                };
+                let (t, argname) = if named {
+                    (p[..p.len()-1].to_vec(), ident(p.last()).unwrap())
+                } else {
+                    (p.clone(), format!("a{}", i))
+                };
+                let typed = match self.typed(&t) {
+                    Some(v) => v,
+                    None => {
+                        self.skipped.push((name, format!("type of argument {} is not supported", argname)));
+                        return;
+                    }
+                };
+                if typed.record && typed.ptr.is_empty() {
+                    self.skipped.push((name, "records by value are not supported".to_string()));
+                    return;
+                }
+                args.push((typed, escape(&argname)));
+            }
+        }
+
+        if vararg {
+            self.varargs.push(name);
+            return;
+        }
+
+        self.functions.push(Function{name, ret, args});
+    }
+
+    // c type to zz type
+    fn typed(&mut self, t: &[Tok]) -> Option<ZType> {
+        let mut words = Vec::new();
+        let mut ptr   = Vec::new();
+        let mut constant = false;
+        for t in t {
+            match t {
+                Tok::Ident(v) if v == "const" => constant = true,
+                Tok::Ident(v) => words.push(v.clone()),
+                Tok::Punct(p) if p == "*" => {
+                    ptr.push(!constant);
+                    constant = false;
+                }
+                _ => return None,
+            }
+        }
+
+        if words.is_empty() {
+            return None;
+        }
+
+        // plain char may be unsigned, signed char is not
+        let signed = words.len() > 1 && words.iter().any(|s| s == "signed");
+        let mut w : Vec<&str> = words.iter().map(|s|s.as_str()).filter(|s|*s != "signed" || words.len() == 1).collect();
+        if w.len() > 1 && w.last() == Some(&"int") {
+            w.pop();
+        }
+        let joined = w.join(" ");
+
+        // long is 32 bit on windows and 32 bit targets
+        let platform = super::pp::Platform::get();
+        let (long, ulong) = if platform.family == "windows" || platform.pointer_width == 32 {
+            ("i32", "u32")
+        } else {
+            ("i64", "u64")
+        };
+
+        let base = match joined.as_str() {
+            "void"                  => "void",
+            "char" if signed        => "i8",
+            "char"                  => "char",
+            "signed"                => "int",
+            "unsigned char"         => "u8",
+            "short"                 => "i16",
+            "unsigned short"        => "u16",
+            "int"                   => "int",
+            "unsigned" | "unsigned int" => "uint",
+            "long"                  => long,
+            "unsigned long"         => ulong,
+            "long long"             => "i64",
+            "unsigned long long"    => "u64",
+            "float"                 => "f32",
+            "double"                => "f64",
+            "_Bool" | "bool"        => "bool",
+            "size_t" | "uintptr_t"  => "usize",
+            "ssize_t" | "intptr_t" | "ptrdiff_t" => "isize",
+            "int8_t"    => "i8",
+            "int16_t"   => "i16",
+            "int32_t"   => "i32",
+            "int64_t"   => "i64",
+            "uint8_t"   => "u8",
+            "uint16_t"  => "u16",
+            "uint32_t"  => "u32",
+            "uint64_t"  => "u64",
+            _ => "",
+        };
+        if !base.is_empty() {
+            return Some(ZType{base: base.to_string(), ptr, record: false});
+        }
+
+        if self.unsupported.contains(&joined) {
+            return None;
+        }
+        if let Some(t) = self.types.get(&joined) {
+            let mut t = t.clone();
+            t.ptr.extend(ptr);
+            return Some(t);
+        }
+
+        match w.as_slice() {
+            // opaque struct only behind a pointer
+            ["struct", tag] | ["union", tag] if !ptr.is_empty() => {
+                let alias = format!("{}_t", tag);
+                self.imports.insert(alias.clone(), format!("({})", joined));
+                Some(ZType{base: alias, ptr, record: false})
+            }
+            ["enum", _] => Some(ZType{base: "int".to_string(), ptr, record: false}),
+            [name] => {
+                // a typedef from somewhere else, like FILE
+                self.imports.insert(name.to_string(), name.to_string());
+                Some(ZType{base: name.to_string(), ptr, record: false})
+            }
+            _ => None,
+        }
+    }
+
+    fn defines(&mut self, raw: &str) {
+        let mut seen = HashSet::new();
+        // the macro tested by the directive before, to recognize include guards
+        let mut guard = None;
+        for line in raw.lines() {
+            let line = line.trim();
+            let line = match line.strip_prefix('#') {
+                Some(v) => v.trim_start(),
+                None => continue,
+            };
+            let tested = guard.take();
+            if let Some(v) = line.strip_prefix("ifndef") {
+                guard = v.split_whitespace().next().map(|v| v.to_string());
+                continue;
+            }
+            let line = match line.strip_prefix("define") {
+                Some(v) => v,
+                None => continue,
+            };
+            let mut parts = line.split_whitespace();
+            let name = match parts.next() {
+                Some(v) if !v.contains('(') => v,
+                _ => continue,
+            };
+            if tested.as_deref() == Some(name) {
+                continue;
+            }
+            let value : String = parts.collect::<Vec<&str>>().join("");
+            if value.is_empty() {
+                continue;
+            }
+            let value = value.split("/*").next().unwrap().split("//").next().unwrap();
+            let value = value.trim_start_matches('(').trim_end_matches(')');
+            if let Some(v) = integer(value) {
+                if !seen.insert(name.to_string()) {
+                    continue;
+                }
+                let parsed = match v.strip_prefix("0x") {
+                    Some(hex) => i64::from_str_radix(hex, 16),
+                    None => v.parse::<i64>(),
+                };
+                let t = match parsed {
+                    Ok(i) if i >= i32::MIN as i64 && i <= i32::MAX as i64 => "int",
+                    Ok(_)   => "i64",
+                    Err(_)  => "u64",
+                };
+                self.constants.push((t.to_string(), escape(name), v));
+            }
+        }
+    }
+
+    fn emit(&self, include: &str, annotations: &HashMap<String, Annotation>) -> String {
+        let mut s = format!("// generated by zz bindgen from {}\n// attach contracts in an annotation file instead of editing this\n\n", include);
+
+        s.push_str(&format!("using {} as c;\n", include));
+        if !self.imports.is_empty() || !self.varargs.is_empty() {
+            let mut locals : Vec<String> = self.imports.iter().map(|(alias, name)| {
+                if alias == name {
+                    name.clone()
+                } else {
+                    format!("{} as {}", name, alias)
+                }
+            }).collect();
+            // varargs cannot be forwarded by a wrapper, so they are imported unchecked
+            locals.extend(self.varargs.iter().cloned());
+            s.push_str(&format!("pub using {}::{{{}}};\n", include, locals.join(", ")));
+        }
+        s.push('\n');
+
+        for (t, name, v) in &self.constants {
+            s.push_str(&format!("pub const {} {} = {};\n", t, name, v));
+        }
+        if !self.constants.is_empty() {
+            s.push('\n');
+        }
+
+        for (name, variants) in &self.enums {
+            s.push_str(&format!("pub enum {} {{\n", name));
+            for (v, value) in variants {
+                match value {
+                    Some(value) => s.push_str(&format!("    {} = {},\n", v, value)),
+                    None        => s.push_str(&format!("    {},\n", v)),
+                }
+            }
+            s.push_str("}\n\n");
+        }
+
+        for (name, union, fields) in &self.structs {
+            s.push_str(&format!("pub {} {} {{\n", if *union { "union" } else { "struct" }, name));
+            for f in fields {
+                s.push_str(&format!("    {} {}", zztype(&f.typed, &[]), f.name));
+                if let Some(a) = &f.array {
+                    s.push_str(&format!("[{}]", a));
+                }
+                s.push_str(";\n");
+            }
+            s.push_str("}\n\n");
+        }
+
+        for f in &self.functions {
+            let empty = Annotation::default();
+            let a = annotations.get(&f.name).unwrap_or(&empty);
+            let name = escape(&f.name);
+
+            let args : Vec<String> = f.args.iter().map(|(t, n)| {
+                let mut tags = Vec::new();
+                if a.nullable.contains(n) {
+                    tags.push("unsafe");
+                }
+                format!("{} {}", zztype(t, &tags), n)
+            }).collect();
+
+            s.push_str(&format!("pub inline fn {}({})", name, args.join(", ")));
+            if let Some(ret) = &f.ret {
+                s.push_str(&format!(" -> {}", zztype(ret, &[])));
+            }
+            s.push('\n');
+            for w in &a.callassert {
+                s.push_str(&format!("    where {}\n", w));
+            }
+            for m in &a.calleffect {
+                s.push_str(&format!("    model {}\n", m));
+            }
+
+            // records are distinct types in zz, so pass them through void
+            let params : Vec<String> = f.args.iter().map(|(t, n)| {
+                if t.record {
+                    format!("(void{}){}", if t.ptr[0] { " mut*" } else { "*" }, n)
+                } else {
+                    n.clone()
+                }
+            }).collect();
+            let mut call = format!("c::{}({})", f.name, params.join(", "));
+            if let Some(ret) = &f.ret {
+                call = format!("as<{}>({})", zztype(ret, &[]), call);
+            }
+
+            s.push_str("{\n");
+            match &f.ret {
+                None => {
+                    s.push_str(&format!("    {};\n", call));
+                }
+                Some(ret) if !a.calleffect.is_empty() => {
+                    // the c function cannot be proven, so the model is attested
+                    let r = temporary(&f.args);
+                    s.push_str(&format!("    {} {} = {};\n", zztype(ret, &[]), r, call));
+                    for m in &a.calleffect {
+                        s.push_str(&format!("    static_attest({});\n", replace_return(m, &r)));
+                    }
+                    s.push_str(&format!("    return {};\n", r));
+                }
+                Some(_) => {
+                    s.push_str(&format!("    return {};\n", call));
+                }
+            }
+            s.push_str("}\n\n");
+        }
+
+        if !self.skipped.is_empty() {
+            s.push_str("/* not imported:\n");
+            for (name, why) in &self.skipped {
+                s.push_str(&format!("   {}: {}\n", name, why));
+            }
+            s.push_str("*/\n");
+        }
+
+        s
+    }
+}
+
+fn matching(decl: &[Tok], open: usize) -> Option<usize> {
+    let (o, c) = match &decl[open] {
+        Tok::Punct(p) if p == "(" => ("(", ")"),
+        Tok::Punct(p) if p == "{" => ("{", "}"),
+        Tok::Punct(p) if p == "[" => ("[", "]"),
+        _ => return None,
+    };
+    let mut d = 0;
+    for (i, t) in decl.iter().enumerate().skip(open) {
+        if is(Some(t), o) {
+            d += 1;
+        } else if is(Some(t), c) {
+            d -= 1;
+            if d == 0 {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+fn zztype(t: &ZType, tags: &[&str]) -> String {
+    let mut s = t.base.clone();
+    for mutable in &t.ptr {
+        if *mutable {
+            s.push_str(" mut*");
+        } else {
+            s.push('*');
+        }
+    }
+    for tag in tags {
+        s.push(' ');
+        s.push_str(tag);
+    }
+    s
+}
+
+// integer literal without C suffixes, or None
+fn integer(v: &str) -> Option<String> {
+    let v = v.trim();
+    let (neg, v) = match v.strip_prefix('-') {
+        Some(v) => (true, v),
+        None => (false, v),
+    };
+    let v = v.trim_end_matches(['u', 'U', 'l', 'L']);
+    let ok = if let Some(hex) = v.strip_prefix("0x").or(v.strip_prefix("0X")) {
+        !hex.is_empty() && hex.chars().all(|c|c.is_ascii_hexdigit())
+    } else {
+        !v.is_empty() && v.chars().all(|c|c.is_ascii_digit())
+    };
+    if !ok {
+        return None;
+    }
+    let v = v.to_lowercase();
+    if neg {
+        Some(format!("-{}", v))
+    } else {
+        Some(v)
+    }
+}
+
+// name for the return value in a wrapper, which must not shadow a parameter
+fn temporary(args: &[(ZType, String)]) -> String {
+    let mut r = "r".to_string();
+    while args.iter().any(|(_, n)| n == &r) {
+        r.push('_');
+    }
+    r
+}
+
+fn replace_return(expr: &str, name: &str) -> String {
+    let mut r = String::new();
+    let mut word = String::new();
+    for c in expr.chars().chain(std::iter::once(' ')) {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            word.push(c);
+            continue;
+        }
+        if word == "return" {
+            r.push_str(name);
+        } else {
+            r.push_str(&word);
+        }
+        word.clear();
+        r.push(c);
+    }
+    r.pop();
+    r
+}
@@ -0,0 +1,117 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use super::make::{self, Stage, Target};
+use super::project::ArtifactType;
+use super::session::Session;
+use super::{BuildSet, Error};
+
+/// builds the staticlibs of a zz project from a cargo build.rs and links them in
+pub struct Builder {
+    root:       PathBuf,
+    variant:    String,
+    stage:      Stage,
+    artifacts:  HashSet<String>,
+    cargo:      bool,
+}
+
+impl Builder {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Builder {
+            root:       root.into(),
+            variant:    "default".to_string(),
+            stage:      Stage::release(),
+            artifacts:  HashSet::new(),
+            cargo:      true,
+        }
+    }
+
+    pub fn variant(mut self, variant: &str) -> Self {
+        self.variant = variant.to_string();
+        self
+    }
+
+    pub fn stage(mut self, stage: Stage) -> Self {
+        self.stage = stage;
+        self
+    }
+
+    /// build only this artifact. may be given more than once.
+    /// without any, every staticlib of the project is built
+    pub fn artifact(mut self, name: &str) -> Self {
+        self.artifacts.insert(name.to_string());
+        self
+    }
+
+    /// print cargo:rerun-if-changed and cargo:rustc-link-lib lines. on by default
+    pub fn cargo(mut self, cargo: bool) -> Self {
+        self.cargo = cargo;
+        self
+    }
+
+    pub fn build(self) -> Result<Vec<Target>, Error> {
+        // errors are reported through log. cargo shows a failed build script's stderr
+        if log::set_logger(&STDERR).is_ok() {
+            log::set_max_level(log::LevelFilter::Warn);
+        }
+
+        let mut session = Session::new(&self.root).variant(&self.variant).stage(self.stage.clone());
+        let (root, project) = session.load()?;
+        let artifacts = project.artifacts.clone().unwrap_or_default();
+
+        let select : HashSet<String> = if self.artifacts.is_empty() {
+            artifacts.iter().filter(|a| a.typ == ArtifactType::Staticlib).map(|a| a.name.clone()).collect()
+        } else {
+            self.artifacts.clone()
+        };
+        if select.is_empty() {
+            return Err(Error::new(format!("{} has no staticlib artifact", project.project.name), Vec::new()));
+        }
+        for name in &select {
+            if !artifacts.iter().any(|a| &a.name == name) {
+                return Err(Error::new(format!("{} has no artifact named {}", project.project.name, name), Vec::new()));
+            }
+            session = session.artifact(name);
+        }
+
+        make::BUILD_RS.store(self.cargo, Ordering::SeqCst);
+        let targets = session.build(BuildSet::All);
+        make::BUILD_RS.store(false, Ordering::SeqCst);
+        let targets = targets?;
+
+        if self.cargo {
+            let mut deps = HashSet::new();
+            deps.insert(root.join("zz.toml"));
+            // new files in src/ change what gets built too
+            deps.insert(root.join("src"));
+            for target in &targets {
+                for unit in &target.units {
+                    deps.extend(unit.deps.iter().map(|d| root.join(d)));
+                }
+            }
+            let mut deps : Vec<PathBuf> = deps.into_iter().collect();
+            deps.sort();
+            for dep in deps {
+                println!("cargo:rerun-if-changed={}", dep.display());
+            }
+        }
+
+        Ok(targets)
+    }
+}
+
+// for build scripts that don't set up a logger of their own
+struct Stderr;
+static STDERR: Stderr = Stderr;
+
+impl log::Log for Stderr {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn
+    }
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{}] {}", record.level(), record.args());
+        }
+    }
+    fn flush(&self) {}
+}
@@ -0,0 +1,248 @@
+use askama::Template;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use super::project::target_dir;
+
+/// file -> line -> execution count
+pub type Lines = BTreeMap<String, BTreeMap<u32, u64>>;
+
+pub fn dir(root: &Path, stage: &str) -> PathBuf {
+    target_dir(root).join(stage).join("coverage")
+}
+
+/// remove profiles of earlier runs, so they don't count towards this one
+pub fn clean(root: &Path, stage: &str) {
+    let profiles = dir(root, stage).join("profiles");
+    std::fs::remove_dir_all(&profiles).ok();
+    std::fs::create_dir_all(&profiles).expect(&format!("cannot create {:?}", profiles));
+}
+
+/// the LLVM_PROFILE_FILE for one testcase. %p keeps apart processes the test spawns
+pub fn profile_file(root: &Path, stage: &str, artifact: &str, case: &str) -> String {
+    dir(root, stage).join("profiles").join(format!("{}-{}-%p.profraw", artifact, case)).to_string_lossy().to_string()
+}
+
+/// merge the profiles of all test executables and write lcov.info and html/ in terms of the zz sources
+pub fn report(root: &Path, stage: &str, exes: &[PathBuf]) -> Result<(usize, usize), String> {
+    let dir = dir(root, stage);
+
+    let mut profiles = Vec::new();
+    for entry in std::fs::read_dir(dir.join("profiles")).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.extension().map(|e| e == "profraw").unwrap_or(false) {
+            profiles.push(path);
+        }
+    }
+    if profiles.is_empty() || exes.is_empty() {
+        return Err("no profiles were written".to_string());
+    }
+
+    let profdata = dir.join("merged.profdata");
+    let llvm_profdata = std::env::var("LLVM_PROFDATA").unwrap_or("llvm-profdata".to_string());
+    let status = Command::new(&llvm_profdata)
+        .arg("merge")
+        .arg("-sparse")
+        .args(&profiles)
+        .arg("-o")
+        .arg(&profdata)
+        .status()
+        .map_err(|e| format!("failed to execute {}: {}", llvm_profdata, e))?;
+    if !status.success() {
+        return Err(format!("{} merge failed", llvm_profdata));
+    }
+
+    let llvm_cov = std::env::var("LLVM_COV").unwrap_or("llvm-cov".to_string());
+    let mut cmd = Command::new(&llvm_cov);
+    cmd.arg("export").arg("-format=lcov").arg(format!("-instr-profile={}", profdata.to_string_lossy()));
+    cmd.arg(&exes[0]);
+    for exe in &exes[1..] {
+        cmd.arg("-object").arg(exe);
+    }
+    let out = cmd.output().map_err(|e| format!("failed to execute {}: {}", llvm_cov, e))?;
+    if !out.status.success() {
+        return Err(format!("{} export failed: {}", llvm_cov, String::from_utf8_lossy(&out.stderr)));
+    }
+
+    let lines = to_zz(root, parse_lcov(&String::from_utf8_lossy(&out.stdout)));
+
+    std::fs::write(dir.join("lcov.info"), write_lcov(&lines)).map_err(|e| e.to_string())?;
+    html(root, &dir.join("html"), &lines).map_err(|e| e.to_string())?;
+
+    let found = lines.values().map(|l| l.len()).sum();
+    let hit   = lines.values().map(|l| l.values().filter(|c| **c > 0).count()).sum();
+    Ok((hit, found))
+}
+
+fn parse_lcov(lcov: &str) -> Lines {
+    let mut r = Lines::new();
+    let mut file = None;
+    for line in lcov.lines() {
+        if let Some(sf) = line.strip_prefix("SF:") {
+            file = Some(sf.to_string());
+        } else if let Some(da) = line.strip_prefix("DA:") {
+            let mut da = da.split(',');
+            let line  = da.next().and_then(|v| v.parse().ok());
+            let count = da.next().and_then(|v| v.parse().ok());
+            if let (Some(file), Some(line), Some(count)) = (&file, line, count) {
+                let e = r.entry(file.clone()).or_default().entry(line).or_insert(0);
+                *e = (*e).max(count);
+            }
+        } else if line == "end_of_record" {
+            file = None;
+        }
+    }
+    r
+}
+
+// everything the emitter writes after a #line directive belongs to that zz line.
+// a zz line that became several c lines counts as executed if any of them was
+fn to_zz(root: &Path, c: Lines) -> Lines {
+    let mut r = Lines::new();
+    for (file, lines) in c {
+        let map = match line_map(&root.join(&file)) {
+            Some(v) => v,
+            None => {
+                // plain c sources are reported as they are
+                r.insert(file, lines);
+                continue;
+            }
+        };
+        for (line, count) in lines {
+            if let Some((zfile, zline)) = map.get(&line) {
+                let e = r.entry(zfile.clone()).or_default().entry(*zline).or_insert(0);
+                *e = (*e).max(count);
+            }
+        }
+    }
+    r
+}
+
+// c line -> zz file and line, or None if the file has no #line directives
+fn line_map(cfile: &Path) -> Option<HashMap<u32, (String, u32)>> {
+    let src = std::fs::read_to_string(cfile).ok()?;
+    let mut r   = HashMap::new();
+    let mut cur = None;
+    for (i, line) in src.lines().enumerate() {
+        if let Some(directive) = line.strip_prefix("#line ") {
+            let mut parts = directive.splitn(2, ' ');
+            let zline = parts.next().and_then(|v| v.parse().ok());
+            let zfile = parts.next().map(|v| v.trim().trim_matches('"').replace("\\\\", "\\"));
+            cur = zline.and_then(|l| zfile.map(|f| (f, l)));
+            continue;
+        }
+        if let Some(cur) = &cur {
+            r.insert(i as u32 + 1, cur.clone());
+        }
+    }
+    if r.is_empty() {
+        return None;
+    }
+    Some(r)
+}
+
+fn write_lcov(lines: &Lines) -> String {
+    let mut r = String::new();
+    r.push_str("TN:\n");
+    for (file, lines) in lines {
+        r.push_str(&format!("SF:{}\n", file));
+        for (line, count) in lines {
+            r.push_str(&format!("DA:{},{}\n", line, count));
+        }
+        r.push_str(&format!("LH:{}\n", lines.values().filter(|c| **c > 0).count()));
+        r.push_str(&format!("LF:{}\n", lines.len()));
+        r.push_str("end_of_record\n");
+    }
+    r
+}
+
+fn html(root: &Path, out: &Path, lines: &Lines) -> std::io::Result<()> {
+    std::fs::create_dir_all(out)?;
+
+    let mut files = Vec::new();
+    for (file, counts) in lines {
+        let name = Path::new(file).strip_prefix(root).map(|p| p.to_string_lossy().to_string()).unwrap_or(file.clone());
+        let page = format!("{}.html", name.replace(|c: char| !c.is_alphanumeric(), "_"));
+        let hit   = counts.values().filter(|c| **c > 0).count();
+        let found = counts.len();
+
+        let src = std::fs::read_to_string(root.join(file)).unwrap_or_default();
+        let lines = src.lines().enumerate().map(|(i, text)| {
+            let count = counts.get(&(i as u32 + 1));
+            LineHtml {
+                number: i + 1,
+                class:  match count { None => "", Some(0) => "miss", Some(_) => "hit" },
+                count:  count.map(|c| c.to_string()).unwrap_or_default(),
+                text:   text.to_string(),
+            }
+        }).collect();
+
+        let tpl = CoverageFileHtml {
+            name:    name.clone(),
+            hit,
+            found,
+            percent: percent(hit, found),
+            lines,
+        };
+        std::fs::write(out.join(&page), tpl.render().unwrap())?;
+
+        files.push(FileHtml{
+            percent: percent(hit, found),
+            name,
+            page,
+            hit,
+            found,
+        });
+    }
+
+    let hit   = files.iter().map(|f| f.hit).sum();
+    let found = files.iter().map(|f| f.found).sum();
+    let tpl = CoverageIndexHtml {
+        hit,
+        found,
+        percent: percent(hit, found),
+        files,
+    };
+    std::fs::write(out.join("index.html"), tpl.render().unwrap())
+}
+
+fn percent(hit: usize, found: usize) -> String {
+    if found == 0 {
+        return "-".to_string();
+    }
+    format!("{:.1}%", hit as f64 / found as f64 * 100.0)
+}
+
+struct FileHtml {
+    name:       String,
+    page:       String,
+    hit:        usize,
+    found:      usize,
+    percent:    String,
+}
+
+struct LineHtml {
+    number:     usize,
+    class:      &'static str,
+    count:      String,
+    text:       String,
+}
+
+#[derive(Template)]
+#[template(path = "coverage_index.html")]
+struct CoverageIndexHtml {
+    hit:        usize,
+    found:      usize,
+    percent:    String,
+    files:      Vec<FileHtml>,
+}
+
+#[derive(Template)]
+#[template(path = "coverage_file.html")]
+struct CoverageFileHtml {
+    name:       String,
+    hit:        usize,
+    found:      usize,
+    percent:    String,
+    lines:      Vec<LineHtml>,
+}
@@ -0,0 +1,44 @@
+use std::sync::{Arc, RwLock};
+use super::Error;
+
+/// receives every error and warning the pipeline reports, as it reports them.
+///
+/// errors still end the stage that reported them. the stage returns the first one.
+pub trait Diagnostics: Send + Sync {
+    fn error(&self, e: &Error);
+    fn warn(&self, e: &Error);
+}
+
+/// the cli's sink. prints through log, or as json lines for editors with zz check
+pub struct Log;
+
+impl Diagnostics for Log {
+    fn error(&self, e: &Error) {
+        super::parser::log_error(e);
+    }
+    fn warn(&self, e: &Error) {
+        super::parser::log_warn(e);
+    }
+}
+
+static SINK: RwLock<Option<Arc<dyn Diagnostics>>> = RwLock::new(None);
+
+/// install a sink for everything reported from now on, returning the previous one.
+/// None goes back to Log
+pub fn set(sink: Option<Arc<dyn Diagnostics>>) -> Option<Arc<dyn Diagnostics>> {
+    std::mem::replace(&mut *SINK.write().unwrap(), sink)
+}
+
+pub fn error(e: &Error) {
+    match &*SINK.read().unwrap() {
+        Some(sink) => sink.error(e),
+        None => Log.error(e),
+    }
+}
+
+pub fn warn(e: &Error) {
+    match &*SINK.read().unwrap() {
+        Some(sink) => sink.warn(e),
+        None => Log.warn(e),
+    }
+}
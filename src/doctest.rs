@@ -0,0 +1,185 @@
+use super::loader;
+use super::make::Stage;
+use super::name::Name;
+use super::project::{Artifact, ArtifactType, target_dir};
+use super::Error;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+
+/// a code example extracted from a doc comment
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Doctest {
+    pub name:           String,
+    pub module:         Name,
+    pub documented:     String,
+    pub file:           String,
+    pub line:           usize,
+    pub should_fail:    bool,
+}
+
+impl Doctest {
+    pub fn artifact(&self) -> Artifact {
+        Artifact {
+            name:   self.name.clone(),
+            main:   self.module.human_name(),
+            typ:    ArtifactType::Test,
+            ..Default::default()
+        }
+    }
+}
+
+pub fn dir(root: &Path, stage: &Stage) -> std::path::PathBuf {
+    target_dir(root).join(stage.to_string()).join("doctests")
+}
+
+/// scan the doc comments of all zz modules belonging to the project and write every fenced
+/// example into target/<stage>/doctests/ as a standalone test module.
+/// files are only written when their content changed, so unchanged examples are not rebuilt.
+pub fn generate(
+    modules:        &HashMap<Name, loader::Module>,
+    project_name:   &Name,
+    root:           &Path,
+    stage:          &Stage,
+) -> Result<Vec<Doctest>, Error> {
+    let dir = dir(root, stage);
+    std::fs::create_dir_all(&dir).expect("create doctest dir");
+    let mut written = HashSet::new();
+
+    let mut names : Vec<&Name> = modules.keys().collect();
+    names.sort_unstable();
+
+    let mut tests = Vec::new();
+    for name in names {
+        // only document what the project itself exports, not dependencies or its own tests
+        if name.0.len() < 2 || name.0[1] != project_name.0[1] || name.0.get(2).map(|s|s.as_str()) == Some("tests") {
+            continue;
+        }
+        let md = match &modules[name] {
+            loader::Module::ZZ(md) => md,
+            loader::Module::C(_) => continue,
+        };
+
+        for local in &md.locals {
+            let examples = extract(&local.doc).map_err(|e| {
+                Error::new(e, vec![(local.loc.clone(), format!("in the documentation of {}", local.name))])
+            })?;
+            for (i, (code, should_fail)) in examples.into_iter().enumerate() {
+                let stem = format!("{}_{}_{}", name.0[1..].join("_"), local.name, i);
+                let path = dir.join(format!("{}.zz", stem));
+                super::write_if_changed(&path, wrap(name, &code).as_bytes()).expect(&format!("cannot write {:?}", path));
+                written.insert(path);
+
+                let mut module = project_name.clone();
+                module.push("doctests".to_string());
+                module.push(stem.clone());
+
+                tests.push(Doctest{
+                    name:       format!("doctest_{}", stem),
+                    module,
+                    documented: format!("{}::{}", name.human_name(), local.name),
+                    file:       local.loc.file.clone(),
+                    line:       local.loc.line(),
+                    should_fail,
+                });
+            }
+        }
+    }
+
+    // examples that were removed from the docs would otherwise still be loaded as modules
+    for entry in std::fs::read_dir(&dir).expect("read doctest dir") {
+        let path = entry.expect("read doctest dir").path();
+        if path.extension().map(|e| e == "zz").unwrap_or(false) && !written.contains(&path) {
+            std::fs::remove_file(&path).expect(&format!("cannot remove {:?}", path));
+        }
+    }
+
+    let index = serde_json::to_vec(&tests).expect("cannot serialize doctest index");
+    super::write_if_changed(&dir.join("index.json"), &index).expect("cannot write doctest index");
+    Ok(tests)
+}
+
+/// the doctests written by the last call to generate()
+pub fn load(root: &Path, stage: &Stage) -> Vec<Doctest> {
+    match std::fs::read_to_string(dir(root, stage).join("index.json")) {
+        Ok(s) => serde_json::from_str(&s).unwrap_or_default(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// returns the code of every fenced block that is marked as zz (or not marked at all),
+/// together with whether it is expected to fail the proof.
+/// blocks marked "ignore" or with any other language are not tests.
+pub fn extract(doc: &str) -> Result<Vec<(String, bool)>, String> {
+    let mut r = Vec::new();
+    let mut current : Option<(String, bool)> = None;
+    let mut skip = false;
+
+    for line in doc.lines() {
+        if let Some(info) = line.trim().strip_prefix("```") {
+            if skip {
+                skip = false;
+                continue;
+            }
+            if let Some(block) = current.take() {
+                r.push(block);
+                continue;
+            }
+            let mut should_fail = false;
+            for attr in info.split(',').map(|s|s.trim()).filter(|s|!s.is_empty()) {
+                match attr {
+                    "zz"            => (),
+                    "should_fail"   => should_fail = true,
+                    _               => skip = true,
+                }
+            }
+            if !skip {
+                current = Some((String::new(), should_fail));
+            }
+            continue;
+        }
+        if let Some((code, _)) = &mut current {
+            // doc comments are written as "/! code", so drop the single separating space
+            code.push_str(line.strip_prefix(' ').unwrap_or(line));
+            code.push('\n');
+        }
+    }
+    if current.is_some() || skip {
+        return Err("code block in doc comment is not closed with ```".to_string());
+    }
+    Ok(r)
+}
+
+/// examples that bring their own main are used verbatim,
+/// everything else becomes the body of a main that returns 0
+fn wrap(documented: &Name, code: &str) -> String {
+    let mut s = format!("using {};\n", documented.human_name());
+
+    if super::parser::declares_main(code) {
+        s.push_str(code);
+        return s;
+    }
+
+    let mut body = String::new();
+    for line in code.lines() {
+        if line.trim_start().starts_with("using ") {
+            s.push_str(line);
+            s.push('\n');
+        } else {
+            body.push_str("    ");
+            body.push_str(line);
+            body.push('\n');
+        }
+    }
+
+    s.push_str("\nexport fn main() -> int {\n");
+    s.push_str(&body);
+    s.push_str("    return 0;\n}\n");
+    s
+}
+
+impl std::fmt::Display for Doctest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}:{})", self.documented, self.file, self.line)
+    }
+}
@@ -1,15 +1,29 @@
-use super::project::{Project};
+use super::project::Project;
 use std::fs;
 use super::flatten;
 use super::ast;
 use super::make;
 use std::io::{Write, Read};
-use std::collections::HashSet;
+use std::collections::{HashSet, HashMap};
 use std::path::PathBuf;
 use super::name::Name;
-use super::parser::{self, emit_error};
+use super::Error;
+use std::cell::RefCell;
+use super::parser::{self, error};
+use super::testrunner;
 use serde::{Serialize,Deserialize};
 
+/// the field a tail variant of a struct without its own tail array gets, to make room for the tail
+pub const TAIL_PADDING : &str = "_____tail";
+
+// what a failed runtime check of a where or model clause calls. a project can define its own first
+const CONTRACT_VIOLATION : &str = r#"#include <stdio.h>
+#include <stdlib.h>
+#ifndef ZZ_CONTRACT_VIOLATION
+#define ZZ_CONTRACT_VIOLATION(fn, kind, clause) do { fprintf(stderr, "%s: %s violated: %s\n", fn, kind, clause); abort(); } while (0)
+#endif
+"#;
+
 #[derive(Serialize, Deserialize)]
 pub struct CFile {
     pub name:       Name,
@@ -24,10 +38,39 @@ pub struct Emitter{
     f:              fs::File,
     module:         flatten::Module,
     header:         bool,
+    // one file holding every module. everything is guarded, since each module brings its own copy of what it uses.
+    // as a header, the definitions are in it too, all static inline
+    amalgamated:    bool,
     inside_macro:   bool,
     cur_loc:        Option<ast::Location>,
     casedir:        String,
     emit_as_extern: HashSet<Name>,
+    contracts:      bool,
+    // main is renamed and driven by LLVMFuzzerTestOneInput
+    fuzz_main:      bool,
+    // the function currently being emitted, for return checks
+    cur_fn:         Option<ContractFn>,
+    // local array name -> c expression of its length
+    bounds:         HashMap<String, String>,
+    error:          RefCell<Option<Error>>,
+}
+
+struct ContractFn {
+    name:       String,
+    ret:        Option<ast::Typed>,
+    calleffect: Vec<ast::Expression>,
+}
+
+pub fn amalgamated_name(stage: &make::Stage, module: &Name, header: bool) -> String {
+    let mut ns = module.0.clone();
+    ns.remove(0);
+    format!("{}/amalgamated/{}.{}", stage.modules().display(), ns.join("_"), if header { "h" } else { "c" })
+}
+
+pub fn headername(project: &str, stage: &make::Stage, module: &Name) -> String {
+    let mut ns = module.0.clone();
+    ns.remove(0);
+    format!("{}/include/zz/{}/{}.h", stage.modules().display(), project, ns.join("_"))
 }
 
 pub fn outname(project: &Project, stage: &make::Stage, module: &Name , header: bool) -> (bool, String) {
@@ -41,21 +84,33 @@ pub fn outname(project: &Project, stage: &make::Stage, module: &Name , header: b
     let mut ns = module.0.clone();
     ns.remove(0);
     if header {
-        (cxx, format!("target/{}/include/zz/{}/{}.h", stage, project.name, ns.join("_")))
+        (cxx, headername(&project.name, stage, module))
     } else if cxx {
-        (cxx, format!("target/{}/zz/{}.cpp", stage, ns.join("_")))
+        (cxx, format!("{}/zz/{}.cpp", stage.modules().display(), ns.join("_")))
     } else {
-        (cxx, format!("target/{}/zz/{}.c", stage, ns.join("_")))
+        (cxx, format!("{}/zz/{}.c", stage.modules().display(), ns.join("_")))
     }
 }
 
 impl Emitter {
     pub fn new(project: &Project, stage: make::Stage , module: flatten::Module, header: bool) -> Self {
-
         let (cxx, p) = outname(project, &stage, &module.name, header);
+        Self::create(cxx, p, stage, module, header)
+    }
+
+    /// emit module for a header artifact, or for zz build --amalgamate
+    pub fn new_amalgamated(project: &Project, stage: make::Stage , module: flatten::Module, header: bool) -> Self {
+        let (cxx, _) = outname(project, &stage, &module.name, header);
+        let p = amalgamated_name(&stage, &module.name, header);
+        let mut em = Self::create(cxx, p, stage, module, header);
+        em.amalgamated = true;
+        em
+    }
+
+    fn create(cxx: bool, p: String, stage: make::Stage , module: flatten::Module, header: bool) -> Self {
         let mut f = fs::File::create(&p).expect(&format!("cannot create {}", p));
 
-        let casedir = format!("target/{}/testcases/{}", stage, module.name.0[1..].join("_"));
+        let casedir = format!("{}/{}/testcases/{}", stage.target.display(), stage, module.name.0[1..].join("_"));
         std::fs::remove_dir_all(&casedir).ok();
         std::fs::create_dir_all(&casedir).unwrap();
 
@@ -64,19 +119,47 @@ impl Emitter {
         write!(f, "#include <stddef.h>\n").unwrap();
         write!(f, "#include <stdbool.h>\n").unwrap();
 
+        let contracts = stage.contracts && !header;
+        let fuzz_main = stage.fuzz == Some(make::Fuzzer::Libfuzzer) && !header;
+        if contracts {
+            write!(f, "\n{}", CONTRACT_VIOLATION).unwrap();
+            write!(f, r#"#ifndef ZZ_CHECKED_INDEX
+#define ZZ_CHECKED_INDEX
+static inline size_t __attribute__ ((unused)) zz_checked_index(size_t i, size_t len, char const* what, char const* file, int line) {{
+    if (i >= len) {{
+        fprintf(stderr, "%s:%d: index %zu out of bounds of %s (len %zu)\n", file, line, i, what, len);
+        abort();
+    }}
+    return i;
+}}
+#endif
+"#).unwrap();
+        }
+
         Emitter{
             cxx,
             p,
             f,
             header,
+            amalgamated: false,
             casedir,
             module,
             inside_macro: false,
             cur_loc: None,
             emit_as_extern: HashSet::new(),
+            contracts,
+            fuzz_main,
+            cur_fn: None,
+            bounds: HashMap::new(),
+            error: RefCell::new(None),
         }
     }
 
+    // emitting goes on after an error, emit returns the first one
+    fn fail(&self, e: Error) {
+        self.error.borrow_mut().get_or_insert(e);
+    }
+
 
     fn emit_loc(&mut self, loc: &ast::Location) {
 
@@ -126,11 +209,11 @@ impl Emitter {
                 s
             }
             ast::Type::ILiteral | ast::Type::ULiteral | ast::Type::Elided | ast::Type::New => {
-                parser::emit_error(
+                self.fail(parser::error(
                     "ICE: untyped literal ended up in emitter",
                     &[(name.loc.clone(), format!("this should have been resolved earlier"))]
-                    );
-                std::process::exit(9);
+                    ));
+                String::new()
             }
         }
     }
@@ -145,8 +228,11 @@ impl Emitter {
         }
 
         assert!(s.is_absolute(), "ICE not abs: '{}'", s);
-        if let Some(an) = self.module.aliases.get(&s) {
-            return an.clone();
+        // aliases are per module, but an amalgamation is one namespace
+        if !self.amalgamated {
+            if let Some(an) = self.module.aliases.get(&s) {
+                return an.clone();
+            }
         }
 
         if s.0[1] == "ext" {
@@ -158,6 +244,19 @@ impl Emitter {
         return s.0.join("_");
     }
 
+    fn open_guard(&mut self, kind: &str, name: &str) {
+        if self.amalgamated {
+            let tn : String = name.chars().map(|x| if x.is_ascii_alphanumeric() { x } else { '_' }).collect();
+            write!(self.f, "\n#ifndef ZZ_AMALGAMATED_{kind}_{tn}\n#define ZZ_AMALGAMATED_{kind}_{tn}\n", kind = kind, tn = tn).unwrap();
+        }
+    }
+
+    fn close_guard(&mut self) {
+        if self.amalgamated {
+            write!(self.f, "#endif\n").unwrap();
+        }
+    }
+
     fn to_local_name_mangle(&self, s: &Name) -> String {
         self.to_local_name(s).chars().map(|x| match x {
             'A'..='Z'   => x,
@@ -168,13 +267,14 @@ impl Emitter {
         }).collect()
     }
 
-    pub fn emit(mut self) -> CFile {
+    pub fn emit(mut self) -> Result<CFile, Error> {
         let module = self.module.clone();
         debug!("emitting {}", module.name.0.join("_"));
 
         if self.header {
             let headername = module.name.0.join("_");
             write!(self.f, "#ifndef ZZ_EXPORT_HEADER_{}\n#define ZZ_EXPORT_HEADER_{}\n", headername, headername).unwrap();
+            write!(self.f, "\n#ifdef ZZ_CHECKED\n{}#endif\n", CONTRACT_VIOLATION).unwrap();
         }
 
         let mut dup = HashSet::new();
@@ -186,6 +286,7 @@ impl Emitter {
             debug!("    emitting0 {}", d.name);
             match &d.def {
                 ast::Def::Struct{..} => {
+                    self.open_guard("fwd", &self.to_local_name(&Name::from(&d.name)));
                     self.emit_struct_def(&d, None);
                     if let Some(vs) = module.typevariants.get(&Name::from(&d.name)) {
                         for v in vs {
@@ -194,6 +295,7 @@ impl Emitter {
                             self.emit_struct_def(&d, Some(*v));
                         }
                     }
+                    self.close_guard();
                 }
                 _ => (),
             }
@@ -206,10 +308,12 @@ impl Emitter {
                     if complete != &flatten::TypeComplete::Complete {
                         continue
                     }
-                    self.emit_macro(&d)
+                    self.open_guard("macro", &self.to_local_name(&Name::from(&d.name)));
+                    self.emit_macro(&d);
+                    self.close_guard();
                 }
                 ast::Def::Const{..} => {
-                    if self.header {
+                    if self.header || self.amalgamated {
                         write!(self.f, r#"
 #ifndef ZZ_EXPORT_{tn}
 #define ZZ_EXPORT_{tn}
@@ -217,23 +321,26 @@ impl Emitter {
                             tn = self.to_local_name_mangle(&Name::from(&d.name))).unwrap();
                     }
 
+                    self.emit_doc(&d);
                     self.emit_const(&d);
 
-                    if self.header {
+                    if self.header || self.amalgamated {
                         write!(self.f, "\n#endif\n").unwrap();
                     }
                 }
                 ast::Def::Static{..} => {
-                    if self.header || complete != &flatten::TypeComplete::Complete {
+                    if (self.header && !self.amalgamated) || complete != &flatten::TypeComplete::Complete {
                         continue
                     }
-                    self.emit_static(&d)
+                    self.open_guard("static", &self.to_local_name(&Name::from(&d.name)));
+                    self.emit_static(&d);
+                    self.close_guard();
                 }
                 ast::Def::Enum{..} => {
                     if complete != &flatten::TypeComplete::Complete {
                         continue
                     }
-                    if self.header {
+                    if self.header || self.amalgamated {
                         write!(self.f, r#"
 #ifndef ZZ_EXPORT_{tn}
 #define ZZ_EXPORT_{tn}
@@ -241,13 +348,17 @@ impl Emitter {
                             tn = self.to_local_name_mangle(&Name::from(&d.name))).unwrap();
                     }
 
+                    self.emit_doc(&d);
                     self.emit_enum(&d);
-                    if self.header {
+                    if self.header || self.amalgamated {
                         write!(self.f, "\n#endif\n").unwrap();
                     }
                 }
                 ast::Def::Fntype{..} => {
+                    self.open_guard("fntype", &self.to_local_name(&Name::from(&d.name)));
+                    self.emit_doc(&d);
                     self.emit_fntype(&d);
+                    self.close_guard();
                 }
                 ast::Def::Theory{..} => {
                 }
@@ -258,16 +369,26 @@ impl Emitter {
                     self.emit_testcase(&d);
                 }
                 ast::Def::Function{..} => {
+                    if self.amalgamated && self.header && d.name.ends_with("::main") {
+                        continue
+                    }
+                    self.emit_doc(&d);
                     self.emit_decl(&d);
                 }
-                ast::Def::Include {inline,..} => {
+                ast::Def::Include {inline, ref expr, ..} => {
                     if dup.insert(d.name.clone()) {
+                        if inline {
+                            self.open_guard("include", expr);
+                        }
                         self.emit_include(&d);
+                        if inline {
+                            self.close_guard();
+                        }
                     }
                 }
                 ast::Def::Struct{..} => {
                     if complete == &flatten::TypeComplete::Complete {
-                        if self.header {
+                        if self.header || self.amalgamated {
                             write!(self.f, r#"
 #ifndef ZZ_EXPORT_{tn}
 #define ZZ_EXPORT_{tn}
@@ -279,9 +400,10 @@ impl Emitter {
                         name.pop();
                         let isimpl = name == module.name;
 
+                        self.emit_doc(&d);
                         self.emit_struct(&d, isimpl, None);
 
-                        if self.header {
+                        if self.header || self.amalgamated {
                             write!(self.f, "\n#endif\n").unwrap();
                         }
 
@@ -289,14 +411,14 @@ impl Emitter {
                             for v in vs {
                                 let mut d = d.clone();
                                 d.name = format!("{}_{}", d.name, v);
-                                if self.header {
+                                if self.header || self.amalgamated {
                                     write!(self.f, r#"
 #ifndef ZZ_EXPORT_{tn}_{v}
 #define ZZ_EXPORT_{tn}_{v}
 "#,                                 tn = self.to_local_name_mangle(&Name::from(&d.name)),  v=v).unwrap();
                                 }
                                 self.emit_struct(&d, isimpl, Some(*v));
-                                if self.header {
+                                if self.header || self.amalgamated {
                                     write!(self.f, "\n#endif\n").unwrap();
                                 }
 
@@ -311,6 +433,34 @@ impl Emitter {
 
 
         if self.header {
+            // runtime guarded entry points for C callers that are not verified
+            write!(self.f, "\n#ifdef ZZ_CHECKED\n").unwrap();
+            for (d, complete) in &module.d {
+                if let ast::Def::Function{..} = &d.def {
+                    let mut mname = Name::from(&d.name);
+                    mname.pop();
+                    if complete == &flatten::TypeComplete::Complete && mname == module.name && d.vis == ast::Visibility::Export {
+                        self.emit_checked(&d);
+                    }
+                }
+            }
+            write!(self.f, "#endif\n").unwrap();
+
+            if self.amalgamated {
+                for (d, complete) in &module.d {
+                    if let ast::Def::Function{attr, ..} = &d.def {
+                        let mut mname = Name::from(&d.name);
+                        mname.pop();
+                        // a library has no main
+                        if complete == &flatten::TypeComplete::Complete && !d.name.ends_with("::main")
+                            && (mname == module.name || attr.contains_key("inline")) {
+                            self.open_guard("def", &self.to_local_name(&Name::from(&d.name)));
+                            self.emit_def(&d);
+                            self.close_guard();
+                        }
+                    }
+                }
+            }
             write!(self.f, "#endif\n").unwrap();
         } else {
             // function impls are always last.
@@ -322,7 +472,9 @@ impl Emitter {
                         let mut mname = Name::from(&d.name);
                         mname.pop();
                         if complete == &flatten::TypeComplete::Complete && (mname == module.name || attr.contains_key("inline")) {
+                            self.open_guard("def", &self.to_local_name(&Name::from(&d.name)));
                             self.emit_def(&d);
+                            self.close_guard();
                         }
                     }
                     _ => (),
@@ -330,15 +482,136 @@ impl Emitter {
             }
         }
 
-        CFile {
+        if let Some(e) = self.error.into_inner() {
+            return Err(e);
+        }
+        Ok(CFile {
             name:       module.name,
             filepath:   self.p,
             sources:    module.sources,
             deps:       module.deps,
+        })
+    }
+
+
+
+    // doc comments and contracts as doxygen block. only the header is meant to be read by humans
+    fn emit_doc(&mut self, ast: &ast::Local) {
+        if !self.header {
+            return;
         }
+
+        let mut lines : Vec<String> = ast.doc.lines().map(|l|l.strip_prefix(' ').unwrap_or(l).to_string()).collect();
+        while lines.last().map(|l|l.trim().is_empty()) == Some(true) {
+            lines.pop();
+        }
+
+        if let ast::Def::Function{callassert, calleffect, ..} = &ast.def {
+            if !lines.is_empty() && (!callassert.is_empty() || !calleffect.is_empty()) {
+                lines.push(String::new());
+            }
+            for expr in callassert {
+                lines.push(format!("@pre  {}", expr));
+            }
+            for expr in calleffect {
+                lines.push(format!("@post {}", expr));
+            }
+        }
+
+        if lines.is_empty() {
+            return;
+        }
+
+        write!(self.f, "\n/**\n").unwrap();
+        for line in lines {
+            // a stray */ in a doc comment would end the block early
+            let line = line.replace("*/", "* /");
+            if line.is_empty() {
+                write!(self.f, " *\n").unwrap();
+            } else {
+                write!(self.f, " * {}\n", line).unwrap();
+            }
+        }
+        write!(self.f, " */\n").unwrap();
     }
 
+    // <name>_checked asserts every where clause before and every model clause after calling <name>
+    fn emit_checked(&mut self, ast: &ast::Local) {
+        let (ret, args, vararg, attr, callassert, calleffect) = match &ast.def {
+            ast::Def::Function{ret, args, vararg, attr, callassert, calleffect, ..}
+                => (ret, args, *vararg, attr, callassert, calleffect),
+            _ => unreachable!(),
+        };
+
+        if vararg || (callassert.is_empty() && calleffect.is_empty()) {
+            return;
+        }
+
+        let name = Name::from(&ast.name);
+        let cname = if attr.contains_key("extern") {
+            name.0.last().unwrap().clone()
+        } else {
+            name.0[1..].join("_")
+        };
 
+        write!(self.f, "static inline ").unwrap();
+        match &ret {
+            None       => write!(self.f, "void ").unwrap(),
+            Some(a)    => {
+                write!(self.f, "{} ", self.to_local_typed_name(&a.typed)).unwrap();
+                self.emit_pointer(&a.typed.ptr);
+            }
+        };
+        write!(self.f, " __attribute__ ((unused)) {}_checked (", cname).unwrap();
+        self.function_args(args);
+        write!(self.f, ") {{\n").unwrap();
+
+        for expr in callassert {
+            self.emit_runtime_check(&cname, "precondition", expr, None);
+        }
+
+        if let Some(a) = &ret {
+            write!(self.f, "    {} ", self.to_local_typed_name(&a.typed)).unwrap();
+            self.emit_pointer(&a.typed.ptr);
+            write!(self.f, " zz_return = ").unwrap();
+        } else {
+            write!(self.f, "    ").unwrap();
+        }
+        write!(self.f, "{}(", cname).unwrap();
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                write!(self.f, ", ").unwrap();
+            }
+            write!(self.f, "{}", arg.name).unwrap();
+        }
+        write!(self.f, ");\n").unwrap();
+
+        for expr in calleffect {
+            self.emit_runtime_check(&cname, "postcondition", expr, Some("zz_return"));
+        }
+
+        if ret.is_some() {
+            write!(self.f, "    return zz_return;\n").unwrap();
+        }
+        write!(self.f, "}}\n").unwrap();
+    }
+
+    fn emit_runtime_check(&mut self, fname: &str, kind: &str, expr: &ast::Expression, ret: Option<&str>) {
+        let check = match runtime_check(expr, ret) {
+            Some(v) => v,
+            None => {
+                write!(self.f, "    /* not checked at runtime: {} */\n", format!("{}", expr).replace("*/", "* /")).unwrap();
+                return;
+            }
+        };
+        write!(self.f, "    if (!(").unwrap();
+        self.emit_expr(&check);
+        write!(self.f, ")) ZZ_CONTRACT_VIOLATION(\"{}\", \"{}\", \"", fname, kind).unwrap();
+        for c in format!("{}", expr).bytes() {
+            self.write_escaped_literal(c, true);
+        }
+        write!(self.f, "\");\n").unwrap();
+    }
 
     pub fn emit_include(&mut self, ast: &ast::Local) {
 
@@ -350,7 +623,7 @@ impl Emitter {
 
         // TODO this is wrong. flatten should run a separate dependency tree for the header than
         // for the c file
-        if *inline && self.header {
+        if *inline && self.header && !self.amalgamated {
             return;
         }
 
@@ -358,11 +631,11 @@ impl Emitter {
         if *inline {
             let mut f = match fs::File::open(&expr) {
                 Err(e) => {
-                    parser::emit_error(
+                    self.fail(parser::error(
                         format!("cannot inline {:?}", expr),
                         &[(loc.clone(), format!("{}", e))]
-                        );
-                    std::process::exit(9);
+                        ));
+                    return;
 
                 },
                 Ok(f) => f,
@@ -510,9 +783,116 @@ impl Emitter {
         let dir = format!("{}/{}", self.casedir, testname);
         std::fs::remove_dir_all(&dir).ok();
         std::fs::create_dir_all(&dir).unwrap();
-        for (fname, expr) in fields {
+        for (fname, op, expr) in fields {
+            // outputs compare with == != < (contains) > (regex), exit and signal with any comparison
+            let ops_ok = match fname.as_str() {
+                "stdin" | "args" | "env"    => op == &ast::InfixOperator::Equals,
+                "stdout" | "stderr"         => matches!(op, ast::InfixOperator::Equals | ast::InfixOperator::Nequals
+                                                        | ast::InfixOperator::Lessthan | ast::InfixOperator::Morethan),
+                "exit" | "signal"           => true,
+                _ => {
+                    self.fail(parser::error(
+                        format!("unknown testcase field {}", fname),
+                        &[(expr.loc().clone(), "expected one of stdin, stdout, stderr, exit, signal, args, env".to_string())]
+                        ));
+                    return;
+                }
+            };
+            if !ops_ok {
+                self.fail(parser::error(
+                    format!("testcase field {} cannot be compared with {}", fname, op),
+                    &[(expr.loc().clone(), "invalid comparison for this field".to_string())]
+                    ));
+                return;
+            }
+            if op != &ast::InfixOperator::Equals {
+                let p = format!("{}/{}.op", dir, fname);
+                std::fs::write(&p, op.to_string()).expect(&format!("cannot create {}", p));
+            }
+
             let p = format!("{}/{}", dir, fname);
             let mut f = fs::File::create(&p).expect(&format!("cannot create {}", p));
+
+            // remember where the expected output came from, so zz test --bless can update it
+            let source = match expr {
+                ast::Expression::LiteralString{loc, ..} => Some(testrunner::Source::Inline{
+                    file:   loc.file.clone(),
+                    start:  loc.span.start(),
+                    end:    loc.span.end(),
+                }),
+                ast::Expression::Call{name, args, loc, ..} => {
+                    let path = match (name.as_ref(), args.as_slice()) {
+                        (ast::Expression::Name(ast::Typed{t: ast::Type::Other(n), ..}), [arg])
+                            if n.0.len() == 1 && n.0[0] == "file" => match arg.as_ref()
+                        {
+                            ast::Expression::LiteralString{v, ..} => String::from_utf8_lossy(v).to_string(),
+                            _ => String::new(),
+                        },
+                        _ => String::new(),
+                    };
+                    if path.is_empty() || !matches!(fname.as_str(), "stdin" | "stdout" | "stderr") {
+                        self.fail(parser::error(
+                            format!("testcase field {} cannot be a call", fname),
+                            &[(loc.clone(), "only stdin, stdout and stderr can be read from a file(\"path\")".to_string())]
+                            ));
+                        return;
+                    }
+                    // relative to the file declaring the test
+                    let path = std::path::Path::new(&loc.file).parent().unwrap_or(std::path::Path::new("")).join(path);
+                    match std::fs::read(&path) {
+                        Ok(v) => f.write_all(&v).unwrap(),
+                        Err(e) => parser::emit_warn(
+                            format!("cannot read snapshot {:?}: {}", path, e),
+                            &[(loc.clone(), "will be treated as empty until created with zz test --bless".to_string())]
+                            ),
+                    }
+                    Some(testrunner::Source::Snapshot{path: path.to_string_lossy().to_string()})
+                }
+                _ => None,
+            };
+            if let Some(source) = source {
+                if fname == "stdout" || fname == "stderr" {
+                    let p = format!("{}/{}.src", dir, fname);
+                    let sf = fs::File::create(&p).expect(&format!("cannot create {}", p));
+                    serde_json::to_writer(sf, &source).expect(&format!("cannot write {}", p));
+                }
+                if let ast::Expression::Call{..} = expr {
+                    continue;
+                }
+            }
+
+            // a list of strings, each terminated by \0.
+            // a single string is split on whitespace
+            if fname == "args" || fname == "env" {
+                let words : Vec<&[u8]> = match expr {
+                    ast::Expression::LiteralString{v,..} => {
+                        v.split(|c|c.is_ascii_whitespace()).filter(|w|!w.is_empty()).collect()
+                    }
+                    ast::Expression::ArrayInit{fields, ..} => fields.iter().map(|field| match field.as_ref() {
+                        ast::Expression::LiteralString{v,..} => v.as_slice(),
+                        _ => {
+                            self.fail(parser::error(
+                                format!("testcase field {} must be a string or an array of strings", fname),
+                                &[(field.loc().clone(), format!("this expression cannot be emitted as testcase file"))]
+                                ));
+                            &[]
+                        }
+                    }).collect(),
+                    _ => {
+                        self.fail(parser::error(
+                            format!("testcase field {} must be a string or an array of strings", fname),
+                            &[(expr.loc().clone(), format!("this expression cannot be emitted as testcase file"))]
+                            ));
+                        return;
+                    }
+                };
+                for word in words {
+                    f.write_all(word).unwrap();
+                    f.write_all(&[0]).unwrap();
+                }
+                continue;
+            }
+
             match expr {
                 ast::Expression::LiteralString{v,..} => {
                     f.write_all(v).unwrap();
@@ -529,20 +909,20 @@ impl Emitter {
                                         f.write(&[v as u8]).unwrap();
                                     }
                                     _ => {
-                                        parser::emit_error(
+                                        self.fail(parser::error(
                                             "testcase field must be literal string or byte array",
                                             &[(loc.clone(), format!("this expression cannot be emitted as testcase file"))]
-                                            );
-                                        std::process::exit(9);
+                                            ));
+                                        return;
                                     }
                                 }
                             },
                             _ => {
-                                parser::emit_error(
+                                self.fail(parser::error(
                                     "testcase field must be literal string or byte array, not",
                                     &[(field.loc().clone(), format!("this expression cannot be emitted as testcase file"))]
-                                    );
-                                std::process::exit(9);
+                                    ));
+                                return;
                             }
                         }
                     }
@@ -551,11 +931,11 @@ impl Emitter {
                     f.write_all(v.as_bytes()).unwrap();
                 }
                 _ => {
-                    parser::emit_error(
+                    self.fail(parser::error(
                         "testcase field must be literal string or byte array",
                         &[(expr.loc().clone(), format!("this expression cannot be emitted as testcase file"))]
-                        );
-                    std::process::exit(9);
+                        ));
+                    return;
                 }
             }
         }
@@ -623,11 +1003,11 @@ impl Emitter {
                     write!(self.f, "]").unwrap();
                 } else {
                     if i != (fields.len() - 1) {
-                        parser::emit_error(
+                        self.fail(parser::error(
                             "tail field has no be the last field in a struct",
                             &[(field.loc.clone(), format!("tail field would displace next field"))]
-                            );
-                        std::process::exit(9);
+                            ));
+                        return;
                     }
                     if let Some(tt) = tail_variant {
                         emitted_tail = true;
@@ -646,7 +1026,7 @@ impl Emitter {
         }
         if let Some(tt) = tail_variant {
             if !emitted_tail {
-                write!(self.f, "   uint8_t _____tail [{}];\n", tt).unwrap();
+                write!(self.f, "   uint8_t {} [{}];\n", TAIL_PADDING, tt).unwrap();
             }
         }
         write!(self.f, "}}\n").unwrap();
@@ -709,11 +1089,11 @@ impl Emitter {
         for (attr, loc) in attr {
             match attr.as_str() {
                 o => {
-                    parser::emit_error(
+                    self.fail(parser::error(
                         "ICE: unsupported attr",
                         &[(loc.clone(), format!("'{}' is not a valid c attribute", o))]
-                        );
-                    std::process::exit(9);
+                        ));
+                    return;
 
                 }
             }
@@ -752,6 +1132,9 @@ impl Emitter {
         self.emit_loc(&ast.loc);
 
         match &ast.vis {
+            _ if self.amalgamated && self.header => {
+                write!(self.f, "static inline ").unwrap();
+            },
             ast::Visibility::Object  => {
                 write!(self.f, "static ").unwrap();
             },
@@ -770,6 +1153,7 @@ impl Emitter {
         };
 
         match &ast.vis {
+            _ if self.amalgamated && self.header => (),
             ast::Visibility::Object => (),
             ast::Visibility::Shared => write!(self.f, "__attribute__ ((visibility (\"hidden\"))) ").unwrap(),
             ast::Visibility::Export => write!(self.f, "__attribute__ ((visibility (\"default\"))) ").unwrap(),
@@ -783,15 +1167,16 @@ impl Emitter {
                     name = Name::from(name.0.last().unwrap());
                     name.0.insert(0, String::new());
                 },
+                "inline" if self.amalgamated && self.header => (),
                 "inline" => {
                     write!(self.f, " static inline ").unwrap();
                 },
                 o => {
-                    parser::emit_error(
+                    self.fail(parser::error(
                         "ICE: unsupported attr",
                         &[(loc.clone(), format!("'{}' is not a valid c attribute", o))]
-                        );
-                    std::process::exit(9);
+                        ));
+                    return;
 
                 }
             }
@@ -869,7 +1254,9 @@ impl Emitter {
 
         self.emit_loc(&ast.loc);
 
-        if !ast.name.ends_with("::main") {
+        if self.amalgamated && self.header {
+            write!(self.f, "static inline ").unwrap();
+        } else if !ast.name.ends_with("::main") {
             match &ast.vis {
                 ast::Visibility::Object  => {
                     write!(self.f, "static ").unwrap();
@@ -886,15 +1273,16 @@ impl Emitter {
                     name = Name::from(name.0.last().unwrap());
                     name.0.insert(0, String::new());
                 },
+                "inline" if self.amalgamated && self.header => (),
                 "inline" => {
                     write!(self.f, " static inline ").unwrap();
                 },
                 o => {
-                    parser::emit_error(
+                    self.fail(parser::error(
                         "ICE: unsupported attr",
                         &[(loc.clone(), format!("'{}' is not a valid c attribute", o))]
-                        );
-                    std::process::exit(9);
+                        ));
+                    return;
 
                 }
             }
@@ -910,10 +1298,13 @@ impl Emitter {
 
 
 
-        if ast.name.ends_with("::main") {
+        if ast.name.ends_with("::main") && self.fuzz_main {
+            write!(self.f, "zz_fuzz_main (").unwrap();
+        } else if ast.name.ends_with("::main") {
             write!(self.f, "main (").unwrap();
         } else  {
             match &ast.vis {
+                _ if self.amalgamated && self.header => (),
                 ast::Visibility::Object => (),
                 ast::Visibility::Shared => write!(self.f, "__attribute__ ((visibility (\"hidden\"))) ").unwrap(),
                 ast::Visibility::Export => write!(self.f, "__attribute__ ((visibility (\"default\"))) ").unwrap(),
@@ -928,8 +1319,87 @@ impl Emitter {
             write!(self.f, ", ...").unwrap();
         }
         write!(self.f, ")\n").unwrap();
-        self.emit_zblock(&body, true);
+        if self.contracts && !self.inside_macro {
+            let cname = if ast.name.ends_with("::main") { "main".to_string() } else { name.0[1..].join("_") };
+            self.emit_contract_body(&cname, ast, body);
+        } else {
+            self.emit_zblock(&body, true);
+        }
         write!(self.f, "\n").unwrap();
+        if ast.name.ends_with("::main") && self.fuzz_main {
+            self.emit_fuzz_harness(args.len());
+        }
+    }
+
+    // libfuzzer brings its own main and hands over inputs as buffers,
+    // so the test main is renamed and gets each input as its stdin
+    fn emit_fuzz_harness(&mut self, nargs: usize) {
+        write!(self.f, r#"
+#include <stdio.h>
+#include <stdlib.h>
+#include <unistd.h>
+int LLVMFuzzerTestOneInput(uint8_t const *data, size_t size) {{
+    FILE *in = tmpfile();
+    if (in == 0 || fwrite(data, 1, size, in) != size || fflush(in) != 0) {{
+        abort();
+    }}
+    dup2(fileno(in), 0);
+    fclose(in);
+    lseek(0, 0, SEEK_SET);
+    clearerr(stdin);
+    fseek(stdin, 0, SEEK_SET);
+"#).unwrap();
+        if nargs == 0 {
+            write!(self.f, "    zz_fuzz_main();
+").unwrap();
+        } else {
+            write!(self.f, "    char *argv[] = {{\"fuzz\", 0}};
+").unwrap();
+            write!(self.f, "    zz_fuzz_main(1, (void*)argv);
+").unwrap();
+        }
+        write!(self.f, "    return 0;
+}}
+").unwrap();
+    }
+
+    // checks every where clause on entry and every model clause on return,
+    // so callers the prover never saw still get caught
+    fn emit_contract_body(&mut self, cname: &str, ast: &ast::Local, body: &ast::Block) {
+        let (ret, callassert, calleffect) = match &ast.def {
+            ast::Def::Function{ret, callassert, calleffect, ..} => (ret, callassert, calleffect),
+            _ => unreachable!(),
+        };
+
+        write!(self.f, "{{\n").unwrap();
+        for expr in callassert {
+            self.emit_runtime_check(cname, "precondition", expr, None);
+        }
+
+        self.bounds.clear();
+        for expr in callassert {
+            if let Some((arg, len)) = len_fact(expr) {
+                write!(self.f, "    size_t const zz_len_{} = (size_t)(", arg).unwrap();
+                self.emit_expr(&len);
+                write!(self.f, ");\n").unwrap();
+                self.bounds.insert(arg.clone(), format!("zz_len_{}", arg));
+            }
+        }
+
+        self.cur_fn = Some(ContractFn{
+            name:       cname.to_string(),
+            ret:        ret.as_ref().map(|r|r.typed.clone()),
+            calleffect: calleffect.clone(),
+        });
+        self.emit_zblock(body, false);
+        if ret.is_none() {
+            for expr in calleffect {
+                self.emit_runtime_check(cname, "postcondition", expr, None);
+            }
+        }
+        self.cur_fn = None;
+        self.bounds.clear();
+        write!(self.f, "}}\n").unwrap();
     }
 
     fn emit_statement(&mut self, stm: &ast::Statement) -> bool /* ends with semicolon */ {
@@ -1061,7 +1531,11 @@ impl Emitter {
                 }
 
                 write!(self.f, " {} ", name).unwrap();
+                self.bounds.remove(name);
                 if let Some(array) = &array {
+                    if self.cur_fn.is_some() {
+                        self.bounds.insert(name.clone(), format!("(sizeof({0})/sizeof({0}[0]))", name));
+                    }
                     write!(self.f, " [ ").unwrap();
                     if let Some(array) = &array {
                         self.emit_expr(array);
@@ -1086,6 +1560,37 @@ impl Emitter {
                 write!(self.f, "continue").unwrap();
                 true
             }
+            ast::Statement::Return{expr, loc} if self.cur_fn.is_some() => {
+                self.emit_loc(&loc);
+                let cur = self.cur_fn.take().unwrap();
+                write!(self.f, "  {{").unwrap();
+                match (&cur.ret, expr) {
+                    (Some(typed), Some(expr)) => {
+                        write!(self.f, "{} ", self.to_local_typed_name(typed)).unwrap();
+                        self.emit_pointer(&typed.ptr);
+                        write!(self.f, " zz_return = ").unwrap();
+                        self.emit_expr(expr);
+                        write!(self.f, ";\n").unwrap();
+                        for check in &cur.calleffect {
+                            self.emit_runtime_check(&cur.name, "postcondition", check, Some("zz_return"));
+                        }
+                        write!(self.f, "    return zz_return; }}").unwrap();
+                    }
+                    (_, expr) => {
+                        write!(self.f, "\n").unwrap();
+                        for check in &cur.calleffect {
+                            self.emit_runtime_check(&cur.name, "postcondition", check, None);
+                        }
+                        write!(self.f, "    return ").unwrap();
+                        if let Some(expr) = expr {
+                            self.emit_expr(expr);
+                        }
+                        write!(self.f, "; }}").unwrap();
+                    }
+                }
+                self.cur_fn = Some(cur);
+                false
+            }
             ast::Statement::Return{expr, loc}  => {
                 self.emit_loc(&loc);
                 write!(self.f, "  return ").unwrap();
@@ -1239,10 +1744,10 @@ impl Emitter {
                     ast::EmitBehaviour::Default     => {},
                     ast::EmitBehaviour::Skip        => {return;},
                     ast::EmitBehaviour::Error{loc, message}   => {
-                        emit_error(format!("{}", message), &[
+                        self.fail(error(format!("{}", message), &[
                             (loc.clone(), "here")
-                        ]);
-                        std::process::exit(9);
+                        ]));
+                        return;
                     }
                 };
 
@@ -1297,7 +1802,19 @@ impl Emitter {
                 self.emit_loc(&loc);
                 self.emit_expr(lhs);
                 write!(self.f, " [ ").unwrap();
-                self.emit_expr(rhs);
+                let bound = match lhs.as_ref() {
+                    ast::Expression::Name(ast::Typed{t: ast::Type::Other(n), ..}) if n.0.len() == 1 => {
+                        self.bounds.get(&n.0[0]).cloned()
+                    }
+                    _ => None,
+                };
+                if let Some(bound) = bound {
+                    write!(self.f, "zz_checked_index(").unwrap();
+                    self.emit_expr(rhs);
+                    write!(self.f, ", {}, \"{}\", __FILE__, __LINE__)", bound, lhs).unwrap();
+                } else {
+                    self.emit_expr(rhs);
+                }
                 write!(self.f, "]").unwrap();
             }
         }
@@ -1339,6 +1856,129 @@ impl Emitter {
     }
 }
 
+/// translate a contract into a plain C condition.
+/// returns None if the expression uses theories that have no runtime representation.
+/// "return" is replaced with the name given as ret, or is not checkable if there is none.
+pub fn runtime_check(expr: &ast::Expression, ret: Option<&str>) -> Option<ast::Expression> {
+    match expr {
+        ast::Expression::Name(typed) => {
+            if let ast::Type::Other(name) = &typed.t {
+                if name.0.len() == 1 && name.0[0] == "return" {
+                    let mut typed = typed.clone();
+                    typed.t = ast::Type::Other(Name::from(ret?));
+                    return Some(ast::Expression::Name(typed));
+                }
+            }
+            Some(expr.clone())
+        },
+        ast::Expression::Literal{..} | ast::Expression::LiteralChar{..} => Some(expr.clone()),
+        ast::Expression::Call{loc, name, args, ..} => {
+            let fname = match name.as_ref() {
+                ast::Expression::Name(ast::Typed{t: ast::Type::Other(n), ..}) => n,
+                _ => return None,
+            };
+            // safe() of a pointer is the only theory with a runtime equivalent
+            if fname.0.len() == 1 && fname.0[0] == "safe" && args.len() == 1 {
+                return Some(ast::Expression::Infix{
+                    loc:    loc.clone(),
+                    lhs:    Box::new(runtime_check(&args[0], ret)?),
+                    rhs:    Box::new(ast::Expression::Literal{loc: loc.clone(), v: "0".to_string()}),
+                    op:     ast::InfixOperator::Nequals,
+                });
+            }
+            None
+        },
+        ast::Expression::Infix{loc, lhs, rhs, op} => {
+            Some(ast::Expression::Infix{
+                loc:    loc.clone(),
+                lhs:    Box::new(runtime_check(lhs, ret)?),
+                rhs:    Box::new(runtime_check(rhs, ret)?),
+                op:     op.clone(),
+            })
+        },
+        ast::Expression::UnaryPre{loc, op, expr} => {
+            match op {
+                ast::PrefixOperator::Increment | ast::PrefixOperator::Decrement => None,
+                _ => Some(ast::Expression::UnaryPre{
+                    loc:    loc.clone(),
+                    op:     op.clone(),
+                    expr:   Box::new(runtime_check(expr, ret)?),
+                }),
+            }
+        },
+        ast::Expression::MemberAccess{loc, lhs, op, rhs} => {
+            Some(ast::Expression::MemberAccess{
+                loc:    loc.clone(),
+                lhs:    Box::new(runtime_check(lhs, ret)?),
+                op:     op.clone(),
+                rhs:    rhs.clone(),
+            })
+        },
+        ast::Expression::ArrayAccess{loc, lhs, rhs} => {
+            Some(ast::Expression::ArrayAccess{
+                loc:    loc.clone(),
+                lhs:    Box::new(runtime_check(lhs, ret)?),
+                rhs:    Box::new(runtime_check(rhs, ret)?),
+            })
+        },
+        ast::Expression::Cast{loc, into, expr} => {
+            Some(ast::Expression::Cast{
+                loc:    loc.clone(),
+                into:   into.clone(),
+                expr:   Box::new(runtime_check(expr, ret)?),
+            })
+        },
+        ast::Expression::UnaryPost{..}
+        | ast::Expression::LiteralString{..}
+        | ast::Expression::StructInit{..}
+        | ast::Expression::ArrayInit{..} => None,
+    }
+}
+
+/// the buffer and its length from a where clause like len(x) >= n.
+/// greater than means one more element is valid than stated
+fn len_fact(expr: &ast::Expression) -> Option<(String, ast::Expression)> {
+    let (loc, lhs, rhs, op) = match expr {
+        ast::Expression::Infix{loc, lhs, rhs, op} => (loc, lhs, rhs, op),
+        _ => return None,
+    };
+    let (arg, len, op) = match (len_of(lhs), len_of(rhs)) {
+        (Some(arg), None) => (arg, rhs, op.clone()),
+        (None, Some(arg)) => (arg, lhs, match op {
+            ast::InfixOperator::Lessthan    => ast::InfixOperator::Morethan,
+            ast::InfixOperator::Lesseq      => ast::InfixOperator::Moreeq,
+            o => o.clone(),
+        }),
+        _ => return None,
+    };
+    let len = runtime_check(len, None)?;
+    match op {
+        ast::InfixOperator::Equals | ast::InfixOperator::Moreeq => Some((arg, len)),
+        ast::InfixOperator::Morethan => Some((arg, ast::Expression::Infix{
+            loc:    loc.clone(),
+            lhs:    Box::new(len),
+            rhs:    Box::new(ast::Expression::Literal{loc: loc.clone(), v: "1".to_string()}),
+            op:     ast::InfixOperator::Add,
+        })),
+        _ => None,
+    }
+}
+
+fn len_of(expr: &ast::Expression) -> Option<String> {
+    if let ast::Expression::Call{name, args, ..} = expr {
+        if let ast::Expression::Name(ast::Typed{t: ast::Type::Other(n), ..}) = name.as_ref() {
+            if n.0.len() == 1 && n.0[0] == "len" && args.len() == 1 {
+                if let ast::Expression::Name(ast::Typed{t: ast::Type::Other(a), ..}) = args[0].as_ref() {
+                    if a.0.len() == 1 {
+                        return Some(a.0[0].clone());
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
 impl CFile {
     pub fn is_newer_than(&self, target: &str) -> bool {
         let itarget = match std::fs::metadata(&target) {
@@ -1348,7 +1988,11 @@ impl CFile {
         let itarget = itarget.modified().expect(&format!("cannot stat {}", target));
 
         for source in &self.sources {
-            let isource = std::fs::metadata(source).expect(&format!("cannot stat {:?}", source));
+            // deleted since, so whatever used it needs to be looked at again
+            let isource = match std::fs::metadata(source) {
+                Ok(v)  => v,
+                Err(_) => return true,
+            };
 
             let isource = isource.modified().expect(&format!("cannot stat {:?}", source));
 
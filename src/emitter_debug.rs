@@ -0,0 +1,234 @@
+use super::project::target_dir;
+use super::flatten;
+use super::ast;
+use super::make;
+use super::name::Name;
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, BTreeMap};
+use askama::Template;
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Symbols {
+    // c name -> zz name
+    pub symbols:    BTreeMap<String, String>,
+    pub structs:    Vec<Struct>,
+    pub enums:      Vec<Enum>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Struct {
+    pub c_name:     String,
+    pub zz_name:    String,
+    pub fields:     Vec<Field>,
+    // the tail field and its length, if this is a tail variant
+    pub tail:       Option<String>,
+    pub tail_len:   Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Field {
+    pub name:       String,
+    pub typed:      String,
+    // char or u8 arrays print as string
+    pub string:     bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Enum {
+    pub c_name:     String,
+    pub zz_name:    String,
+    pub variants:   Vec<(String, i64)>,
+}
+
+pub struct Emitter{
+    p:              String,
+    module:         flatten::Module,
+}
+
+pub fn outname(stage: &make::Stage, module: &Name) -> String {
+    format!("{}/debug/{}.json", stage.modules().display(), module.0[1..].join("_"))
+}
+
+impl Emitter {
+    pub fn new(stage: make::Stage , module: flatten::Module) -> Self {
+        std::fs::create_dir_all(format!("{}/debug/", stage.modules().display())).unwrap();
+        let p = outname(&stage, &module.name);
+        Emitter{
+            p,
+            module,
+        }
+    }
+
+    pub fn emit(self) {
+        let mut sym = Symbols::default();
+
+        for (d, complete) in &self.module.d {
+            if complete != &flatten::TypeComplete::Complete {
+                continue;
+            }
+            let name = Name::from(&d.name);
+            let mut mname = name.clone();
+            mname.pop();
+            if mname != self.module.name {
+                continue;
+            }
+            let c_name = name.0[1..].join("_");
+
+            match &d.def {
+                ast::Def::Function{attr, ..} => {
+                    if attr.contains_key("extern") || d.name.ends_with("::main") {
+                        continue;
+                    }
+                    sym.symbols.insert(c_name, name.human_name());
+                }
+                ast::Def::Static{..} => {
+                    sym.symbols.insert(c_name, name.human_name());
+                }
+                ast::Def::Enum{names} => {
+                    let mut variants = Vec::new();
+                    let mut value = 0;
+                    for (vname, literal) in names {
+                        if let Some(literal) = literal {
+                            value = *literal as i64;
+                        }
+                        variants.push((vname.clone(), value));
+                        sym.symbols.insert(format!("{}_{}", c_name, vname), format!("{}::{}", name.human_name(), vname));
+                        value += 1;
+                    }
+                    sym.symbols.insert(c_name.clone(), name.human_name());
+                    sym.enums.push(Enum{
+                        c_name,
+                        zz_name: name.human_name(),
+                        variants,
+                    });
+                }
+                ast::Def::Struct{fields, ..} => {
+                    sym.symbols.insert(c_name.clone(), name.human_name());
+                    sym.structs.push(self.record(&c_name, &name.human_name(), fields, None));
+                    if let Some(vs) = self.module.typevariants.get(&name) {
+                        for v in vs {
+                            let vc = format!("{}_{}", c_name, v);
+                            let vz = format!("{}+{}", name.human_name(), v);
+                            sym.symbols.insert(vc.clone(), vz.clone());
+                            sym.structs.push(self.record(&vc, &vz, fields, Some(*v)));
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let f = std::fs::File::create(&self.p).expect(&format!("cannot create {}", self.p));
+        serde_json::ser::to_writer(f, &sym).expect(&format!("cannot write {}", self.p));
+    }
+
+    fn record(&self, c_name: &str, zz_name: &str, fields: &[ast::Field], tail_variant: Option<u64>) -> Struct {
+        let mut tail = None;
+        let fields = fields.iter().map(|field| {
+            // same rule as the c emitter: an array without size is the tail
+            if let Some(None) = &field.array {
+                tail = Some(field.name.clone());
+            }
+            let string = field.typed.ptr.is_empty() && field.array.is_some() && match &field.typed.t {
+                ast::Type::U8 | ast::Type::I8 => true,
+                ast::Type::Other(n) => n.0.last().map(|s|s.as_str()) == Some("char"),
+                _ => false,
+            };
+            Field {
+                name:   field.name.clone(),
+                typed:  format!("{}", field.typed),
+                string,
+            }
+        }).collect();
+
+        if tail.is_none() && tail_variant.is_some() {
+            tail = Some(super::emitter::TAIL_PADDING.to_string());
+        }
+
+        Struct {
+            c_name:     c_name.to_string(),
+            zz_name:    zz_name.to_string(),
+            fields,
+            tail,
+            tail_len:   tail_variant,
+        }
+    }
+}
+
+/// merge the symbols of all modules of a build into target/<stage>/symbols.json,
+/// and with debug info, the gdb and lldb pretty printer scripts.
+/// files are only written when they change
+pub fn link(stage: &make::Stage, modules: &[&Name]) {
+    let mut all = Symbols::default();
+    for name in modules {
+        let p = outname(stage, name);
+        let s = match std::fs::read_to_string(&p) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        let s : Symbols = match serde_json::from_str(&s) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        all.symbols.extend(s.symbols);
+        all.structs.extend(s.structs);
+        all.enums.extend(s.enums);
+    }
+
+    let json = serde_json::to_string(&all).unwrap();
+    let dir = stage.target.join(stage.to_string());
+    super::write_if_changed(&dir.join("symbols.json"), json.as_bytes())
+        .expect(&format!("cannot write target/{}/symbols.json", stage));
+
+    // without debug info, there is nothing for a debugger to print
+    if !stage.debug {
+        return;
+    }
+
+    // the json ends up in a python raw string
+    let data = json.replace("\"\"\"", "\\u0022\\u0022\\u0022");
+    let gdb = GdbPy{data: data.clone()}.render().unwrap();
+    super::write_if_changed(&dir.join("zz-gdb.py"), gdb.as_bytes())
+        .expect(&format!("cannot write target/{}/zz-gdb.py", stage));
+    let lldb = LldbPy{data}.render().unwrap();
+    super::write_if_changed(&dir.join("zz-lldb.py"), lldb.as_bytes())
+        .expect(&format!("cannot write target/{}/zz-lldb.py", stage));
+}
+
+/// read the symbol map of a previous build
+pub fn symbols(root: &std::path::Path, stage: &make::Stage) -> HashMap<String, String> {
+    let p = target_dir(root).join(stage.to_string()).join("symbols.json");
+    match std::fs::read_to_string(&p).ok().and_then(|s|serde_json::from_str::<Symbols>(&s).ok()) {
+        Some(s) => s.symbols.into_iter().collect(),
+        None => HashMap::new(),
+    }
+}
+
+/// replace every identifier that is a known c name with its zz name
+pub fn symbolize(line: &str, symbols: &HashMap<String, String>) -> String {
+    let mut r = String::with_capacity(line.len());
+    let mut word = String::new();
+    for c in line.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            word.push(c);
+            continue;
+        }
+        r.push_str(symbols.get(&word).unwrap_or(&word));
+        word.clear();
+        r.push(c);
+    }
+    r.push_str(symbols.get(&word).unwrap_or(&word));
+    r
+}
+
+#[derive(Template)]
+#[template(path = "zz-gdb.py", escape = "none")]
+struct GdbPy {
+    data:   String,
+}
+
+#[derive(Template)]
+#[template(path = "zz-lldb.py", escape = "none")]
+struct LldbPy {
+    data:   String,
+}
@@ -1,4 +1,4 @@
-use super::project::{Project};
+use super::project::Project;
 use std::fs;
 use super::flatten;
 use super::ast;
@@ -7,7 +7,9 @@ use std::io::{Write};
 use std::collections::HashSet;
 use std::path::PathBuf;
 use super::name::Name;
-use super::parser::{self, emit_error};
+use super::Error;
+use std::cell::RefCell;
+use super::parser::{self, error};
 
 
 pub struct CFile {
@@ -25,12 +27,13 @@ pub struct Emitter{
     cur_loc:        Option<ast::Location>,
     register_structs:   Vec<String>,
     register_fns:   Vec<(String, String)>,
+    error:          RefCell<Option<Error>>,
 }
 
 
 pub fn make_npm_module(make: &super::make::Make) {
 
-    let pdir_ = format!("target/{}/npm/{}/", make.stage, make.artifact.name);
+    let pdir_ = format!("{}/{}/npm/{}/", make.stage.target.display(), make.stage, make.artifact.name);
     let pdir = std::path::Path::new(&pdir_);
     std::fs::create_dir_all(&pdir).unwrap();
 
@@ -39,7 +42,7 @@ pub fn make_npm_module(make: &super::make::Make) {
         .join("target")
         .join(&format!("{}", make.stage));
 
-    copyr(format!("target/{}/", make.stage), &nutarget, Some("npm"));
+    copyr(format!("{}/{}/", make.stage.target.display(), make.stage), &nutarget, Some("npm"));
 
     let p = pdir.join("package.json");
     let mut f = fs::File::create(&p).expect(&format!("cannot create {:?}", p));
@@ -169,13 +172,13 @@ write!(f, r#"
 }
 
 pub fn outname(_project: &Project, stage: &make::Stage, module: &flatten::Module) -> String {
-    format!("target/{}/js/{}.c", stage, module.name.0[1..].join("_"))
+    format!("{}/js/{}.c", stage.modules().display(), module.name.0[1..].join("_"))
 }
 
 impl Emitter {
     pub fn new(project: &Project, stage: make::Stage , module: flatten::Module) -> Self {
 
-        std::fs::create_dir_all(format!("target/{}/js/", stage)).unwrap();
+        std::fs::create_dir_all(format!("{}/js/", stage.modules().display())).unwrap();
         let p = outname(project, &stage, &module);
         let f = fs::File::create(&p).expect(&format!("cannot create {}", p));
 
@@ -187,10 +190,15 @@ impl Emitter {
             cur_loc: None,
             register_structs:   Vec::new(),
             register_fns:       Vec::new(),
-
+            error:              RefCell::new(None),
         }
     }
 
+    // emitting goes on after an error, emit returns the first one
+    fn fail(&self, e: Error) {
+        self.error.borrow_mut().get_or_insert(e);
+    }
+
 
     fn emit_loc(&mut self, loc: &ast::Location) {
         if let Some(cur_loc) = &self.cur_loc {
@@ -232,11 +240,11 @@ impl Emitter {
                 s
             }
             ast::Type::ILiteral | ast::Type::ULiteral | ast::Type::Elided | ast::Type::New => {
-                parser::emit_error(
+                self.fail(parser::error(
                     "ICE: untyped literal ended up in emitter",
                     &[(name.loc.clone(), format!("this should have been resolved earlier"))]
-                    );
-                std::process::exit(9);
+                    ));
+                String::new()
             }
         }
     }
@@ -279,11 +287,10 @@ impl Emitter {
                 //TODO
             }
             ast::Type::ILiteral | ast::Type::ULiteral | ast::Type::Elided | ast::Type::New => {
-                parser::emit_error(
+                self.fail(parser::error(
                     "ICE: untyped literal ended up in emitter",
                     &[(typed.loc.clone(), format!("this should have been resolved earlier"))]
-                    );
-                std::process::exit(9);
+                    ));
             }
         }
     }
@@ -342,11 +349,10 @@ impl Emitter {
                 //TODO
             }
             ast::Type::ILiteral | ast::Type::ULiteral | ast::Type::Elided | ast::Type::New => {
-                parser::emit_error(
+                self.fail(parser::error(
                     "ICE: untyped literal ended up in emitter",
                     &[(typed.loc.clone(), format!("this should have been resolved earlier"))]
-                    );
-                std::process::exit(9);
+                    ));
             }
         }
     }
@@ -371,7 +377,7 @@ impl Emitter {
         return s.0.join("_");
     }
 
-    pub fn emit(mut self) {
+    pub fn emit(mut self) -> Result<(), Error> {
         let module = self.module.clone();
         debug!("emitting js {}", module.name);
 
@@ -461,6 +467,10 @@ self.module.name.0[1..].join("_")).unwrap();
         }
         write!(self.f, "    return exports;\n").unwrap();
         write!(self.f, "}}\n").unwrap();
+        match self.error.into_inner() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
     }
 
     pub fn emit_static(&mut self, ast: &ast::Local) {
@@ -861,10 +871,10 @@ napi_value js_{}(napi_env env, napi_callback_info info) {{
                     ast::EmitBehaviour::Default     => {},
                     ast::EmitBehaviour::Skip        => {return;},
                     ast::EmitBehaviour::Error{loc, message}   => {
-                        emit_error(format!("{}", message), &[
+                        self.fail(error(format!("{}", message), &[
                             (loc.clone(), "here")
-                        ]);
-                        std::process::exit(9);
+                        ]));
+                        return;
                     }
                 };
 
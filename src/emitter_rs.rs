@@ -1,4 +1,4 @@
-use super::project::{Project};
+use super::project::Project;
 use std::fs;
 use super::flatten;
 use super::ast;
@@ -7,7 +7,9 @@ use std::io::{Write};
 use std::collections::HashSet;
 use std::path::PathBuf;
 use super::name::Name;
-use super::parser::{self, emit_error};
+use super::Error;
+use std::cell::RefCell;
+use super::parser::{self, error};
 
 
 pub struct CFile {
@@ -23,16 +25,17 @@ pub struct Emitter{
     module:         flatten::Module,
     inside_macro:   bool,
     cur_loc:        Option<ast::Location>,
+    error:          RefCell<Option<Error>>,
 }
 
 pub fn outname(_project: &Project, stage: &make::Stage, module: &flatten::Module) -> String {
-    format!("target/{}/rs/{}.rs", stage, module.name.0[1..].join("_"))
+    format!("{}/rs/{}.rs", stage.modules().display(), module.name.0[1..].join("_"))
 }
 
 impl Emitter {
     pub fn new(project: &Project, stage: make::Stage , module: flatten::Module) -> Self {
 
-        std::fs::create_dir_all(format!("target/{}/rs/", stage)).unwrap();
+        std::fs::create_dir_all(format!("{}/rs/", stage.modules().display())).unwrap();
         let p = outname(project, &stage, &module);
         let f = fs::File::create(&p).expect(&format!("cannot create {}", p));
 
@@ -42,9 +45,15 @@ impl Emitter {
             module,
             inside_macro: false,
             cur_loc: None,
+            error: RefCell::new(None),
         }
     }
 
+    // emitting goes on after an error, emit returns the first one
+    fn fail(&self, e: Error) {
+        self.error.borrow_mut().get_or_insert(e);
+    }
+
 
     fn emit_loc(&mut self, loc: &ast::Location) {
         if let Some(cur_loc) = &self.cur_loc {
@@ -93,11 +102,11 @@ impl Emitter {
             }
                 */
             ast::Type::ILiteral | ast::Type::ULiteral | ast::Type::Elided | ast::Type::New => {
-                parser::emit_error(
+                self.fail(parser::error(
                     "ICE: untyped ended up in emitter",
                     &[(name.loc.clone(), format!("this should have been resolved earlier"))]
-                    );
-                std::process::exit(9);
+                    ));
+                return None;
             }
         })
     }
@@ -121,7 +130,7 @@ impl Emitter {
         return s.0.join("_");
     }
 
-    pub fn emit(mut self) {
+    pub fn emit(mut self) -> Result<(), Error> {
         let module = self.module.clone();
         debug!("emitting rs {}", module.name);
 
@@ -212,6 +221,10 @@ impl Emitter {
         }
 
         write!(self.f, "}}\n").unwrap();
+        match self.error.into_inner() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
     }
 
     pub fn emit_static(&mut self, ast: &ast::Local) {
@@ -344,11 +357,11 @@ impl {name} {{
                     write!(self.f, "]").unwrap();
                 } else {
                     if i != (fields.len() - 1) {
-                        parser::emit_error(
+                        self.fail(parser::error(
                             "tail field has no be the last field in a struct",
                             &[(field.loc.clone(), format!("tail field would displace next field"))]
-                            );
-                        std::process::exit(9);
+                            ));
+                        return;
                     }
                     if let Some(tt) = tail_variant {
                         write!(self.f, "    pub {} : [", field.name).unwrap();
@@ -525,10 +538,10 @@ impl {name} {{
                     ast::EmitBehaviour::Default     => {},
                     ast::EmitBehaviour::Skip        => {return;},
                     ast::EmitBehaviour::Error{loc, message}   => {
-                        emit_error(format!("{}", message), &[
+                        self.fail(error(format!("{}", message), &[
                             (loc.clone(), "here")
-                        ]);
-                        std::process::exit(9);
+                        ]));
+                        return;
                     }
                 };
 
@@ -0,0 +1,390 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use super::make::{Stage, Target};
+use super::project::{ArtifactType, target_dir};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Cmake,
+    Make,
+    Meson,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cmake" => Ok(Format::Cmake),
+            "make"  => Ok(Format::Make),
+            "meson" => Ok(Format::Meson),
+            _       => Err(format!("unknown build system '{}', expected cmake, make or meson", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Cmake   => write!(f, "cmake"),
+            Format::Make    => write!(f, "make"),
+            Format::Meson   => write!(f, "meson"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CompileCommand {
+    directory:  String,
+    file:       String,
+    arguments:  Vec<String>,
+    output:     String,
+}
+
+/// write target/<stage>/compile_commands.json for clangd, clang-tidy and friends
+pub fn compile_commands(root: &Path, stage: &Stage, targets: &[Target]) {
+    let directory = root.canonicalize().unwrap_or(root.to_path_buf()).to_string_lossy().to_string();
+    let mut seen = HashSet::new();
+    let mut r = Vec::new();
+    for target in targets {
+        for unit in &target.units {
+            if !seen.insert(unit.output.clone()) {
+                continue;
+            }
+            let file = unit.source.to_string_lossy().to_string();
+            let mut arguments = vec![target.cc.clone()];
+            arguments.extend(unit.flags.iter().cloned());
+            arguments.extend(vec!["-c".to_string(), file.clone(), "-o".to_string(), unit.output.clone()]);
+            r.push(CompileCommand {
+                directory: directory.clone(),
+                file,
+                arguments,
+                output: unit.output.clone(),
+            });
+        }
+    }
+
+    let path = target_dir(root).join(stage.to_string()).join("compile_commands.json");
+    let f = std::fs::File::create(&path).expect(&format!("cannot create {:?}", path));
+    serde_json::ser::to_writer_pretty(f, &r).expect(&format!("cannot write {:?}", path));
+}
+
+// one artifact, with its sources renamed to where they were copied
+struct Exported {
+    id:         String,
+    name:       String,
+    typ:        ArtifactType,
+    // sources that share the same flags are compiled together
+    groups:     Vec<(Vec<String>, Vec<String>)>,
+    lflags:     Vec<String>,
+}
+
+/// copy the sources of targets to out/src, the local C headers they include to out/include,
+/// and write a build description for them, so the emitted C can be built without zz or the project
+pub fn export(root: &Path, project: &str, stage: &Stage, format: Format, targets: &[Target], out: &Path)
+    -> Result<PathBuf, String>
+{
+    let srcdir = out.join("src");
+    std::fs::create_dir_all(&srcdir).map_err(|e| format!("cannot create {:?}: {}", srcdir, e))?;
+
+    let mut copied : HashMap<PathBuf, String> = HashMap::new();
+    let mut taken  : HashSet<String> = HashSet::new();
+    let mut exported = Vec::new();
+
+    // the headers of the project's modules, for whoever links against an exported library.
+    // they keep their place, and are copied once every include directory is known
+    let mut modules = Vec::new();
+    let headers = root.join(stage.modules()).join("include").join("zz").join(project);
+    if let Ok(entries) = std::fs::read_dir(&headers) {
+        for entry in entries {
+            let path = entry.map_err(|e| format!("cannot read {:?}: {}", headers, e))?.path();
+            let rel = format!("include/zz/{}/{}", project, path.file_name().unwrap_or_default().to_string_lossy());
+            taken.insert(rel.clone());
+            copied.insert(path.canonicalize().unwrap_or(path.clone()), rel.clone());
+            modules.push((path, rel));
+        }
+    }
+    let mut includes : Vec<String> = Vec::new();
+
+    for target in targets {
+        match target.typ {
+            ArtifactType::Header | ArtifactType::NodeModule => {
+                warn!("cannot export {:?} artifact {}", target.typ, target.name);
+                continue;
+            }
+            _ => (),
+        }
+
+        let mut groups : Vec<(Vec<String>, Vec<String>)> = Vec::new();
+        for unit in &target.units {
+            let (flags, local) = local_includes(root, &unit.flags);
+            for dir in &local {
+                if !includes.contains(dir) {
+                    includes.push(dir.clone());
+                }
+            }
+            let source = root.join(&unit.source);
+            let rel = match copied.get(&source) {
+                Some(v) => v.clone(),
+                None => {
+                    let rel = unique_name(&source, "src", &mut taken);
+                    copied.insert(source.clone(), rel.clone());
+                    copy_local(&source, &rel, out, &local, &mut copied, &mut taken)?;
+                    rel
+                }
+            };
+
+            match groups.iter_mut().find(|(f, _)| *f == flags) {
+                Some((_, sources)) => sources.push(rel),
+                None => groups.push((flags, vec![rel])),
+            }
+        }
+
+        exported.push(Exported {
+            id:     String::new(),
+            name:   target.name.clone(),
+            typ:    target.typ.clone(),
+            groups,
+            lflags: target.lflags.clone(),
+        });
+    }
+
+    for (path, rel) in &modules {
+        copy_local(path, rel, out, &includes, &mut copied, &mut taken)?;
+    }
+
+    // a lib and an exe may share a name, but not a build system target
+    let mut count : BTreeMap<String, usize> = BTreeMap::new();
+    for e in &exported {
+        *count.entry(e.name.clone()).or_insert(0) += 1;
+    }
+    for e in &mut exported {
+        e.id = if count[&e.name] > 1 {
+            format!("{}_{}", e.name, kind(&e.typ))
+        } else {
+            e.name.clone()
+        };
+    }
+
+    let cc = targets.first().map(|t| t.cc.clone()).unwrap_or("cc".to_string());
+    let (file, content) = match format {
+        Format::Cmake   => ("CMakeLists.txt", cmake(project, stage, &exported)),
+        Format::Make    => ("Makefile", makefile(project, stage, &cc, &exported)),
+        Format::Meson   => ("meson.build", meson(project, stage, &exported)),
+    };
+    let path = out.join(file);
+    std::fs::write(&path, content).map_err(|e| format!("cannot write {:?}: {}", path, e))?;
+    Ok(path)
+}
+
+// copies a file to out/rel, and every local header it includes to out/include.
+// the includes are rewritten relative to the copy, so no include path into the project is needed
+fn copy_local(source: &Path, rel: &str, out: &Path, local: &[String],
+    copied: &mut HashMap<PathBuf, String>, taken: &mut HashSet<String>) -> Result<(), String>
+{
+    let text = std::fs::read_to_string(source).map_err(|e| format!("cannot copy {:?}: {}", source, e))?;
+    let mut r = String::new();
+    for line in text.lines() {
+        if line.trim_start().starts_with("#include") {
+            if let Some(header) = super::make::local_include(line, source, local)? {
+                let hrel = match copied.get(&header) {
+                    Some(v) => v.clone(),
+                    None => {
+                        let hrel = unique_name(&header, "include", taken);
+                        copied.insert(header.clone(), hrel.clone());
+                        copy_local(&header, &hrel, out, local, copied, taken)?;
+                        hrel
+                    }
+                };
+                r.push_str(&format!("#include \"{}\"\n", relative(rel, &hrel)));
+                continue;
+            }
+        }
+        r.push_str(line);
+        r.push('\n');
+    }
+
+    let to = out.join(rel);
+    if let Some(dir) = to.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("cannot create {:?}: {}", dir, e))?;
+    }
+    std::fs::write(&to, r).map_err(|e| format!("cannot write {:?}: {}", to, e))
+}
+
+fn unique_name(source: &Path, dir: &str, taken: &mut HashSet<String>) -> String {
+    let stem = source.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or("source".to_string());
+    let ext  = source.extension().map(|s| format!(".{}", s.to_string_lossy())).unwrap_or_default();
+    let mut name = format!("{}/{}{}", dir, stem, ext);
+    let mut i = 1;
+    while !taken.insert(name.clone()) {
+        name = format!("{}/{}_{}{}", dir, stem, i, ext);
+        i += 1;
+    }
+    name
+}
+
+// the path of one file in the export as seen from another
+fn relative(from: &str, to: &str) -> String {
+    let from : Vec<&str> = from.split('/').collect();
+    let to   : Vec<&str> = to.split('/').collect();
+    let from = &from[..from.len() - 1];
+    let common = from.iter().zip(&to[..to.len() - 1]).take_while(|(a, b)| a == b).count();
+    let mut r : Vec<&str> = vec![".."; from.len() - common];
+    r.extend(&to[common..]);
+    r.join("/")
+}
+
+// include paths into the project are left out, the headers found there are copied instead.
+// returns the remaining flags and the project's include directories
+fn local_includes(root: &Path, flags: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut r = Vec::new();
+    let mut local = Vec::new();
+    let mut it = flags.iter();
+    while let Some(flag) = it.next() {
+        let path = if flag == "-I" {
+            match it.next() {
+                Some(v) => v.clone(),
+                None => break,
+            }
+        } else if let Some(v) = flag.strip_prefix("-I") {
+            v.to_string()
+        } else {
+            r.push(flag.clone());
+            continue;
+        };
+        if path.starts_with('-') || Path::new(&path).is_absolute() {
+            r.push(format!("-I{}", path));
+        } else {
+            local.push(root.join(&path).to_string_lossy().to_string());
+        }
+    }
+    (r, local)
+}
+
+fn kind(typ: &ArtifactType) -> &'static str {
+    match typ {
+        ArtifactType::Lib       => "lib",
+        ArtifactType::Staticlib => "staticlib",
+        ArtifactType::Exe       => "exe",
+        ArtifactType::Test      => "test",
+        ArtifactType::Header    => "header",
+        ArtifactType::NodeModule=> "npm",
+    }
+}
+
+fn cmake(project: &str, stage: &Stage, exported: &[Exported]) -> String {
+    let q = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"").replace('$', "\\$").replace(';', "\\;"));
+    let mut r = String::new();
+    r.push_str(&format!("# generated by zz export from {}, stage {}\n", project, stage));
+    r.push_str("cmake_minimum_required(VERSION 3.13)\n");
+    r.push_str(&format!("project({} C)\n", q(project)));
+
+    for e in exported {
+        r.push('\n');
+        let mut objects = Vec::new();
+        for (i, (flags, sources)) in e.groups.iter().enumerate() {
+            let lib = format!("{}_objects_{}", e.id, i);
+            r.push_str(&format!("add_library({} OBJECT {})\n", lib, sources.join(" ")));
+            if !flags.is_empty() {
+                r.push_str(&format!("target_compile_options({} PRIVATE {})\n", lib,
+                    flags.iter().map(|f| q(f)).collect::<Vec<_>>().join(" ")));
+            }
+            objects.push(format!("$<TARGET_OBJECTS:{}>", lib));
+        }
+        match e.typ {
+            ArtifactType::Exe | ArtifactType::Test =>
+                r.push_str(&format!("add_executable({} {})\n", e.id, objects.join(" "))),
+            ArtifactType::Lib =>
+                r.push_str(&format!("add_library({} SHARED {})\n", e.id, objects.join(" "))),
+            _ =>
+                r.push_str(&format!("add_library({} STATIC {})\n", e.id, objects.join(" "))),
+        }
+        if e.id != e.name {
+            r.push_str(&format!("set_target_properties({} PROPERTIES OUTPUT_NAME {})\n", e.id, q(&e.name)));
+        }
+        if !e.lflags.is_empty() && e.typ != ArtifactType::Staticlib {
+            r.push_str(&format!("target_link_options({} PRIVATE {})\n", e.id,
+                e.lflags.iter().map(|f| q(f)).collect::<Vec<_>>().join(" ")));
+        }
+    }
+    r
+}
+
+fn makefile(project: &str, stage: &Stage, cc: &str, exported: &[Exported]) -> String {
+    let mut r = String::new();
+    r.push_str(&format!("# generated by zz export from {}, stage {}\n", project, stage));
+    r.push_str(&format!("CC = {}\n", cc));
+    r.push_str("AR = ar\n\n");
+
+    let outputs : Vec<String> = exported.iter().map(|e| make_output(e)).collect();
+    r.push_str(&format!("all: {}\n\n", outputs.join(" ")));
+
+    for (e, output) in exported.iter().zip(&outputs) {
+        let mut objects = Vec::new();
+        for (flags, sources) in &e.groups {
+            for source in sources {
+                let object = format!("obj/{}/{}.o", e.id, source.trim_start_matches("src/"));
+                r.push_str(&format!("{}: {}\n", object, source));
+                r.push_str("\t@mkdir -p $(@D)\n");
+                r.push_str(&format!("\t$(CC) {} -c $< -o $@\n\n", flags.iter().map(|f| sh(f)).collect::<Vec<_>>().join(" ")));
+                objects.push(object);
+            }
+        }
+
+        r.push_str(&format!("{}: {}\n", output, objects.join(" ")));
+        r.push_str("\t@mkdir -p $(@D)\n");
+        let lflags = e.lflags.iter().map(|f| sh(f)).collect::<Vec<_>>().join(" ");
+        match e.typ {
+            ArtifactType::Staticlib => r.push_str("\t$(AR) rcs $@ $^\n\n"),
+            ArtifactType::Lib       => r.push_str(&format!("\t$(CC) $^ {} -shared -o $@\n\n", lflags)),
+            _                       => r.push_str(&format!("\t$(CC) $^ {} -o $@\n\n", lflags)),
+        }
+    }
+
+    r.push_str("clean:\n\trm -rf obj bin lib\n\n");
+    r.push_str(".PHONY: all clean\n");
+    r
+}
+
+fn make_output(e: &Exported) -> String {
+    match e.typ {
+        ArtifactType::Staticlib => format!("lib/lib{}.a", e.name),
+        ArtifactType::Lib       => format!("lib/lib{}.so", e.name),
+        _                       => format!("bin/{}", e.name),
+    }
+}
+
+// make hands recipes to sh, and takes $ for itself
+fn sh(s: &str) -> String {
+    let s = s.replace('$', "$$");
+    if !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || "-_=./,+:@%".contains(c)) {
+        return s;
+    }
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+fn meson(project: &str, stage: &Stage, exported: &[Exported]) -> String {
+    let q = |s: &str| format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"));
+    let list = |v: &[String]| format!("[{}]", v.iter().map(|s| q(s)).collect::<Vec<_>>().join(", "));
+    let mut r = String::new();
+    r.push_str(&format!("# generated by zz export from {}, stage {}\n", project, stage));
+    r.push_str(&format!("project({}, 'c')\n", q(project)));
+
+    for e in exported {
+        r.push('\n');
+        let mut objects = Vec::new();
+        for (i, (flags, sources)) in e.groups.iter().enumerate() {
+            let lib = format!("{}_objects_{}", e.id.replace(|c: char| !c.is_alphanumeric(), "_"), i);
+            r.push_str(&format!("{} = static_library({}, {}, c_args: {})\n", lib, q(&lib), list(sources), list(flags)));
+            objects.push(format!("{}.extract_all_objects(recursive: false)", lib));
+        }
+        let function = match e.typ {
+            ArtifactType::Exe | ArtifactType::Test  => "executable",
+            ArtifactType::Lib                       => "shared_library",
+            _                                       => "static_library",
+        };
+        let link_args = if e.typ == ArtifactType::Staticlib { Vec::new() } else { e.lflags.clone() };
+        r.push_str(&format!("{}({}, objects: [{}], link_args: {})\n", function, q(&e.name), objects.join(", "), list(&link_args)));
+    }
+    r
+}
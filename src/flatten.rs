@@ -1,7 +1,8 @@
 use super::ast;
 use super::name::Name;
 use super::loader;
-use super::parser::emit_error;
+use super::parser::error;
+use super::Error;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::path::PathBuf;
@@ -280,7 +281,7 @@ fn expr_deps(cr: &mut Collector, expr: &ast::Expression) -> Vec<(Name, TypeCompl
     }
 }
 
-pub fn flatten(md: &ast::Module, all_modules: &HashMap<Name, loader::Module>, ext: &Ext) -> Module {
+pub fn flatten(md: &ast::Module, all_modules: &HashMap<Name, loader::Module>, ext: &Ext) -> Result<Module, Error> {
     debug!("flatten {}", md.name);
 
     let mut flat    = Module::default();
@@ -325,10 +326,9 @@ pub fn flatten(md: &ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
             debug!("  localizing {}", name);
 
             if !name.is_absolute() {
-                emit_error(format!("undefined type '{}' during flatten of '{}'", name, md.name), &[
+                return Err(error(format!("undefined type '{}' during flatten of '{}'", name, md.name), &[
                        (loc.clone(), &format!("type '{}' unavailable in this scope", name)),
-                ]);
-                std::process::exit(9);
+                ]));
             }
 
 
@@ -340,10 +340,9 @@ pub fn flatten(md: &ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
             if name.0[1] == "ext" {
                 local = ext.ext.get(&module_name);
                 if local.is_none() {
-                    emit_error(format!("ICE ext module {} unavable or somehow we're missing local {}", module_name, local_name ), &[
+                    return Err(error(format!("ICE ext module {} unavable or somehow we're missing local {}", module_name, local_name ), &[
                         (loc.clone(), &format!("type '{}' unavailable in this scope", name)),
-                    ]);
-                    std::process::exit(9);
+                    ]));
                 }
             } else {
                 let module = loop {
@@ -360,10 +359,9 @@ pub fn flatten(md: &ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
                                 }
                             }
 
-                            emit_error(format!("ice: unknown module {}", module_name), &[
+                            return Err(error(format!("ice: unknown module {}", module_name), &[
                                        (loc.clone(), &format!("type '{}' unavailable in this scope", name)),
-                            ]);
-                            std::process::exit(9);
+                            ]));
                         },
                         Some(loader::Module::C(_)) => panic!("not implemented"),
                         Some(loader::Module::ZZ(ast)) => ast,
@@ -397,10 +395,9 @@ pub fn flatten(md: &ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
             let local = match local {
                 Some(v) => v,
                 None => {
-                    emit_error(format!("module {} does not contain {}", module_name, local_name ), &[
+                    return Err(error(format!("module {} does not contain {}", module_name, local_name ), &[
                                (loc.clone(), &format!("type '{}' unavailable in this scope", name)),
-                    ]);
-                    std::process::exit(9);
+                    ]));
                 }
             };
 
@@ -452,10 +449,9 @@ pub fn flatten(md: &ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
 
                         if arg.name == "self" {
                             if i != 0 {
-                                emit_error(format!("self arg must be first"), &[
+                                return Err(error(format!("self arg must be first"), &[
                                     (arg.loc.clone(), "self argument in wrong position"),
-                                ]);
-                                std::process::exit(9);
+                                ]));
 
                             }
                             if let ast::Type::Other(name) = &arg.typed.t {
@@ -520,7 +516,7 @@ pub fn flatten(md: &ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
                     forceinline.insert(name.clone());
                 }
                 ast::Def::Testcase{fields, ..} => {
-                    for (_, expr) in fields {
+                    for (_, _, expr) in fields {
                         decl_deps.extend(expr_deps(cr, expr));
                     }
                     forceinline.insert(name.clone());
@@ -730,7 +726,7 @@ pub fn flatten(md: &ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
             0,
             &forceinline,
             &mut more,
-        );
+        )?;
     }
     for name in std::mem::replace(&mut more, HashSet::new()) {
         sort_visit(
@@ -743,7 +739,7 @@ pub fn flatten(md: &ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
             0,
             &forceinline,
             &mut more,
-        );
+        )?;
     }
 
 
@@ -781,7 +777,7 @@ pub fn flatten(md: &ast::Module, all_modules: &HashMap<Name, loader::Module>, ex
     }
 
     flat.typevariants = collector.typevariants;
-    flat
+    Ok(flat)
 }
 
 fn sort_visit(
@@ -794,7 +790,7 @@ fn sort_visit(
         depth:              usize,
         forceinline:        &HashSet<Name>,
         more:               &mut HashSet<Name>, // discover more dependencies
-)
+) -> Result<(), Error>
 {
     if name.0[1] == "ext" {
         if name.0.len() > 3 {
@@ -812,7 +808,7 @@ fn sort_visit(
 
     if let Some(cc) = sorted_mark.get(&name) {
         if cc >= &complete {
-            return;
+            return Ok(());
         }
     }
 
@@ -823,7 +819,7 @@ fn sort_visit(
                 Some(n) => n.clone(),
                 None => {
                     debug!("  {} skipped (incomplete and not found", " ".repeat(depth));
-                    return;
+                    return Ok(());
                 }
             }
         }
@@ -835,8 +831,6 @@ fn sort_visit(
                     if let Some(here) = here {
                         estack.push((here.clone(), format!("type incomplete in this scope")));
                     }
-                    emit_error(format!("recursive type {} will never complete", name), &estack);
-
                     debug!("sorted:");
                     for (name, _, _) in sorted{
                         debug!("  {}", name);
@@ -845,7 +839,7 @@ fn sort_visit(
                     for (name, _) in unsorted {
                         debug!("  {}", name);
                     }
-                    std::process::exit(10);
+                    return Err(error(format!("recursive type {} will never complete", name), &estack).with_code(10));
                 }
             }
         }
@@ -856,13 +850,13 @@ fn sort_visit(
             if dep == &name {
                 continue;
             }
-            sort_visit(sorted, sorted_mark, unsorted, dep.clone(), complete.clone(), Some(loc), depth+1, forceinline, more);
+            sort_visit(sorted, sorted_mark, unsorted, dep.clone(), complete.clone(), Some(loc), depth+1, forceinline, more)?;
         }
     }
 
 
     for (dep, complete, loc) in &n.decl_deps {
-        sort_visit(sorted, sorted_mark, unsorted, dep.clone(), complete.clone(), Some(loc), depth+1, forceinline, more);
+        sort_visit(sorted, sorted_mark, unsorted, dep.clone(), complete.clone(), Some(loc), depth+1, forceinline, more)?;
     }
 
     debug!("  {} < marked : {} {:?}", " ".repeat(depth), name, complete);
@@ -878,6 +872,7 @@ fn sort_visit(
     for (dep,_) in &n.use_deps {
         more.insert(dep.clone());
     }
+    Ok(())
 }
 
 fn dependency_visit(
@@ -0,0 +1,257 @@
+use super::make::Fuzzer;
+use super::testrunner::{self, Case, Outcome};
+use metrohash::MetroHash64;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+use super::project::target_dir;
+
+// inputs are thrown away once they took this long to reproduce
+const REPRODUCE_TIMEOUT: u64 = 10;
+// upper bound of executions spent minimizing a single crash
+const MINIMIZE_RUNS: usize = 2000;
+
+pub struct Dirs {
+    pub corpus:     PathBuf,
+    pub crashes:    PathBuf,
+    // crashes already turned into testcases
+    pub triaged:    PathBuf,
+    // afl keeps its whole state here, and creates it itself
+    pub afl:        PathBuf,
+}
+
+impl Dirs {
+    pub fn new(root: &Path, stage: &str, test: &str) -> Self {
+        let base = target_dir(root).join(stage).join("fuzz").join(test);
+        let dirs = Dirs {
+            corpus:     base.join("corpus"),
+            crashes:    base.join("crashes"),
+            triaged:    base.join("triaged"),
+            afl:        base.join("afl"),
+        };
+        for dir in &[&dirs.corpus, &dirs.crashes, &dirs.triaged] {
+            std::fs::create_dir_all(dir).expect(&format!("cannot create {:?}", dir));
+        }
+        dirs
+    }
+}
+
+/// copy the stdin of every testcase into the corpus.
+/// fuzzers refuse to start from nothing, so an empty corpus gets a single newline.
+pub fn seed(corpus: &Path, cases: &[Case]) -> usize {
+    let mut n = 0;
+    for case in cases {
+        if let Some(stdin) = &case.stdin {
+            let p = corpus.join(format!("seed-{}", case.name));
+            std::fs::write(&p, stdin).expect(&format!("cannot write {:?}", p));
+            n += 1;
+        }
+    }
+    let empty = std::fs::read_dir(corpus).map(|mut d| d.next().is_none()).unwrap_or(true);
+    if empty {
+        let p = corpus.join("seed-empty");
+        std::fs::write(&p, b"\n").expect(&format!("cannot write {:?}", p));
+    }
+    n
+}
+
+/// run the fuzzer until it stops by itself, runs out of max_time, or is interrupted
+pub fn run(fuzzer: Fuzzer, exe: &Path, dirs: &Dirs, max_time: Option<u64>, extra: &[String]) -> ExitStatus {
+    let mut cmd = match fuzzer {
+        Fuzzer::Afl => {
+            let mut cmd = Command::new("afl-fuzz");
+            cmd.env("AFL_AUTORESUME", "1").arg("-m30000");
+            if let Some(t) = max_time {
+                cmd.arg("-V").arg(t.to_string());
+            }
+            cmd.arg("-i").arg(&dirs.corpus).arg("-o").arg(&dirs.afl);
+            cmd.args(extra).arg("--").arg(exe);
+            cmd
+        }
+        Fuzzer::Libfuzzer => {
+            let mut cmd = Command::new(exe);
+            if let Some(t) = max_time {
+                cmd.arg(format!("-max_total_time={}", t));
+            }
+            cmd.arg(format!("-artifact_prefix={}/", dirs.crashes.to_string_lossy()));
+            cmd.args(extra).arg(&dirs.corpus);
+            cmd
+        }
+        Fuzzer::Honggfuzz => {
+            let mut cmd = Command::new("honggfuzz");
+            if let Some(t) = max_time {
+                cmd.arg("--run_time").arg(t.to_string());
+            }
+            cmd.arg("-i").arg(&dirs.corpus).arg("-W").arg(&dirs.crashes).arg("-s");
+            cmd.args(extra).arg("--").arg(exe);
+            cmd
+        }
+    };
+
+    let mut child = cmd.spawn().expect(&format!("failed to execute {:?}", cmd));
+
+    // ctrl-c is how fuzzers are usually stopped, but we still want to triage afterwards
+    interrupt(false);
+    let status = child.wait().unwrap();
+    interrupt(true);
+    status
+}
+
+#[cfg(unix)]
+fn interrupt(enable: bool) {
+    unsafe {
+        libc::signal(libc::SIGINT, if enable { libc::SIG_DFL } else { libc::SIG_IGN });
+    }
+}
+
+#[cfg(not(unix))]
+fn interrupt(_enable: bool) {
+}
+
+/// every crashing input the fuzzer found that isn't triaged yet
+pub fn crashes(fuzzer: Fuzzer, dirs: &Dirs) -> Vec<PathBuf> {
+    let mut from = vec![dirs.crashes.clone()];
+    if fuzzer == Fuzzer::Afl {
+        // afl++ nests by instance name, afl doesn't
+        from.push(dirs.afl.join("crashes"));
+        from.push(dirs.afl.join("default").join("crashes"));
+    }
+
+    let mut r = Vec::new();
+    for dir in from {
+        let dir = match std::fs::read_dir(&dir) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        for entry in dir {
+            let path = match entry {
+                Ok(v) => v.path(),
+                Err(_) => continue,
+            };
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            let crash = match fuzzer {
+                Fuzzer::Afl         => name.starts_with("id:") || name.starts_with("id_"),
+                Fuzzer::Libfuzzer   => name.starts_with("crash-") || name.starts_with("leak-"),
+                Fuzzer::Honggfuzz   => name.ends_with(".fuzz"),
+            };
+            if crash && path.is_file() {
+                r.push(path);
+            }
+        }
+    }
+    r.sort();
+    r
+}
+
+/// minimize every new crash, add it as a testcase to the test source and move it to triaged.
+/// returns the names of the added testcases.
+pub fn triage(fuzzer: Fuzzer, exe: &Path, dirs: &Dirs, testfile: &Path) -> Vec<String> {
+    let mut added = Vec::new();
+    for crash in crashes(fuzzer, dirs) {
+        let raw = std::fs::read(&crash).expect(&format!("cannot read {:?}", crash));
+        let min = match fuzzer {
+            Fuzzer::Libfuzzer => Some(minimize_libfuzzer(exe, &crash, dirs).unwrap_or_else(|| raw.clone())),
+            _ => minimize(exe, &raw),
+        };
+
+        match min {
+            Some(min) => {
+                info!("CRASH {:?} minimized from {} to {} bytes", crash, raw.len(), min.len());
+                if let Some(name) = add_testcase(testfile, &min) {
+                    info!("added test {} to {:?}", name, testfile);
+                    added.push(name);
+                }
+            }
+            None => {
+                warn!("CRASH {:?} does not reproduce outside the fuzzer", crash);
+            }
+        }
+
+        let to = dirs.triaged.join(crash.file_name().unwrap());
+        std::fs::rename(&crash, &to)
+            .or_else(|_| std::fs::copy(&crash, &to).and_then(|_| std::fs::remove_file(&crash)))
+            .expect(&format!("cannot move {:?} to {:?}", crash, to));
+    }
+    added
+}
+
+// libfuzzer executables read inputs from files, not stdin, but know how to minimize themselves
+fn minimize_libfuzzer(exe: &Path, crash: &Path, dirs: &Dirs) -> Option<Vec<u8>> {
+    let out = dirs.triaged.join("minimized");
+    std::fs::remove_file(&out).ok();
+    Command::new(exe)
+        .arg("-minimize_crash=1")
+        .arg(format!("-runs={}", MINIMIZE_RUNS))
+        .arg(format!("-exact_artifact_path={}", out.to_string_lossy()))
+        .arg(crash)
+        .output()
+        .ok()?;
+    let r = std::fs::read(&out).ok()?;
+    std::fs::remove_file(&out).ok();
+    Some(r)
+}
+
+// run the instrumented test directly with the input as stdin.
+// returns how it ended, or None if it passed or hung
+fn reproduce(exe: &Path, input: &[u8]) -> Option<(Option<i32>, Option<i32>)> {
+    let case = Case {
+        name:  "fuzz".to_string(),
+        stdin: Some(input.to_vec()),
+        ..Default::default()
+    };
+    let deadline = Instant::now() + Duration::from_secs(REPRODUCE_TIMEOUT);
+    let run = testrunner::run_case(exe, &case, Some(deadline), &AtomicBool::new(false));
+    match run.outcome {
+        Outcome::Fail(_) => Some((run.exit, run.signal)),
+        _ => None,
+    }
+}
+
+/// remove ever smaller chunks from the input as long as it still fails the same way.
+/// None if the input does not fail at all.
+pub fn minimize(exe: &Path, input: &[u8]) -> Option<Vec<u8>> {
+    let failure = reproduce(exe, input)?;
+
+    let mut data  = input.to_vec();
+    let mut chunk = data.len() / 2;
+    let mut runs  = 0;
+    while chunk > 0 && runs < MINIMIZE_RUNS {
+        let mut i = 0;
+        while i < data.len() && runs < MINIMIZE_RUNS {
+            let end = (i + chunk).min(data.len());
+            let mut candidate = data[..i].to_vec();
+            candidate.extend_from_slice(&data[end..]);
+            runs += 1;
+            if reproduce(exe, &candidate) == Some(failure) {
+                data = candidate;
+            } else {
+                i += chunk;
+            }
+        }
+        chunk /= 2;
+    }
+    Some(data)
+}
+
+/// append a testcase feeding the input as stdin, expecting a clean exit,
+/// so the test keeps failing until the crash is fixed
+pub fn add_testcase(testfile: &Path, input: &[u8]) -> Option<String> {
+    let mut hasher = MetroHash64::default();
+    hasher.write(input);
+    let name = format!("fuzz_{:08x}", hasher.finish() as u32);
+
+    let mut src = std::fs::read(testfile).expect(&format!("cannot read {:?}", testfile));
+    if String::from_utf8_lossy(&src).contains(&format!("test {} ", name)) {
+        return None;
+    }
+    if !src.ends_with(b"\n") {
+        src.push(b'\n');
+    }
+    src.extend_from_slice(format!("\ntest {} {{\n    stdin = ", name).as_bytes());
+    src.extend_from_slice(&testrunner::literal(input));
+    src.extend_from_slice(b"\n}\n");
+    std::fs::write(testfile, src).expect(&format!("cannot write {:?}", testfile));
+    Some(name)
+}
@@ -0,0 +1,172 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+use super::make::Stage;
+use super::project::{Build, target_dir};
+
+/// run the [build] generator of the project at root, if it has one and anything it depends on changed.
+/// returns the directory it wrote to, to be loaded like src/
+pub fn generate(root: &Path, build: Option<&Build>, stage: &Stage) -> Result<Option<PathBuf>, String> {
+    let build = match build {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let dir = target_dir(root).join(stage.to_string()).join("gen");
+    let stamp = stamp(root, stage);
+
+    if dir.exists() && !stale(root, build, &stamp)? {
+        return Ok(Some(dir.canonicalize().unwrap_or(dir)));
+    }
+
+    // the generator starts from an empty directory, so files it no longer writes don't stay around as modules.
+    // only what it changed is copied over, to not emit and compile the rest again
+    let tmp = target_dir(root).join(stage.to_string()).join("gen.tmp");
+    if tmp.exists() {
+        std::fs::remove_dir_all(&tmp).map_err(|e| format!("cannot remove {:?}: {}", tmp, e))?;
+    }
+    std::fs::create_dir_all(&tmp).map_err(|e| format!("cannot create {:?}: {}", tmp, e))?;
+    std::fs::create_dir_all(&dir).map_err(|e| format!("cannot create {:?}: {}", dir, e))?;
+    let tmp = tmp.canonicalize().unwrap_or(tmp);
+    let dir = dir.canonicalize().unwrap_or(dir);
+
+    let project = root.join(&build.generator);
+    let mut cmd = if project.join("zz.toml").exists() {
+        let mut cmd = Command::new(zz());
+        cmd.arg("run").current_dir(&project);
+        cmd
+    } else if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(&build.generator).current_dir(root);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(&build.generator).current_dir(root);
+        cmd
+    };
+    info!("generating {}", build.generator);
+    let status = cmd
+        .env("ZZ_GEN_DIR", &tmp)
+        .status()
+        .map_err(|e| format!("cannot run generator '{}': {}", build.generator, e))?;
+    if !status.success() {
+        return Err(format!("generator '{}' failed ({})", build.generator,
+            status.code().map(|c|c.to_string()).unwrap_or("signal".to_string())));
+    }
+
+    let changed = changed(root, stage);
+    if sync(&tmp, &dir)? || !changed.exists() {
+        std::fs::write(&changed, "").map_err(|e| format!("cannot write {:?}: {}", changed, e))?;
+    }
+    std::fs::remove_dir_all(&tmp).map_err(|e| format!("cannot remove {:?}: {}", tmp, e))?;
+    std::fs::write(&stamp, seen(build)).map_err(|e| format!("cannot write {:?}: {}", stamp, e))?;
+    Ok(Some(dir))
+}
+
+// turn to into a copy of from, writing only the files that differ.
+// returns whether anything changed
+fn sync(from: &Path, to: &Path) -> Result<bool, String> {
+    let entries = |dir: &Path| -> Result<Vec<std::fs::DirEntry>, String> {
+        std::fs::read_dir(dir).and_then(|d| d.collect())
+            .map_err(|e| format!("cannot read {:?}: {}", dir, e))
+    };
+    let mut changed = false;
+    for entry in entries(to)? {
+        let path = entry.path();
+        let there = from.join(entry.file_name());
+        let r = if path.is_dir() && !there.is_dir() {
+            std::fs::remove_dir_all(&path)
+        } else if !path.is_dir() && !there.is_file() {
+            std::fs::remove_file(&path)
+        } else {
+            continue;
+        };
+        r.map_err(|e| format!("cannot remove {:?}: {}", path, e))?;
+        changed = true;
+    }
+    for entry in entries(from)? {
+        let path = entry.path();
+        let here = to.join(entry.file_name());
+        if path.is_dir() {
+            std::fs::create_dir_all(&here).map_err(|e| format!("cannot create {:?}: {}", here, e))?;
+            changed |= sync(&path, &here)?;
+        } else {
+            let content = std::fs::read(&path).map_err(|e| format!("cannot read {:?}: {}", path, e))?;
+            changed |= super::write_if_changed(&here, &content).map_err(|e| format!("cannot write {:?}: {}", here, e))?;
+        }
+    }
+    Ok(changed)
+}
+
+// written after every run of the generator
+fn stamp(root: &Path, stage: &Stage) -> PathBuf {
+    target_dir(root).join(stage.to_string()).join("gen.stamp")
+}
+
+/// written after a run of the generator that changed what is in the gen directory
+pub fn changed(root: &Path, stage: &Stage) -> PathBuf {
+    target_dir(root).join(stage.to_string()).join("gen.changed")
+}
+
+/// the declared inputs, and the sources of a zz generator or the script a command runs
+pub fn inputs(root: &Path, build: &Build) -> Vec<PathBuf> {
+    let mut r : Vec<PathBuf> = build.inputs.iter().map(|i| root.join(i)).collect();
+    let project = root.join(&build.generator);
+    if project.join("zz.toml").exists() {
+        r.push(project.join("zz.toml"));
+        r.push(project.join("src"));
+    } else {
+        // like tools/gen.sh, or python3 tools/gen.py
+        r.extend(build.generator.split_whitespace().map(|w| root.join(w)).filter(|p| p.is_file()));
+    }
+    r
+}
+
+// without inputs, it only runs again when [build] changes or target is removed
+fn stale(root: &Path, build: &Build, stamp: &Path) -> Result<bool, String> {
+    let built = match std::fs::read_to_string(stamp) {
+        Ok(ref s) if s == &seen(build) => std::fs::metadata(stamp).and_then(|m| m.modified())
+            .map_err(|e| format!("cannot stat {:?}: {}", stamp, e))?,
+        _ => return Ok(true),
+    };
+    for input in inputs(root, build) {
+        if !input.exists() {
+            return Err(format!("input {:?} of generator '{}' does not exist", input, build.generator));
+        }
+        if newer(&input, built) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// what the stamp remembers about [build], so changing it runs the generator again
+fn seen(build: &Build) -> String {
+    format!("{}\n{}\n", build.generator, build.inputs.join("\n"))
+}
+
+// any file in path modified after then
+fn newer(path: &Path, then: SystemTime) -> bool {
+    let meta = match std::fs::metadata(path) {
+        Ok(v) => v,
+        Err(_) => return true,
+    };
+    if meta.is_dir() {
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        if name == "target" {
+            return false;
+        }
+        return match std::fs::read_dir(path) {
+            Ok(dir) => dir.filter_map(|e| e.ok()).any(|e| newer(&e.path(), then)),
+            Err(_) => true,
+        };
+    }
+    meta.modified().map(|m| m > then).unwrap_or(true)
+}
+
+// a zz generator is built by the zz running now, or the one in PATH when zz is used as a library
+fn zz() -> PathBuf {
+    match std::env::current_exe() {
+        Ok(exe) if exe.file_stem().map(|s| s == "zz").unwrap_or(false) => exe,
+        _ => PathBuf::from("zz"),
+    }
+}
@@ -0,0 +1,132 @@
+use std::path::{Path, PathBuf};
+use super::make::{self, Stage, Target};
+use super::project::{ArtifactType, Config, target_dir};
+
+/// copy the built targets, the exported headers and a pkg-config file into prefix.
+/// destdir is put in front of every path written, but not of the paths in the .pc file,
+/// so packaging can stage an install somewhere else than where it will end up
+pub fn install(root: &Path, config: &Config, stage: &Stage, targets: &[Target], prefix: &Path, destdir: Option<&Path>)
+    -> Result<Vec<PathBuf>, String>
+{
+    let dest = match destdir {
+        Some(d) => d.join(prefix.strip_prefix("/").unwrap_or(prefix)),
+        None    => prefix.to_path_buf(),
+    };
+    let build = target_dir(root).join(stage.to_string());
+    let project = &config.project;
+
+    let mut installed = Vec::new();
+    let mut libs = Vec::new();
+
+    for target in targets {
+        match target.typ {
+            ArtifactType::Exe => {
+                let to = dest.join("bin").join(&target.name);
+                copy(&build.join("bin").join(&target.name), &to)?;
+                installed.push(to);
+            }
+            ArtifactType::Staticlib => {
+                let file = format!("lib{}.a", target.name);
+                let to = dest.join("lib").join(&file);
+                copy(&build.join("lib").join(&file), &to)?;
+                installed.push(to);
+                libs.push(target.name.clone());
+            }
+            ArtifactType::Lib => {
+                // libfoo.so -> libfoo.so.1 -> libfoo.so.1.2.3
+                let file    = format!("lib{}.so", target.name);
+                let soname  = make::soname(&target.name, &project.version);
+                let full    = format!("lib{}.so.{}", target.name, project.version);
+                let to = dest.join("lib").join(&full);
+                copy(&build.join("lib").join(&file), &to)?;
+                installed.push(to);
+                if soname != full {
+                    symlink(&full, &dest.join("lib").join(&soname))?;
+                }
+                symlink(&soname, &dest.join("lib").join(&file))?;
+                libs.push(target.name.clone());
+            }
+            ArtifactType::Header => {
+                let file = format!("{}.h", target.name);
+                let to = dest.join("include").join(&file);
+                copy(&build.join("include").join(&file), &to)?;
+                installed.push(to);
+            }
+            ArtifactType::Test | ArtifactType::NodeModule => (),
+        }
+    }
+
+    // the headers of every module, laid out like target/<stage>/include
+    let headers = build.join("include").join("zz").join(&project.name);
+    if headers.exists() {
+        let to = dest.join("include").join("zz").join(&project.name);
+        let entries = std::fs::read_dir(&headers).map_err(|e| format!("cannot open {:?}: {}", headers, e))?;
+        for entry in entries {
+            let path = entry.map_err(|e| format!("cannot open {:?}: {}", headers, e))?.path();
+            if path.extension().map(|e| e == "h").unwrap_or(false) {
+                let to = to.join(path.file_name().unwrap());
+                copy(&path, &to)?;
+                installed.push(to);
+            }
+        }
+    }
+
+    if !libs.is_empty() {
+        let to = dest.join("lib").join("pkgconfig").join(format!("{}.pc", project.name));
+        std::fs::create_dir_all(to.parent().unwrap()).map_err(|e| format!("cannot create {:?}: {}", to.parent(), e))?;
+        std::fs::write(&to, pkgconfig(config, prefix, &libs)).map_err(|e| format!("cannot write {:?}: {}", to, e))?;
+        installed.push(to);
+    }
+
+    Ok(installed)
+}
+
+fn pkgconfig(config: &Config, prefix: &Path, libs: &[String]) -> String {
+    let project = &config.project;
+    let mut r = String::new();
+    r.push_str(&format!("prefix={}\n", prefix.display()));
+    r.push_str("exec_prefix=${prefix}\n");
+    r.push_str("libdir=${exec_prefix}/lib\n");
+    r.push_str("includedir=${prefix}/include\n\n");
+    r.push_str(&format!("Name: {}\n", project.name));
+    r.push_str(&format!("Description: {}, built with zz\n", project.name));
+    r.push_str(&format!("Version: {}\n", project.version));
+    if !project.pkgconfig.is_empty() {
+        r.push_str(&format!("Requires: {}\n", project.pkgconfig.join(", ")));
+    }
+
+    let mut cflags = vec!["-I${includedir}".to_string()];
+    cflags.extend(project.cflags.iter().cloned());
+    r.push_str(&format!("Cflags: {}\n", cflags.join(" ")));
+
+    let mut lflags = vec!["-L${libdir}".to_string()];
+    lflags.extend(libs.iter().map(|l| format!("-l{}", l)));
+    r.push_str(&format!("Libs: {}\n", lflags.join(" ")));
+    if !project.lflags.is_empty() {
+        r.push_str(&format!("Libs.private: {}\n", project.lflags.join(" ")));
+    }
+    r
+}
+
+fn copy(from: &Path, to: &Path) -> Result<(), String> {
+    if let Some(dir) = to.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("cannot create {:?}: {}", dir, e))?;
+    }
+    // replace rather than write through, in case the old file is in use or a link
+    if to.exists() {
+        std::fs::remove_file(to).map_err(|e| format!("cannot remove {:?}: {}", to, e))?;
+    }
+    std::fs::copy(from, to).map_err(|e| format!("cannot copy {:?} to {:?}: {}", from, to, e))?;
+    Ok(())
+}
+
+fn symlink(target: &str, link: &Path) -> Result<(), String> {
+    if link.symlink_metadata().is_ok() {
+        std::fs::remove_file(link).map_err(|e| format!("cannot remove {:?}: {}", link, e))?;
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(target, link).map_err(|e| format!("cannot link {:?}: {}", link, e))?;
+    #[cfg(not(unix))]
+    std::fs::copy(link.with_file_name(target), link).map_err(|e| format!("cannot copy {:?}: {}", link, e)).map(|_| ())?;
+    Ok(())
+}
@@ -23,6 +23,22 @@ pub mod symbolic;
 pub mod expand;
 pub mod smt;
 pub mod emitter_docs;
+pub mod emitter_debug;
+pub mod doctest;
+pub mod bindgen;
+pub mod testrunner;
+pub mod bench;
+pub mod fuzz;
+pub mod coverage;
+pub mod watch;
+pub mod export;
+pub mod builder;
+pub mod diagnostics;
+pub mod session;
+pub mod install;
+pub mod gen;
+
+pub use session::Session;
 
 use std::path::Path;
 use name::Name;
@@ -31,9 +47,11 @@ use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, Ordering};
 
 
+#[derive(Debug, Clone)]
 pub struct Error {
     message:    String,
     details:    Vec<(ast::Location, String)>,
+    code:       i32,
 }
 
 impl Error {
@@ -41,80 +59,212 @@ impl Error {
         Self{
             message,
             details,
+            code: 9,
         }
     }
+
+    /// the exit code of the cli for this error
+    pub fn with_code(mut self, code: i32) -> Self {
+        self.code = code;
+        self
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn details(&self) -> &[(ast::Location, String)] {
+        &self.details
+    }
+
+    pub fn code(&self) -> i32 {
+        self.code
+    }
 }
 
-static ABORT: AtomicBool = AtomicBool::new(false);
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        for (loc, message) in &self.details {
+            write!(f, "\n  {}: {}", loc, message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 pub enum BuildSet {
     Tests,
     Run,
     Check,
     All,
+    Doctests,
+    /// everything All builds, as one C file and one header per artifact, without compiling it
+    Amalgamate,
 }
 
-pub fn build(buildset: BuildSet, variant: &str, stage: make::Stage, slow: bool) {
+pub fn build(buildset: BuildSet, variant: &str, stage: make::Stage, slow: bool) -> Result<(), Error> {
+    pipeline(buildset, variant, stage, slow, None).map(|_| ())
+}
+
+// report an error where the pipeline stops on it
+fn report<T>(r: Result<T, Error>) -> Result<T, Error> {
+    r.map_err(|e| {
+        diagnostics::error(&e);
+        e
+    })
+}
+
+// what failed to read or write, like in "cannot create target/test/zz"
+fn io<T>(r: std::io::Result<T>, what: String) -> Result<T, Error> {
+    r.map_err(|e| Error::new(format!("{}: {}", what, e), Vec::new()).with_code(3))
+}
+
+// select limits the artifacts to those names
+// errors have been reported when this returns them
+fn pipeline(buildset: BuildSet, variant: &str, stage: make::Stage, slow: bool, select: Option<&HashSet<String>>)
+    -> Result<Vec<make::Target>, Error>
+{
+    let (root, project) = report(project::load_cwd())?;
+
+    // #if feature() also sees an artifact's own features,
+    // so artifacts with different ones have the modules parsed and emitted for each of them
+    let mut flavors : Vec<Vec<String>> = Vec::new();
+    if buildset != BuildSet::Doctests {
+        for artifact in project.artifacts.iter().flatten() {
+            if select.map(|s| s.contains(&artifact.name)).unwrap_or(true) && builds(&buildset, artifact)
+                && !flavors.contains(&flavor(artifact))
+            {
+                flavors.push(flavor(artifact));
+            }
+        }
+    }
+    if flavors.is_empty() {
+        flavors.push(Vec::new());
+    }
+    flavors.sort();
+
+    let mut makes = Vec::new();
+    for features in flavors {
+        let mut stage = stage.clone();
+        stage.features = features;
+        makes.extend(modules(buildset, variant, stage, slow, select)?);
+    }
+
+    // written before linking, so the tools also work on a build that fails
+    let targets : Vec<make::Target> = makes.iter().map(|m| m.target()).collect();
+    export::compile_commands(&root, &stage, &targets);
+
+    if buildset != BuildSet::Check {
+        for make in makes {
+            make.link()?;
+        }
+    }
+
+    Ok(targets)
+}
+
+// whether the buildset builds this kind of artifact
+fn builds(buildset: &BuildSet, artifact: &project::Artifact) -> bool {
+    match (&artifact.typ, buildset) {
+        (project::ArtifactType::Test, BuildSet::Tests)      => true,
+        (project::ArtifactType::Test, BuildSet::Doctests)   => true,
+        (project::ArtifactType::Test, _)                    => false,
+        (project::ArtifactType::Exe, _)                     => true,
+        (_, BuildSet::Run)                                  => false,
+        (_,_)                                               => true,
+    }
+}
+
+// the artifact's own features, in the form stage.features has them
+fn flavor(artifact: &project::Artifact) -> Vec<String> {
+    let mut features = artifact.features.clone();
+    features.sort();
+    features.dedup();
+    features
+}
+
+// everything up to linking, for the artifacts whose features are stage.features
+fn modules(buildset: BuildSet, variant: &str, stage: make::Stage, slow: bool, select: Option<&HashSet<String>>)
+    -> Result<Vec<make::Make>, Error>
+{
     use rayon::prelude::*;
     use std::sync::{Arc, Mutex};
 
-    let (root, mut project) = project::load_cwd();
+    let (root, mut project) = report(project::load_cwd())?;
     //std::env::set_current_dir(root).unwrap();
 
-    std::fs::create_dir_all(root.join("target").join(stage.to_string()).join("c")).expect("create target dir");
-    std::fs::create_dir_all(root.join("target").join(stage.to_string()).join("zz")).expect("create target dir");
-    std::fs::create_dir_all(root.join("target").join(stage.to_string()).join("include")
-                            .join("zz").join(&project.project.name)).expect("create target dir");
+    let mut stage = stage.configure(&project).map_err(|e| {
+        let e = Error::new(e, Vec::new());
+        diagnostics::error(&e);
+        e
+    })?;
+    // relative to the project like everything else, unless it's shared by a workspace
+    let target = project::target_dir(&root);
+    stage.target = target.strip_prefix(&root).map(|t| t.to_path_buf()).unwrap_or(target);
+
+    for dir in &[
+        root.join(&stage.target).join(stage.to_string()).join("c"),
+        root.join(&stage.target).join(stage.to_string()).join("zz"),
+        root.join(stage.modules()).join("zz"),
+        root.join(stage.modules()).join("amalgamated"),
+        root.join(stage.modules()).join("include").join("zz").join(&project.project.name),
+    ] {
+        report(io(std::fs::create_dir_all(dir), format!("cannot create {:?}", dir)))?;
+    }
 
     let project_name        = Name(vec![String::new(), project.project.name.clone()]);
     let project_tests_name  = Name(vec![String::new(), project.project.name.clone(), "tests".to_string()]);
 
 
 
+    let generated = report(gen::generate(&root, project.build.as_ref(), &stage).map_err(|e| Error::new(e, Vec::new())))?;
+
     let mut modules = HashMap::new();
-    let features = project.features(variant).into_iter().map(|(n,(e,_))|(n,e)).collect();
+    let features = report(project.features_for(variant, &stage.features))?.into_iter().map(|(n,(e,_))|(n,e)).collect();
     if root.join("src").exists() {
-        loader::load(&mut modules, &project_name, &root.join("src"), &features, &stage);
+        report(loader::load(&mut modules, &project_name, &root.join("src"), &features, &stage))?;
+    }
+    // generated modules belong to the project, and its C can include generated headers
+    if let Some(generated) = &generated {
+        report(loader::load(&mut modules, &project_name, generated, &features, &stage))?;
+        project.project.cincludes.push(generated.to_string_lossy().into());
     }
     if root.join("tests").exists() {
-        loader::load(&mut modules, &project_tests_name, &root.join("tests").canonicalize().unwrap(), &features, &stage);
+        report(loader::load(&mut modules, &project_tests_name, &root.join("tests").canonicalize().unwrap(), &features, &stage))?;
     }
 
-
-
-    let mut searchpaths = HashSet::new();
-    searchpaths.insert(std::env::current_exe().expect("self path")
-        .canonicalize().expect("self path")
-        .parent().expect("self path")
-        .parent().expect("self path")
-        .parent().expect("self path")
-        .join("modules"));
-    searchpaths.insert(
-        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("modules")
-    );
-
-    if let Ok(zz_path) = std::env::var("ZZ_MODULE_PATHS") {
-        let module_paths = if cfg!(windows) {
-            zz_path.split(";")
+    // doc examples are tests of their own and replace the regular artifacts
+    let mut expect_fail = HashSet::new();
+    if buildset == BuildSet::Doctests {
+        let doctests = report(doctest::generate(&modules, &project_name, &root, &stage))?;
+        if doctests.is_empty() {
+            info!("no examples found in doc comments");
         } else {
-            zz_path.split(":")
-        };
-
-        for path in module_paths {
-            searchpaths.insert(std::path::Path::new(&path).to_path_buf());
+            let mut project_doctests_name = project_name.clone();
+            project_doctests_name.push("doctests".to_string());
+            report(loader::load(&mut modules, &project_doctests_name, &doctest::dir(&root, &stage), &features, &stage))?;
         }
-    }
-
-    if let Some(deps) = &project.dependencies {
-        for (name, dep) in deps {
-            match dep {
-                toml::Value::String(_) => {
-                    getdep(name, &mut modules, &mut project.project, &mut searchpaths, &stage);
-                },
-                _ => (),
+        let mut artifacts = Vec::new();
+        for t in doctests {
+            if t.should_fail {
+                expect_fail.insert(t.module.clone());
+            } else {
+                artifacts.push(t.artifact());
             }
         }
+        project.artifacts = Some(artifacts);
+    }
+
+
+
+    let mut searchpaths = searchpaths();
+
+    // optional dependencies come with the features of the variant or of the artifacts being built
+    for name in project.dependencies(&report(project.features_for(variant, &stage.features))?) {
+        report(getdep(&name, &mut modules, &mut project.project, &mut searchpaths, &stage))?;
     }
 
 
@@ -131,7 +281,7 @@ pub fn build(buildset: BuildSet, variant: &str, stage: make::Stage, slow: bool)
         match &mut md {
             loader::Module::C(_) => (),
             loader::Module::ZZ(ast) => {
-                abs::abs(ast, &modules, &mut ext);
+                report(abs::abs(ast, &modules, &mut ext))?;
             }
         }
         modules.insert(name.clone(), md);
@@ -171,8 +321,30 @@ pub fn build(buildset: BuildSet, variant: &str, stage: make::Stage, slow: bool)
     //pb.lock().unwrap().show_speed = false;
 
     let silent = parser::ERRORS_AS_JSON.load(Ordering::SeqCst);
+    // a module failed to prove. the others still go on, to report theirs too
+    let abort = AtomicBool::new(false);
     let working_on_these = Arc::new(Mutex::new(HashSet::new()));
 
+    // header artifacts are put together from every module's amalgamated form
+    let amalgamate_h = project.artifacts.as_ref().map(|a| a.iter().any(|a| {
+        a.typ == project::ArtifactType::Header && select.map(|s| s.contains(&a.name)).unwrap_or(true)
+    })).unwrap_or(false);
+    let amalgamate_c = buildset == BuildSet::Amalgamate;
+
+    // what #if saw while parsing. every module depends on it like on a source file,
+    // so a different define or feature emits and compiles everything again
+    let ppstamp = root.join(stage.modules()).join("preprocessor");
+    let ppseen = format!("{:?}\n{:?}\n{:?}\n",
+        features.iter().collect::<std::collections::BTreeMap<_,_>>(), stage.defines, pp::Platform::get());
+    if std::fs::read_to_string(&ppstamp).map(|s| s != ppseen).unwrap_or(true) {
+        report(io(std::fs::write(&ppstamp, &ppseen), format!("cannot write {:?}", ppstamp)))?;
+    }
+    let mut stamps = vec![ppstamp];
+    // any module may use what the generator wrote, so changing it is like changing every source
+    if generated.is_some() {
+        stamps.push(gen::changed(&root, &stage));
+    }
+
     let iterf =  |name: Name| {
         let (_, outname) = emitter::outname(&project.project, &stage, &name, false);
 
@@ -182,7 +354,7 @@ pub fn build(buildset: BuildSet, variant: &str, stage: make::Stage, slow: bool)
                 match serde_json::from_str(&f) {
                     Ok(cf) => Some(cf),
                     Err(_) => {
-                        std::fs::remove_file(&cachename).expect(&format!("cannot remove {}", cachename));
+                        io(std::fs::remove_file(&cachename), format!("cannot remove {}", cachename))?;
                         None
                     }
                 }
@@ -192,8 +364,12 @@ pub fn build(buildset: BuildSet, variant: &str, stage: make::Stage, slow: bool)
         };
 
         //only emit if any source file is newer than the cache or output
-        if let Some(cached) = cached {
-            if !cached.is_newer_than(&outname) && !cached.is_newer_than(&cachename) {
+        if let Some(mut cached) = cached {
+            cached.sources.extend(stamps.iter().cloned());
+            if !cached.is_newer_than(&outname) && !cached.is_newer_than(&cachename) &&
+                !(amalgamate_h && cached.is_newer_than(&emitter::amalgamated_name(&stage, &name, true))) &&
+                !(amalgamate_c && cached.is_newer_than(&emitter::amalgamated_name(&stage, &name, false)))
+            {
                 if !silent {
                     //pb.lock().unwrap().message(&format!("cached {} ", module.name));
                     pb.lock().unwrap().inc();
@@ -217,7 +393,7 @@ pub fn build(buildset: BuildSet, variant: &str, stage: make::Stage, slow: bool)
                 return Ok(Some((cf.name.clone(), cf)));
             }
             loader::Module::ZZ(ast) => {
-                flatten::flatten(ast, &modules, &ext)
+                flatten::flatten(ast, &modules, &ext)?
             }
         };
 
@@ -241,25 +417,47 @@ pub fn build(buildset: BuildSet, variant: &str, stage: make::Stage, slow: bool)
         }
 
         expand::expand(&mut module)?;
-        if !symbolic::execute(&mut module) {
-            ABORT.store(true, Ordering::Relaxed);
+        if stage.prove && !symbolic::execute(&mut module, &stage.target.join("ssa")) {
+            if expect_fail.contains(&name) {
+                info!("PASS {} failed to prove, as it should", name.human_name());
+            } else {
+                abort.store(true, Ordering::Relaxed);
+            }
+            return Ok(None);
+        }
+        if expect_fail.contains(&name) {
+            error!("FAIL {} is marked should_fail, but passed symbolic execution", name.human_name());
+            abort.store(true, Ordering::Relaxed);
             return Ok(None);
         }
 
         let header  = emitter::Emitter::new(&project.project, stage.clone(), module.clone(), true);
-        header.emit();
+        header.emit()?;
 
         let rsbridge = emitter_rs::Emitter::new(&project.project, stage.clone(), module.clone());
-        rsbridge.emit();
+        rsbridge.emit()?;
 
         let jsbridge = emitter_js::Emitter::new(&project.project, stage.clone(), module.clone());
-        jsbridge.emit();
+        jsbridge.emit()?;
 
         let docs = emitter_docs::Emitter::new(&project.project, stage.clone(), module.clone());
         docs.emit();
 
+        let debug = emitter_debug::Emitter::new(stage.clone(), module.clone());
+        debug.emit();
+
+        if amalgamate_h {
+            let amalgamated = emitter::Emitter::new_amalgamated(&project.project, stage.clone(), module.clone(), true);
+            amalgamated.emit()?;
+        }
+        if amalgamate_c {
+            let amalgamated = emitter::Emitter::new_amalgamated(&project.project, stage.clone(), module.clone(), false);
+            amalgamated.emit()?;
+        }
+
         let em = emitter::Emitter::new(&project.project, stage.clone(), module, false);
-        let cf = em.emit();
+        let mut cf = em.emit()?;
+        cf.sources.extend(stamps.iter().cloned());
 
 
         if !silent {
@@ -280,8 +478,14 @@ pub fn build(buildset: BuildSet, variant: &str, stage: make::Stage, slow: bool)
             pb.lock().unwrap().inc();
         }
 
-        let cachefile = std::fs::File::create(&cachename).expect(&format!("cannot create {}", cachename));
-        serde_json::ser::to_writer(cachefile, &cf).expect(&format!("cannot write {}", cachename));
+        // unproven output must not pass for proven once the profile turns proving back on
+        if stage.prove {
+            let cachefile = io(std::fs::File::create(&cachename), format!("cannot create {}", cachename))?;
+            serde_json::ser::to_writer(cachefile, &cf)
+                .map_err(|e| Error::new(format!("cannot write {}: {}", cachename, e), Vec::new()).with_code(3))?;
+        } else {
+            std::fs::remove_file(&cachename).ok();
+        }
 
         Ok(Some((cf.name.clone(), cf)))
     };
@@ -299,38 +503,52 @@ pub fn build(buildset: BuildSet, variant: &str, stage: make::Stage, slow: bool)
                 cfiles.insert(v.0, v.1);
             }
             Err(e) => {
-                parser::emit_error(e.message.clone(), &e.details);
-                ABORT.store(true, Ordering::Relaxed);
+                diagnostics::error(&e);
+                abort.store(true, Ordering::Relaxed);
             }
         }
     };
 
 
 
-    if ABORT.load(Ordering::Relaxed) {
-        std::process::exit(9);
+    if abort.load(Ordering::Relaxed) {
+        return Err(Error::new(format!("could not compile {}", project.project.name), Vec::new()));
     }
 
     if !silent {
         pb.lock().unwrap().finish_print("done emitting");
     }
 
+    let mut emitted : Vec<&Name> = cfiles.keys().collect();
+    emitted.sort_unstable();
+    emitter_debug::link(&stage, &emitted);
+
+    let mut makes = Vec::new();
     for artifact in std::mem::replace(&mut project.artifacts, None).expect("no artifacts") {
-        match (&artifact.typ, &buildset) {
-            (project::ArtifactType::Test, BuildSet::Tests)  => (),
-            (project::ArtifactType::Test, _)                => continue,
-            (project::ArtifactType::Exe, _)                 => (),
-            (_, BuildSet::Run)                              => continue,
-            (_,_)                                           => (),
-        };
-        let mut make = make::Make::new(project.clone(), variant, stage.clone(), artifact.clone());
+        if let Some(select) = select {
+            if !select.contains(&artifact.name) {
+                continue;
+            }
+        }
+        if !builds(&buildset, &artifact) || flavor(&artifact) != stage.features {
+            continue;
+        }
+        let mut make = report(make::Make::new(project.clone(), variant, stage.clone(), artifact.clone()))?;
+        make.amalgamate = buildset == BuildSet::Amalgamate;
 
         let mut main = Name::from(&artifact.main);
         if !main.is_absolute() {
             main.0.insert(0,String::new());
         }
-        let main = cfiles.get(&main).expect(&format!(
-                "cannot build artifact '{}', main module '{}' does not exist", artifact.name, main));
+        let main = match cfiles.get(&main) {
+            Some(v) => v,
+            None => {
+                let details = project::locate(&root, &artifact.main)
+                    .map(|loc| vec![(loc, "artifact declared here".to_string())]).unwrap_or_default();
+                return report(Err(Error::new(format!(
+                    "cannot build artifact '{}', main module '{}' does not exist", artifact.name, main), details)));
+            }
+        };
 
         let mut need = Vec::new();
         need.push(main.name.clone());
@@ -362,11 +580,99 @@ pub fn build(buildset: BuildSet, variant: &str, stage: make::Stage, slow: bool)
             }
         }
 
-        if buildset != BuildSet::Check {
-            make.link();
+        makes.push(make);
+    };
+
+    Ok(makes)
+}
+
+/// replace path with content, unless it already has exactly that, so its mtime only moves on a real change.
+/// returns whether it was written
+pub(crate) fn write_if_changed(path: &std::path::Path, content: &[u8]) -> std::io::Result<bool> {
+    if let Ok(old) = std::fs::read(path) {
+        if old == content {
+            return Ok(false);
         }
+    }
+    std::fs::write(path, content)?;
+    Ok(true)
+}
+
+fn searchpaths() -> HashSet<std::path::PathBuf> {
+    let mut searchpaths = HashSet::new();
+    searchpaths.insert(std::env::current_exe().expect("self path")
+        .canonicalize().expect("self path")
+        .parent().expect("self path")
+        .parent().expect("self path")
+        .parent().expect("self path")
+        .join("modules"));
+    searchpaths.insert(
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("modules")
+    );
+
+    if let Ok(zz_path) = std::env::var("ZZ_MODULE_PATHS") {
+        let module_paths = if cfg!(windows) {
+            zz_path.split(";")
+        } else {
+            zz_path.split(":")
+        };
 
+        for path in module_paths {
+            searchpaths.insert(std::path::Path::new(&path).to_path_buf());
+        }
+    }
+    searchpaths
+}
+
+fn find_dep(name: &str, searchpaths: &HashSet<std::path::PathBuf>) -> Result<Option<std::path::PathBuf>, Error> {
+    // members of the same workspace depend on each other by name,
+    // and everything else resolves to what the workspace resolved it to before
+    if let Some(ws) = project::workspace()? {
+        if let Some((_, dir)) = ws.members.iter().find(|(n, _)| n == name) {
+            return Ok(Some(dir.clone()));
+        }
+        if let Some(dir) = project::Lock::load(&ws.root).get(&ws.root, name) {
+            return Ok(Some(dir));
+        }
+    }
+
+    let mut found = None;
+    for searchpath in searchpaths.iter() {
+        let modpath = searchpath.join(name).join("zz.toml");
+        if modpath.exists() {
+            found = Some(searchpath.join(name));
+        }
+    }
+    Ok(found)
+}
+
+/// directories of every dependency of the project in the current directory,
+/// found the same way the build does
+pub fn dependency_dirs() -> Result<Vec<std::path::PathBuf>, Error> {
+    let (_, project) = project::load_cwd()?;
+    let mut searchpaths = searchpaths();
+    searchpaths.insert(std::env::current_dir().unwrap().join("modules"));
+
+    let deps = |project: &project::Config| -> Result<Vec<String>, Error> {
+        Ok(project.dependencies(&project.features("default")?))
     };
+
+    let mut r = Vec::new();
+    let mut need = deps(&project)?;
+    while let Some(name) = need.pop() {
+        let found = match find_dep(&name, &searchpaths)? {
+            Some(v) => v,
+            None => continue,
+        };
+        if r.contains(&found) {
+            continue;
+        }
+        let (root, project) = project::load(&found)?;
+        searchpaths.insert(root.join("modules"));
+        need.extend(deps(&project)?);
+        r.push(found);
+    }
+    Ok(r)
 }
 
 fn getdep(
@@ -375,35 +681,36 @@ fn getdep(
         rootproj: &mut project::Project,
         searchpaths: &mut HashSet<std::path::PathBuf>,
         stage:  &make::Stage,
-) {
+) -> Result<(), Error> {
 
     searchpaths.insert(
         std::env::current_dir().unwrap().join("modules")
     );
 
-    let mut found = None;
-    for searchpath in searchpaths.iter() {
-        let modpath = searchpath.join(name).join("zz.toml");
-        if modpath.exists() {
-            found = Some(searchpath.join(name));
-        }
-    }
-
-    let found = match found {
+    let found = match find_dep(name, searchpaths)? {
         Some(v) => v,
         None => {
-            eprintln!("dependency \"{}\" not found in any of {:#?}", name, searchpaths);
-            std::process::exit(9);
+            return Err(Error::new(format!("dependency \"{}\" not found in any of {:#?}", name, searchpaths), Vec::new()));
         }
     };
 
     //let pp = std::env::current_dir().unwrap();
     //std::env::set_current_dir(&found).unwrap();
-    let (root, project)  = project::load(&found);
+    let (root, project)  = project::load(&found)?;
     let project_name     = Name(vec![String::new(), project.project.name.clone()]);        
+    let deps             = project.dependencies(&project.features("default")?);
+    if let Some(ws) = project::workspace()? {
+        project::Lock::update(&ws.root, name, &project.project.version, &found);
+    }
+    let generated = gen::generate(&root, project.build.as_ref(), &stage)
+        .map_err(|e| Error::new(format!("{}: {}", name, e), Vec::new()))?;
+    let features = project.features("default")?.into_iter().map(|(n,(e,_))|(n,e)).collect();
     if found.join("src").exists() {
-        let features = project.features("default").into_iter().map(|(n,(e,_))|(n,e)).collect();
-        loader::load(modules, &project_name, &found.join("src"), &features, &stage);
+        loader::load(modules, &project_name, &found.join("src"), &features, &stage)?;
+    }
+    if let Some(generated) = generated {
+        loader::load(modules, &project_name, &generated, &features, &stage)?;
+        rootproj.cincludes.push(generated.to_string_lossy().into());
     }
     //std::env::set_current_dir(pp).unwrap();
 
@@ -414,12 +721,12 @@ fn getdep(
 
     for i in project.project.cincludes {
         let ii = root.join(&i);
-        let i = std::fs::canonicalize(&ii).expect(&format!("{}: cannot resolve cinclude {:?}", name, ii));
+        let i = io(std::fs::canonicalize(&ii), format!("{}: cannot resolve cinclude {:?}", name, ii))?;
         rootproj.cincludes.push(i.to_string_lossy().into());
     }
     for i in project.project.cobjects {
         let ii = root.join(&i);
-        let i = std::fs::canonicalize(&ii).expect(&format!("{}: cannot resolve cobject {:?}", name, ii));
+        let i = io(std::fs::canonicalize(&ii), format!("{}: cannot resolve cobject {:?}", name, ii))?;
         rootproj.cobjects.push(i.to_string_lossy().into());
     }
     rootproj.pkgconfig.extend(project.project.pkgconfig);
@@ -427,16 +734,10 @@ fn getdep(
     rootproj.lflags.extend(project.project.lflags);
 
 
-    if let Some(deps) = &project.dependencies {
-        for (name, dep) in deps {
-            match dep {
-                toml::Value::String(_) => {
-                    getdep(name, modules, rootproj, searchpaths, stage);
-                },
-                _ => (),
-            }
-        }
+    for name in deps {
+        getdep(&name, modules, rootproj, searchpaths, stage)?;
     }
+    Ok(())
 }
 
 
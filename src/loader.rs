@@ -9,6 +9,7 @@ use rayon::prelude::*;
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{Ordering};
 use super::make::Stage;
+use super::Error;
 
 #[derive(Clone)]
 pub enum Module {
@@ -22,7 +23,7 @@ pub fn load(
     src:            &Path,
     features:       &HashMap<String, bool>,
     stage:          &Stage,
-) {
+) -> Result<(), Error> {
 
 
     let mut files = Vec::new();
@@ -59,7 +60,7 @@ pub fn load(
         if !silent{
             pb.lock().unwrap().message(&format!("parsing {:?} ", path));
         }
-        let mut m = parser::parse(&path, features, stage);
+        let mut m = parser::parse(&path, features, stage)?;
         m.name = artifact_name.clone();
         let stem = path.file_stem().unwrap().to_string_lossy().to_string();
         if stem != "lib" {
@@ -69,10 +70,11 @@ pub fn load(
         if !silent{
             pb.lock().unwrap().inc();
         }
-        (m.name.clone(), Module::ZZ(m))
-    }).collect();
+        Ok((m.name.clone(), Module::ZZ(m)))
+    }).collect::<Result<_, Error>>()?;
     modules.extend(om);
     if !silent{
         pb.lock().unwrap().finish_print(&format!("finished parsing {}", artifact_name));
     }
+    Ok(())
 }
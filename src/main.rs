@@ -3,13 +3,12 @@ extern crate clap;
 extern crate env_logger;
 extern crate pbr;
 extern crate rayon;
-extern crate tempdir;
 
 use clap::{App, Arg, SubCommand};
 use std::process::Command;
 use std::sync::atomic::{Ordering};
 use zz;
-use std::io::{Read, Write};
+use std::io::{Write};
 use std::time::{Duration, Instant};
 
 fn main() {
@@ -26,6 +25,8 @@ fn main() {
         .version(clap::crate_version!())
         .setting(clap::AppSettings::UnifiedHelpMessage)
         .arg(Arg::with_name("smt-timeout").takes_value(true).required(false).long("smt-timeout"))
+        .arg(Arg::with_name("define").takes_value(true).multiple(true).number_of_values(1).required(false).short("D").global(true)
+             .help("name=value for #if def(), instead of the one in [defines] in zz.toml"))
         .subcommand(SubCommand::with_name("check").about("check the current project"))
             .arg(Arg::with_name("slow").takes_value(false).required(false).long("slow").short("0"))
             .arg(Arg::with_name("variant").takes_value(true).required(false).long("variant").short("s"))
@@ -36,24 +37,110 @@ fn main() {
             .arg(Arg::with_name("variant").takes_value(true).required(false).long("variant").short("s"))
             .arg(Arg::with_name("release").takes_value(false).required(false).long("release"))
             .arg(Arg::with_name("debug").takes_value(false).required(false).long("debug"))
+            .arg(Arg::with_name("contracts").takes_value(false).required(false).long("contracts")
+                 .help("check where and model clauses at runtime"))
+            .arg(Arg::with_name("profile").takes_value(true).required(false).long("profile")
+                 .help("build with a built in stage or a [profile.<name>] from zz.toml"))
+            .arg(Arg::with_name("watch").takes_value(false).required(false).long("watch")
+                 .help("rebuild whenever a source changes"))
+            .arg(Arg::with_name("amalgamate").takes_value(false).required(false).long("amalgamate")
+                 .help("write each artifact as one C file and one header in target/<stage>/ instead of compiling it"))
+        )
+        .subcommand(SubCommand::with_name("export").about("write a build description of the generated C for another build system")
+            .arg(Arg::with_name("format").takes_value(true).required(true).index(1)
+                 .possible_values(&["cmake", "make", "meson"]))
+            .arg(Arg::with_name("output").takes_value(true).required(false).long("output").short("o")
+                 .help("directory to export to, target/<stage>/export/<format> by default"))
+            .arg(Arg::with_name("variant").takes_value(true).required(false).long("variant").short("s"))
+            .arg(Arg::with_name("release").takes_value(false).required(false).long("release"))
+            .arg(Arg::with_name("debug").takes_value(false).required(false).long("debug"))
+            .arg(Arg::with_name("profile").takes_value(true).required(false).long("profile")
+                 .help("build with a built in stage or a [profile.<name>] from zz.toml"))
+        )
+        .subcommand(SubCommand::with_name("install").about("build and install libraries, executables, headers and a pkg-config file")
+            .arg(Arg::with_name("prefix").takes_value(true).required(false).long("prefix").default_value("/usr/local"))
+            .arg(Arg::with_name("variant").takes_value(true).required(false).long("variant").short("s"))
+            .arg(Arg::with_name("debug").takes_value(false).required(false).long("debug"))
+            .arg(Arg::with_name("profile").takes_value(true).required(false).long("profile")
+                 .help("build with a built in stage or a [profile.<name>] from zz.toml"))
         )
         .subcommand(SubCommand::with_name("clean").about("remove the target directory"))
         .subcommand(SubCommand::with_name("bench").about("benchmark tests/*.zz")
-                    .arg(Arg::with_name("testname").takes_value(true).required(false).index(1)),
+                    .arg(Arg::with_name("testname").takes_value(true).required(false).index(1))
+                    .arg(Arg::with_name("warmup").takes_value(true).required(false).long("warmup").default_value("3")
+                         .help("runs before measuring"))
+                    .arg(Arg::with_name("samples").takes_value(true).required(false).long("samples").default_value("30")
+                         .help("measured runs per testcase"))
+                    .arg(Arg::with_name("save-baseline").takes_value(true).required(false).long("save-baseline")
+                         .help("save the results as target/bench/<name>.json"))
+                    .arg(Arg::with_name("compare").takes_value(true).required(false).long("compare")
+                         .help("compare against the baseline saved as <name>"))
+                    .arg(Arg::with_name("threshold").takes_value(true).required(false).long("threshold").default_value("5")
+                         .help("percent the median may slow down before --compare fails")),
         )
         .subcommand(SubCommand::with_name("test").about("execute tests/*.zz")
-                    .arg(Arg::with_name("testname").takes_value(true).required(false).index(1)),
+                    .arg(Arg::with_name("testname").takes_value(true).required(false).index(1))
+                    .arg(Arg::with_name("doc").takes_value(false).required(false).long("doc")
+                         .help("execute code examples from doc comments instead"))
+                    .arg(Arg::with_name("contracts").takes_value(false).required(false).long("contracts")
+                         .help("check where and model clauses at runtime"))
+                    .arg(Arg::with_name("profile").takes_value(true).required(false).long("profile")
+                         .help("test with a built in stage or a [profile.<name>] from zz.toml"))
+                    .arg(Arg::with_name("sanitize").takes_value(true).required(false).long("sanitize").use_delimiter(true)
+                         .possible_values(&["address", "undefined", "memory", "thread", "leak", "none"])
+                         .help("build with these sanitizers instead, into a target directory of their own"))
+                    .arg(Arg::with_name("fail-fast").takes_value(false).required(false).long("fail-fast")
+                         .help("stop at the first failing test"))
+                    .arg(Arg::with_name("timeout").takes_value(true).required(false).long("timeout").default_value("60")
+                         .help("seconds before a single testcase is killed, 0 for none"))
+                    .arg(Arg::with_name("global-timeout").takes_value(true).required(false).long("global-timeout")
+                         .help("seconds before the whole test run is stopped"))
+                    .arg(Arg::with_name("jobs").takes_value(true).required(false).long("jobs").short("j")
+                         .help("number of tests to run in parallel"))
+                    .arg(Arg::with_name("report").takes_value(true).multiple(true).number_of_values(1).required(false).long("report")
+                         .help("write results to junit=<path.xml> or json=<path.json>"))
+                    .arg(Arg::with_name("watch").takes_value(false).required(false).long("watch")
+                         .help("rerun the tests whenever a source changes"))
+                    .arg(Arg::with_name("coverage").takes_value(false).required(false).long("coverage")
+                         .help("write an lcov and html coverage report of the zz sources"))
+                    .arg(Arg::with_name("bless").takes_value(false).required(false).long("bless")
+                         .help("update expected stdout and stderr of testcases to what the tests printed")),
         )
         .subcommand(SubCommand::with_name("init").about("init zz project in current directory"))
         .subcommand(
             SubCommand::with_name("run").about("build and run")
             .arg(Arg::with_name("release").takes_value(false).required(false).long("release"))
             .arg(Arg::with_name("debug").takes_value(false).required(false).long("debug"))
+            .arg(Arg::with_name("contracts").takes_value(false).required(false).long("contracts")
+                 .help("check where and model clauses at runtime"))
+            .arg(Arg::with_name("profile").takes_value(true).required(false).long("profile")
+                 .help("build with a built in stage or a [profile.<name>] from zz.toml"))
             .arg(Arg::with_name("variant").takes_value(true).required(false).long("variant").short("s"))
             .arg(Arg::with_name("args").takes_value(true).multiple(true).required(false).index(1))
         )
-        .subcommand(SubCommand::with_name("fuzz").about("execute tests/*.zz with afl fuzzer")
-            .arg(Arg::with_name("testname").takes_value(true).required(false).index(1)),
+        .subcommand(SubCommand::with_name("bindgen").about("generate a zz module from a C header")
+            .arg(Arg::with_name("header").takes_value(true).required(true).index(1))
+            .arg(Arg::with_name("output").takes_value(true).required(false).long("output").short("o"))
+            .arg(Arg::with_name("annotations").takes_value(true).required(false).long("annotations").short("a")
+                 .help("toml file with where/model contracts for the imported functions")),
+        )
+        .subcommand(SubCommand::with_name("symbolize").about("rewrite C names in stack traces from stdin into zz names")
+            .arg(Arg::with_name("release").takes_value(false).required(false).long("release"))
+            .arg(Arg::with_name("debug").takes_value(false).required(false).long("debug"))
+            .arg(Arg::with_name("profile").takes_value(true).required(false).long("profile")
+                 .help("build with a built in stage or a [profile.<name>] from zz.toml"))
+        )
+        .subcommand(SubCommand::with_name("fuzz").about("execute tests/*.zz with a fuzzer")
+            .arg(Arg::with_name("testname").takes_value(true).required(false).index(1))
+            .arg(Arg::with_name("contracts").takes_value(false).required(false).long("contracts")
+                 .help("check where and model clauses at runtime"))
+            .arg(Arg::with_name("engine").takes_value(true).required(false).long("engine").short("e")
+                 .possible_values(&["afl", "libfuzzer", "honggfuzz"]).default_value("afl")
+                 .help("fuzzer to build and run with"))
+            .arg(Arg::with_name("max-time").takes_value(true).required(false).long("max-time")
+                 .help("seconds after which the fuzzer stops"))
+            .arg(Arg::with_name("args").takes_value(true).multiple(true).required(false).last(true)
+                 .help("passed on to the fuzzer")),
         )
         .get_matches();
 
@@ -66,20 +153,52 @@ fn main() {
             zz::project::init();
         },
         ("clean", Some(_submatches)) => {
-            let (root, _) = zz::project::load_cwd();
-            if root.join("target").exists() {
-                std::fs::remove_dir_all(root.join("target")).unwrap();
+            let root = project_root();
+            if zz::project::target_dir(&root).exists() {
+                std::fs::remove_dir_all(zz::project::target_dir(&root)).unwrap();
             }
         },
         ("test", Some(submatches))  | ("bench", Some(submatches)) => {
             let bench = matches.subcommand().0 == "bench";
 
+            let doc = !bench && submatches.is_present("doc");
+
             let variant = submatches.value_of("variant").unwrap_or("default");
-            let stage = zz::make::Stage::test();
-            zz::build(zz::BuildSet::Tests, variant, stage.clone(), false);
-            let (root, mut project) = zz::project::load_cwd();
+            let mut stage = if let Some(name) = submatches.value_of("profile") {
+                profile(name)
+            } else if !bench && submatches.is_present("contracts") {
+                zz::make::Stage::contracts()
+            } else {
+                zz::make::Stage::test()
+            };
+            if let Some(sanitize) = submatches.values_of("sanitize") {
+                stage = sanitized(stage, sanitize.filter(|s| *s != "none").collect());
+            }
+            let coverage = !bench && submatches.is_present("coverage");
+            if coverage {
+                stage.name      = format!("{}-coverage", stage.name);
+                stage.coverage  = true;
+            }
+            if !bench && submatches.is_present("watch") {
+                zz::watch::watch(&project_root(), &stage.to_string(), &without_watch());
+            }
+            if let Some(ws) = or_report(zz::project::workspace_cwd()) {
+                members(&ws, true);
+            }
+            or_exit(session(submatches, variant, &stage).build(if doc { zz::BuildSet::Doctests } else { zz::BuildSet::Tests }));
+            let (root, mut project) = or_report(zz::project::load_cwd());
+
+            let artifacts = if doc {
+                zz::doctest::load(&root, &stage).into_iter()
+                    .filter(|t|!t.should_fail)
+                    .map(|t|t.artifact())
+                    .collect()
+            } else {
+                std::mem::replace(&mut project.artifacts, None).expect("no artifacts")
+            };
 
-            for artifact in std::mem::replace(&mut project.artifacts, None).expect("no artifacts") {
+            let mut jobs = Vec::new();
+            for artifact in artifacts {
                 if let zz::project::ArtifactType::Test = artifact.typ {
                     if let Some(testname) = submatches.value_of("testname") {
                         if testname != artifact.name {
@@ -89,146 +208,139 @@ fn main() {
                         }
                     }
 
-
-                    let casedir = root.join("target").join(stage.to_string()).join("testcases").join(format!("{}",artifact.main.replace("::","_")));
-                    let mut cases = Vec::new();
-                    match std::fs::read_dir(casedir) {
-                        Err(_) => (),
-                        Ok(dir) => {
-                            for entry in dir {
-                                let entry = match entry {
-                                    Ok(v) => v,
-                                    Err(_) => continue,
-                                };
-                                let path = entry.path();
-                                let mut stdin  = None;
-                                let mut stdout = None;
-                                let mut exit  = 0;
-                                match std::fs::File::open(path.join("stdin")) {
-                                    Ok(mut f) => {
-                                        let mut v = Vec::new();
-                                        f.read_to_end(&mut v).unwrap();
-                                        v.retain(|&i|i!=b'\r');
-                                        stdin = Some(v);
-                                    },
-                                    Err(_) => {eprintln!("stdin testfile not found {}", path.to_string_lossy());}
-                                }
-                                match std::fs::File::open(path.join("stdout")) {
-                                    Ok(mut f) => {
-                                        let mut v = Vec::new();
-                                        f.read_to_end(&mut v).unwrap();
-                                        v.retain(|&i|i!=b'\r');
-                                        stdout = Some(v);
-                                    },
-                                    Err(_) => {eprintln!("stdout testfile not found {}", path.to_string_lossy());}
-                                }
-
-                                match std::fs::File::open(path.join("exit")) {
-                                    Ok(mut f) => {
-                                        let mut v = String::new();
-                                        f.read_to_string(&mut v).unwrap();
-                                        exit = v.parse().unwrap_or(0);
-                                    },
-                                    Err(_) => {}
-                                }
-                                cases.push((
-                                        entry.file_name().to_string_lossy().to_string(),
-                                        stdin,
-                                        stdout,
-                                        exit
-                                ));
-                            }
+                    let casedir = zz::project::target_dir(&root).join(stage.to_string()).join("testcases").join(format!("{}",artifact.main.replace("::","_")));
+                    let mut cases = zz::testrunner::load_cases(&casedir);
+                    if cases.is_empty() {
+                        cases.push(zz::testrunner::Case{name: "default".to_string(), ..Default::default()});
+                    }
+                    if coverage {
+                        for case in &mut cases {
+                            let profile = zz::coverage::profile_file(&root, &stage.to_string(), &artifact.name, &case.name);
+                            case.env.push(("LLVM_PROFILE_FILE".to_string(), profile));
                         }
                     }
 
-                    if cases.is_empty() {
-                        cases.push(("default".to_string(), None, None, 0));
+                    jobs.push(zz::testrunner::Job{
+                        exe:        zz::project::target_dir(&root).join(stage.to_string()).join("bin").join(&artifact.name),
+                        artifact:   artifact.name,
+                        cases,
+                    });
+                }
+            }
+
+            if bench {
+                let opts = zz::bench::Options{
+                    warmup:     number(submatches, "warmup"),
+                    samples:    number(submatches, "samples"),
+                    threshold:  number(submatches, "threshold"),
+                };
+
+                let baseline = submatches.value_of("compare").map(|name| {
+                    zz::bench::load(&root, name).unwrap_or_else(||{
+                        error!("no baseline '{}' in {:?}", name, zz::bench::dir(&root));
+                        std::process::exit(9);
+                    })
+                });
+
+                let current = match zz::bench::run(&jobs, &opts) {
+                    Some(v) => v,
+                    None    => std::process::exit(10),
+                };
+
+                zz::bench::save(&root, "latest", &current);
+                if let Some(name) = submatches.value_of("save-baseline") {
+                    zz::bench::save(&root, name, &current);
+                }
+
+                if let Some(baseline) = baseline {
+                    if !zz::bench::compare(&baseline, &current, opts.threshold) {
+                        std::process::exit(10);
                     }
+                }
+                return;
+            }
 
-                    for case in &cases {
-                        let running = root.join("target").join(stage.to_string()).join("bin").join(&artifact.name);
-                        println!("running \"{}\"\n", running.to_string_lossy());
-                        let start = Instant::now();
-                        let mut average = 0;
-                        loop {
-                            let istart = Instant::now();
-                            let mut child = Command::new(&running)
-                                .stdin(std::process::Stdio::piped())
-                                .stdout(std::process::Stdio::piped())
-                                .spawn()
-                                .expect("failed to execute process");
-
-                            if let Some(i) = &case.1 {
-                                let stdin = child.stdin.as_mut().expect("Failed to open stdin");
-                                stdin.write_all(&i).unwrap();
-                            }
-                            let output = child.wait_with_output().expect("Failed to read stdout");
-                            average = (average + istart.elapsed().as_millis()) / 2;
-
-                            match output.status.code() {
-                                Some(c) => {
-                                    if c != case.3 {
-                                        error!("FAIL {}::{} exit: {} instead of: {}", artifact.name, case.0, c, case.3);
-                                        std::process::exit(10);
-                                    }
-                                }
-                                _ => {
-                                    #[cfg(unix)]
-                                    {
-                                        use std::os::unix::process::ExitStatusExt;
-                                        error!("FAIL {}::{} died by signal {}", artifact.name, case.0, output.status.signal().unwrap());
-                                    }
-                                    #[cfg(not(unix))]
-                                    {
-                                        error!("FAIL {}::{} died by signal", artifact.name, case.0);
-                                    }
-                                    std::process::exit(10);
-                                }
-                            }
-                            if let Some(expect_stdout) = &case.2 {
-                                let mut output_stdout = output.stdout;
-                                output_stdout.retain(|&i|i!=b'\r');
-                                if &output_stdout != expect_stdout {
-                                    error!("FAIL {} {} \nstdout expected:\n<{}>({})\nbut got:\n<{}>({})\n",
-                                           artifact.name,
-                                           case.0,
-                                           String::from_utf8_lossy(&expect_stdout),
-                                           expect_stdout.len(),
-                                           String::from_utf8_lossy(&output_stdout),
-                                           output_stdout.len()
-                                          );
-                                    std::process::exit(10);
-                                }
-                            }
-                            if bench {
-                                if start.elapsed().as_secs() > 0 {
-                                    info!("PASS {} {} {}ms/iter", artifact.name, case.0, average);
-                                    break;
-                                } else {
-                                    continue;
-                                }
-                            } else {
-                                info!("PASS {} {} in {}ms", artifact.name, case.0, average);
-                                break;
-                            }
-                        }
+            let seconds = |name| submatches.value_of(name).map(|v: &str| {
+                let v : u64 = v.parse().unwrap_or_else(|_|{
+                    error!("--{} expects seconds, not '{}'", name, v);
+                    std::process::exit(9);
+                });
+                Duration::from_secs(v)
+            }).filter(|d| d.as_secs() > 0);
+
+            let jobs_arg = submatches.value_of("jobs").map(|v| v.parse().unwrap_or_else(|_|{
+                error!("--jobs expects a number, not '{}'", v);
+                std::process::exit(9);
+            }));
+
+            let opts = zz::testrunner::Options{
+                timeout:        seconds("timeout"),
+                global_timeout: seconds("global-timeout"),
+                fail_fast:      submatches.is_present("fail-fast"),
+                jobs:           jobs_arg,
+            };
+
+            let start   = Instant::now();
+            if coverage {
+                zz::coverage::clean(&root, &stage.to_string());
+            }
+            let mut results = zz::testrunner::run(&jobs, &opts);
+            let elapsed = start.elapsed();
+
+            if submatches.is_present("bless") {
+                zz::testrunner::bless(&mut results);
+            }
 
+            for report in submatches.values_of("report").unwrap_or_default() {
+                let (r, path) = match report.find('=') {
+                    Some(i) => (&report[..i], std::path::Path::new(&report[i+1..])),
+                    None => {
+                        error!("--report expects junit=<path> or json=<path>, not '{}'", report);
+                        std::process::exit(9);
                     }
+                };
+                let written = match r {
+                    "junit" => zz::testrunner::report_junit(path, &results, elapsed),
+                    "json"  => zz::testrunner::report_json(path, &results, elapsed),
+                    _ => {
+                        error!("unknown report format '{}', expected junit or json", r);
+                        std::process::exit(9);
+                    }
+                };
+                if let Err(e) = written {
+                    error!("cannot write report {:?}: {}", path, e);
+                    std::process::exit(9);
                 }
             }
 
+            if coverage {
+                let exes : Vec<_> = jobs.iter().map(|j| j.exe.clone()).collect();
+                match zz::coverage::report(&root, &stage.to_string(), &exes) {
+                    Ok((hit, found)) => info!("coverage: {} of {} lines, report in {:?}",
+                        hit, found, zz::coverage::dir(&root, &stage.to_string()).join("html").join("index.html")),
+                    Err(e) => error!("coverage: {}", e),
+                }
+            }
+
+            if !zz::testrunner::summary(&results, elapsed) {
+                std::process::exit(10);
+            }
         }
         ("run", Some(submatches)) => {
-            let stage = if submatches.is_present("release") {
+            let stage = if let Some(name) = submatches.value_of("profile") {
+                profile(name)
+            } else if submatches.is_present("release") {
                 zz::make::Stage::release()
             } else if submatches.is_present("debug") {
                 zz::make::Stage::debug()
+            } else if submatches.is_present("contracts") {
+                zz::make::Stage::contracts()
             } else {
                 zz::make::Stage::test()
             };
             let variant = submatches.value_of("variant").unwrap_or("default");
-            zz::build(zz::BuildSet::Run, variant, stage.clone(), false);
-            let (root, mut project) = zz::project::load_cwd();
+            or_exit(session(submatches, variant, &stage).build(zz::BuildSet::Run));
+            let (root, mut project) = or_report(zz::project::load_cwd());
 
             let mut exes = Vec::new();
             for artifact in std::mem::replace(&mut project.artifacts, None).expect("no artifacts") {
@@ -245,19 +357,33 @@ fn main() {
                 std::process::exit(9);
             }
 
-            let running = root.join("target").join(stage.to_string()).join("bin").join(&exes[0].name);
+            let running = zz::project::target_dir(&root).join(stage.to_string()).join("bin").join(&exes[0].name);
             println!("running \"{}\"\n", running.to_string_lossy());
             let status = Command::new(running)
                 .args(submatches.values_of("args").unwrap_or_default())
                 .status()
                 .expect("failed to execute process");
+            // killed by a signal, like an aborting contract check. exit like a shell would
+            #[cfg(unix)]
+            {
+                use std::os::unix::process::ExitStatusExt;
+                if let Some(signal) = status.signal() {
+                    error!("{} was killed by signal {}", exes[0].name, signal);
+                    std::process::exit(128 + signal);
+                }
+            }
             std::process::exit(status.code().expect("failed to execute process"));
         },
         ("fuzz", Some(submatches)) => {
             let variant = submatches.value_of("variant").unwrap_or("default");
-            let stage = zz::make::Stage::fuzz();
-            zz::build(zz::BuildSet::Tests, variant, stage.clone(), false);
-            let (root, mut project) = zz::project::load_cwd();
+            let engine : zz::make::Fuzzer = submatches.value_of("engine").unwrap().parse().unwrap();
+            let mut stage = zz::make::Stage::fuzz(engine);
+            if submatches.is_present("contracts") {
+                stage.name      = format!("{}-contracts", stage.name);
+                stage.contracts = true;
+            }
+            or_exit(session(submatches, variant, &stage).build(zz::BuildSet::Tests));
+            let (root, mut project) = or_report(zz::project::load_cwd());
 
 
 
@@ -265,7 +391,7 @@ fn main() {
             for artifact in std::mem::replace(&mut project.artifacts, None).expect("no artifacts") {
                 if let zz::project::ArtifactType::Test = artifact.typ {
                     match submatches.value_of("testname") {
-                        Some(v) if v == artifact.name => {
+                        Some(v) if v == artifact.name || format!("tests_{}", v) == artifact.name => {
                             exes.push((artifact.name, artifact.main));
                         },
                         Some(_) => {
@@ -294,71 +420,282 @@ fn main() {
                 std::process::exit(1);
             }
 
-            let indir = tempdir::TempDir::new("zzfuzz").unwrap();
-            let casedir = root.join("target").join(stage.to_string()).join("testcases").join(format!("_{}", &exes[0].1));
-            let mut havesome = false;
-            match std::fs::read_dir(casedir) {
-                Err(_) => (),
-                Ok(dir) => {
-                    for entry in dir {
-                        let entry = match entry {
-                            Ok(v) => v,
-                            Err(_) => continue,
-                        };
-                        let path = entry.path();
-                        let stdin = path.join("stdin");
-                        if stdin.exists() {
-                            havesome = true;
-                            std::fs::copy(&stdin, indir.path().join(path.file_name().unwrap())).unwrap();
-                        }
-                    }
-                }
-            }
-
-            if !havesome {
-                eprintln!("the test {} has no testcases with stdin", exes[0].0);
-                std::process::exit(1);
-            }
+            let (name, main) = exes.remove(0);
+            let exe  = zz::project::target_dir(&root).join(stage.to_string()).join("bin").join(&name);
+            let dirs = zz::fuzz::Dirs::new(&root, &stage.to_string(), &name);
 
-            let outdir = root.join("target").join(stage.to_string()).join(&exes[0].1);
-            std::fs::create_dir_all(&outdir).unwrap();
+            let casedir = zz::project::target_dir(&root).join(stage.to_string()).join("testcases").join(main.replace("::","_"));
+            let seeds = zz::fuzz::seed(&dirs.corpus, &zz::testrunner::load_cases(&casedir));
+            info!("seeded corpus with {} testcases", seeds);
 
-            println!("fuzzer output in {}", outdir.to_string_lossy());
+            let max_time = submatches.value_of("max-time").map(|v| v.parse().unwrap_or_else(|_|{
+                error!("--max-time expects seconds, not '{}'", v);
+                std::process::exit(9);
+            }));
+            let extra : Vec<String> = submatches.values_of("args").map(|v|v.map(String::from).collect()).unwrap_or_default();
 
-            let mut child = Command::new("afl-fuzz")
-                .arg("-m30000")
-                .arg("-i")
-                .arg(indir.path())
-                .arg("-o")
-                .arg(&outdir)
-                .arg(root.join("target").join(stage.to_string()).join("bin").join(&exes[0].0))
-                .spawn()
-                .expect("failed to execute process");
-            child.wait().unwrap();
+            println!("fuzzer output in {}", dirs.corpus.parent().unwrap().to_string_lossy());
+            zz::fuzz::run(engine, &exe, &dirs, max_time, &extra);
 
-            println!("\n\nfuzzer output in {}", outdir.to_string_lossy());
+            let testfile = root.join("tests").join(format!("{}.zz", main.split("::").last().unwrap()));
+            let added = zz::fuzz::triage(engine, &exe, &dirs, &testfile);
+            if !added.is_empty() {
+                println!("\n\n{} new crashes added to {}", added.len(), testfile.to_string_lossy());
+                std::process::exit(10);
+            }
             return;
 
         },
+        ("bindgen", Some(submatches)) => {
+            let header = submatches.value_of("header").unwrap();
+            let annotations = submatches.value_of("annotations").map(std::path::Path::new);
+            let zz = or_report(zz::bindgen::bindgen(header, annotations));
+            match submatches.value_of("output") {
+                Some(out) => {
+                    std::fs::write(out, zz).expect(&format!("cannot write {}", out));
+                    info!("wrote {}", out);
+                },
+                None => print!("{}", zz),
+            }
+        },
+        ("symbolize", Some(submatches)) => {
+            let stage = if let Some(name) = submatches.value_of("profile") {
+                profile(name)
+            } else if submatches.is_present("release") {
+                zz::make::Stage::release()
+            } else if submatches.is_present("debug") {
+                zz::make::Stage::debug()
+            } else {
+                zz::make::Stage::test()
+            };
+            let (root, _) = or_report(zz::project::load_cwd());
+            let symbols = zz::emitter_debug::symbols(&root, &stage);
+            if symbols.is_empty() {
+                warn!("no symbols in target/{}, build first", stage);
+            }
+
+            let stdin  = std::io::stdin();
+            let stdout = std::io::stdout();
+            let mut stdout = stdout.lock();
+            for line in std::io::BufRead::lines(stdin.lock()) {
+                let line = match line {
+                    Ok(v) => v,
+                    Err(_) => break,
+                };
+                if writeln!(stdout, "{}", zz::emitter_debug::symbolize(&line, &symbols)).is_err() {
+                    break;
+                }
+            }
+        },
         ("check", Some(submatches)) => {
             zz::parser::ERRORS_AS_JSON.store(true, Ordering::SeqCst);
-            zz::build(zz::BuildSet::Check, submatches.value_of("variant").unwrap_or("default"), zz::make::Stage::test(), false)
+            or_exit(session(submatches, submatches.value_of("variant").unwrap_or("default"), &zz::make::Stage::test()).check())
         },
         ("build", Some(submatches)) => {
-            let stage = if submatches.is_present("release") {
+            let stage = if let Some(name) = submatches.value_of("profile") {
+                profile(name)
+            } else if submatches.is_present("release") {
                 zz::make::Stage::release()
             } else if submatches.is_present("debug") {
                 zz::make::Stage::debug()
+            } else if submatches.is_present("contracts") {
+                zz::make::Stage::contracts()
             } else {
                 zz::make::Stage::test()
             };
 
-            zz::build(zz::BuildSet::All, submatches.value_of("variant").unwrap_or("default"), stage, submatches.is_present("slow"))
+            if submatches.is_present("watch") {
+                zz::watch::watch(&project_root(), &stage.to_string(), &without_watch());
+            }
+            if let Some(ws) = or_report(zz::project::workspace_cwd()) {
+                members(&ws, false);
+            }
+
+            let buildset = if submatches.is_present("amalgamate") {
+                zz::BuildSet::Amalgamate
+            } else {
+                zz::BuildSet::All
+            };
+            or_exit(session(submatches, submatches.value_of("variant").unwrap_or("default"), &stage)
+                .slow(submatches.is_present("slow"))
+                .build(buildset));
+        },
+        ("export", Some(submatches)) => {
+            let stage = if let Some(name) = submatches.value_of("profile") {
+                profile(name)
+            } else if submatches.is_present("release") {
+                zz::make::Stage::release()
+            } else if submatches.is_present("debug") {
+                zz::make::Stage::debug()
+            } else {
+                zz::make::Stage::test()
+            };
+            let format : zz::export::Format = submatches.value_of("format").unwrap().parse().unwrap();
+
+            // nothing is compiled, the other build system does that
+            let targets = or_exit(session(submatches, submatches.value_of("variant").unwrap_or("default"), &stage).build(zz::BuildSet::Check));
+            let (root, project) = or_report(zz::project::load_cwd());
+
+            let out = match submatches.value_of("output") {
+                Some(v) => std::path::PathBuf::from(v),
+                None => zz::project::target_dir(&root).join(stage.to_string()).join("export").join(format.to_string()),
+            };
+            match zz::export::export(&root, &project.project.name, &stage, format, &targets, &out) {
+                Ok(path) => info!("exported {}", path.display()),
+                Err(e) => {
+                    error!("{}", e);
+                    std::process::exit(9);
+                }
+            }
+        },
+        ("install", Some(submatches)) => {
+            let stage = if let Some(name) = submatches.value_of("profile") {
+                profile(name)
+            } else if submatches.is_present("debug") {
+                zz::make::Stage::debug()
+            } else {
+                zz::make::Stage::release()
+            };
+            if let Some(ws) = or_report(zz::project::workspace_cwd()) {
+                members(&ws, false);
+            }
+
+            let targets = or_exit(session(submatches, submatches.value_of("variant").unwrap_or("default"), &stage).build(zz::BuildSet::All));
+            let (root, project) = or_report(zz::project::load_cwd());
+
+            let prefix = std::env::current_dir().expect("current directory").join(submatches.value_of("prefix").unwrap());
+            // the usual way of staging an install for a package
+            let destdir = std::env::var_os("DESTDIR").filter(|d| !d.is_empty()).map(std::path::PathBuf::from);
+            match zz::install::install(&root, &project, &stage, &targets, &prefix, destdir.as_ref().map(|d| d.as_path())) {
+                Ok(installed) => {
+                    for path in installed {
+                        info!("installed {}", path.display());
+                    }
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    std::process::exit(9);
+                }
+            }
         },
         ("", None) => {
-            zz::build(zz::BuildSet::All, "default", zz::make::Stage::test(), false);
+            or_exit(session(&matches, "default", &zz::make::Stage::test()).build(zz::BuildSet::All));
         },
         _ => unreachable!(),
     }
 }
 
+// --profile picks a built in stage, or one that only a [profile.<name>] in zz.toml defines.
+// in a workspace, every member checks its own
+fn profile(name: &str) -> zz::make::Stage {
+    if zz::make::Stage::builtin(name).is_none() && or_report(zz::project::workspace_cwd()).is_none() {
+        let (_, config) = or_report(zz::project::load_cwd());
+        if !config.profile.contains_key(name) {
+            error!("no profile named '{}' in zz.toml", name);
+            std::process::exit(9);
+        }
+    }
+    zz::make::Stage::named(name)
+}
+
+// the stage's own profile goes first, the sanitizers from the command line replace what it has
+fn sanitized(stage: zz::make::Stage, names: Vec<&str>) -> zz::make::Stage {
+    let stage = if or_report(zz::project::workspace_cwd()).is_none() {
+        stage.configure(&or_report(zz::project::load_cwd()).1)
+    } else {
+        Ok(stage)
+    };
+    let sanitize : Vec<zz::make::Sanitizer> = names.iter().map(|s| s.parse().unwrap()).collect();
+    match stage.and_then(|s| s.sanitized(&sanitize)) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(9);
+        }
+    }
+}
+
+// with the -D defines of the command line
+fn session(m: &clap::ArgMatches, variant: &str, stage: &zz::make::Stage) -> zz::Session {
+    let mut stage = stage.clone();
+    for define in m.values_of("define").into_iter().flatten() {
+        // a bare name is 1, like in C
+        let (name, value) = match define.find('=') {
+            Some(i) => (&define[..i], &define[i+1..]),
+            None    => (define, "1"),
+        };
+        if name.is_empty() {
+            error!("-D expects name=value, not '{}'", define);
+            std::process::exit(9);
+        }
+        stage = stage.define(name, value);
+    }
+    zz::Session::new(".").variant(variant).stage(stage)
+}
+
+// errors have been reported by the time a stage returns them
+fn or_exit<T>(r: Result<T, zz::Error>) -> T {
+    match r {
+        Ok(v) => v,
+        Err(e) => std::process::exit(e.code()),
+    }
+}
+
+// for everything that is not a stage, which leaves reporting to the caller
+fn or_report<T>(r: Result<T, zz::Error>) -> T {
+    match r {
+        Ok(v) => v,
+        Err(e) => {
+            zz::diagnostics::error(&e);
+            std::process::exit(e.code());
+        }
+    }
+}
+
+fn number<T: std::str::FromStr>(m: &clap::ArgMatches, name: &str) -> T {
+    let v = m.value_of(name).unwrap();
+    v.parse().unwrap_or_else(|_|{
+        error!("--{} expects a number, not '{}'", name, v);
+        std::process::exit(9);
+    })
+}
+
+// the command line this process was started with, minus --watch
+fn without_watch() -> Vec<String> {
+    std::env::args().skip(1).filter(|a| a != "--watch").collect()
+}
+
+// the workspace root, or the root of the project in the current directory
+fn project_root() -> std::path::PathBuf {
+    match or_report(zz::project::workspace_cwd()) {
+        Some(ws) => ws.root,
+        None => or_report(zz::project::load_cwd()).0,
+    }
+}
+
+// run the same command in every member of the workspace, dependencies first.
+// a failed build stops there, failed tests don't stop the other members from testing
+fn members(ws: &zz::project::Members, keep_going: bool) -> ! {
+    let exe  = std::env::current_exe().expect("self path");
+    let args : Vec<String> = std::env::args().skip(1).collect();
+    let mut failed = Vec::new();
+    for (name, dir) in &ws.members {
+        info!("member {}", name);
+        let status = Command::new(&exe)
+            .args(&args)
+            .current_dir(dir)
+            .status()
+            .expect("failed to execute zz");
+        if !status.success() {
+            failed.push(name.clone());
+            if !keep_going {
+                break;
+            }
+        }
+    }
+    if !failed.is_empty() {
+        error!("failed: {}", failed.join(", "));
+        std::process::exit(10);
+    }
+    std::process::exit(0);
+}
@@ -1,15 +1,15 @@
-use super::project::{Config, Artifact};
+use super::project::{Config, Artifact, Profile};
 use std::hash::{Hasher};
 use metrohash::{MetroHash128};
 use std::path::Path;
 use std::path::PathBuf;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::process::Command;
 use pbr;
 use std::sync::atomic::{AtomicBool, Ordering};
 use super::emitter_js;
+use super::{diagnostics, Error};
 
-static ABORT:           AtomicBool = AtomicBool::new(false);
 pub static BUILD_RS:    AtomicBool = AtomicBool::new(false);
 
 
@@ -19,9 +19,22 @@ pub struct Stage {
     pub debug:      bool,
     pub optimize:   Option<String>,
     pub lto:        bool,
-    pub asan:       bool,
-    pub fuzz:       bool,
+    pub sanitize:   Vec<Sanitizer>,
+    pub fuzz:       Option<Fuzzer>,
     pub pic:        bool,
+    pub contracts:  bool,
+    pub coverage:   bool,
+    pub prove:      bool,
+    pub cflags:     Vec<String>,
+    pub lflags:     Vec<String>,
+    /// what #if def() sees, from -D on the command line and [defines] in zz.toml
+    pub defines:    BTreeMap<String, String>,
+    /// the target directory of the project being built, which has a directory per stage
+    pub target:     PathBuf,
+    /// artifact features the modules are parsed with, on top of the variant's
+    pub features:   Vec<String>,
+    // zz.toml's profile has been applied
+    configured:     bool,
 }
 
 impl Stage {
@@ -31,9 +44,18 @@ impl Stage {
             debug:      false,
             optimize:   Some("03".to_string()),
             lto:        true,
-            asan:       false,
-            fuzz:       false,
+            sanitize:   Vec::new(),
+            fuzz:       None,
             pic:        !cfg!(windows),
+            contracts:  false,
+            coverage:   false,
+            prove:      true,
+            cflags:     Vec::new(),
+            lflags:     Vec::new(),
+            defines:    BTreeMap::new(),
+            configured: false,
+            target:     PathBuf::from("target"),
+            features:   Vec::new(),
         }
     }
     pub fn test() -> Self {
@@ -42,9 +64,18 @@ impl Stage {
             debug:      true,
             optimize:   None,
             lto:        false,
-            asan:       true,
-            fuzz:       false,
+            sanitize:   vec![Sanitizer::Address],
+            fuzz:       None,
             pic:        !cfg!(windows),
+            contracts:  false,
+            coverage:   false,
+            prove:      true,
+            cflags:     Vec::new(),
+            lflags:     Vec::new(),
+            defines:    BTreeMap::new(),
+            configured: false,
+            target:     PathBuf::from("target"),
+            features:   Vec::new(),
         }
     }
     pub fn debug() -> Self {
@@ -53,20 +84,257 @@ impl Stage {
             debug:      true,
             optimize:   Some("03".to_string()),
             lto:        false,
-            asan:       false,
-            fuzz:       false,
+            sanitize:   Vec::new(),
+            fuzz:       None,
+            pic:        !cfg!(windows),
+            contracts:  false,
+            coverage:   false,
+            prove:      true,
+            cflags:     Vec::new(),
+            lflags:     Vec::new(),
+            defines:    BTreeMap::new(),
+            configured: false,
+            target:     PathBuf::from("target"),
+            features:   Vec::new(),
+        }
+    }
+    pub fn fuzz(fuzzer: Fuzzer) -> Self {
+        Stage {
+            name:       format!("fuzz-{}", fuzzer),
+            debug:      true,
+            optimize:   None,
+            lto:        false,
+            sanitize:   vec![Sanitizer::Address],
+            fuzz:       Some(fuzzer),
             pic:        !cfg!(windows),
+            contracts:  false,
+            coverage:   false,
+            prove:      true,
+            cflags:     Vec::new(),
+            lflags:     Vec::new(),
+            defines:    BTreeMap::new(),
+            configured: false,
+            target:     PathBuf::from("target"),
+            features:   Vec::new(),
         }
     }
-    pub fn fuzz() -> Self {
+    // like test, but the emitted C also checks contracts at runtime,
+    // for callers the symbolic executor cannot see, such as C code
+    pub fn contracts() -> Self {
         Stage {
-            name:       "fuzz".to_string(),
+            name:       "contracts".to_string(),
             debug:      true,
             optimize:   None,
             lto:        false,
-            asan:       true,
-            fuzz:       true,
+            sanitize:   vec![Sanitizer::Address],
+            fuzz:       None,
             pic:        !cfg!(windows),
+            contracts:  true,
+            coverage:   false,
+            prove:      true,
+            cflags:     Vec::new(),
+            lflags:     Vec::new(),
+            defines:    BTreeMap::new(),
+            configured: false,
+            target:     PathBuf::from("target"),
+            features:   Vec::new(),
+        }
+    }
+
+    /// release, test, debug or contracts
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "release"   => Some(Stage::release()),
+            "test"      => Some(Stage::test()),
+            "debug"     => Some(Stage::debug()),
+            "contracts" => Some(Stage::contracts()),
+            _           => None,
+        }
+    }
+
+    /// where modules are emitted to. modules parsed with other features are emitted apart,
+    /// the artifacts built from them are not
+    pub fn modules(&self) -> PathBuf {
+        let dir = self.target.join(self.to_string());
+        if self.features.is_empty() {
+            dir
+        } else {
+            dir.join("features").join(self.features.join("+"))
+        }
+    }
+
+    /// the stage selected with --profile. anything that is not built in is only known from zz.toml,
+    /// and starts out as test until configure() applies its profile
+    pub fn named(name: &str) -> Self {
+        match Stage::builtin(name) {
+            Some(stage) => stage,
+            None => Stage {
+                name: name.to_string(),
+                ..Stage::test()
+            },
+        }
+    }
+
+    /// apply [profile.<name>] from zz.toml, if there is one for this stage.
+    /// only the first call does anything, so a stage can be configured before it is changed further
+    pub fn configure(mut self, config: &Config) -> Result<Self, String> {
+        if self.configured {
+            return Ok(self);
+        }
+        self.configured = true;
+        // the command line overrides zz.toml
+        for (name, value) in &config.defines {
+            let value = match value {
+                toml::Value::String(s)  => s.clone(),
+                toml::Value::Integer(i) => i.to_string(),
+                toml::Value::Boolean(b) => b.to_string(),
+                v => return Err(format!("define {} must be a string, integer or boolean, not {}", name, v)),
+            };
+            self.defines.entry(name.clone()).or_insert(value);
+        }
+        let profile : &Profile = match config.profile.get(&self.name) {
+            Some(p) => p,
+            None => return Ok(self),
+        };
+
+        let mut stage = match &profile.inherits {
+            Some(base) => Stage {
+                name: self.name.clone(),
+                defines: self.defines.clone(),
+                target: self.target.clone(),
+                features: self.features.clone(),
+                configured: true,
+                ..Stage::builtin(base).ok_or(format!("profile {} inherits from unknown stage '{}'", self.name, base))?
+            },
+            None => self,
+        };
+
+        match &profile.optimize {
+            None => (),
+            Some(toml::Value::Integer(i)) => stage.optimize = Some(i.to_string()),
+            Some(toml::Value::String(s)) if s.is_empty() => stage.optimize = None,
+            Some(toml::Value::String(s)) => stage.optimize = Some(s.clone()),
+            Some(toml::Value::Boolean(false)) => stage.optimize = None,
+            Some(o) => return Err(format!("profile {}: optimize must be a level like 2 or \"s\", not {}", stage.name, o)),
+        }
+        if let Some(v) = profile.lto   { stage.lto   = v; }
+        if let Some(v) = profile.asan  {
+            stage.sanitize.retain(|s| *s != Sanitizer::Address);
+            if v {
+                stage.sanitize.push(Sanitizer::Address);
+            }
+        }
+        if let Some(v) = profile.debug { stage.debug = v; }
+        if let Some(v) = profile.pic   { stage.pic   = v; }
+        if let Some(v) = profile.prove { stage.prove = v; }
+        if let Some(v) = &profile.sanitize {
+            stage.sanitize = v.iter().map(|s| s.parse()).collect::<Result<_,_>>()
+                .map_err(|e| format!("profile {}: {}", stage.name, e))?;
+        }
+        check_sanitizers(&mut stage.sanitize)?;
+        stage.cflags.extend(profile.cflags.iter().cloned());
+        stage.lflags.extend(profile.lflags.iter().cloned());
+        Ok(stage)
+    }
+
+    /// name=value for #if def(), as -D passes it
+    pub fn define(mut self, name: &str, value: &str) -> Self {
+        self.defines.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// the same stage built with other sanitizers, in a directory of its own,
+    /// so switching between them does not throw away each other's objects
+    pub fn sanitized(mut self, sanitize: &[Sanitizer]) -> Result<Self, String> {
+        let mut sanitize = sanitize.to_vec();
+        check_sanitizers(&mut sanitize)?;
+        if sanitize != self.sanitize {
+            let names : Vec<String> = sanitize.iter().map(|s| s.to_string()).collect();
+            self.name = format!("{}-{}", self.name, if names.is_empty() { "nosan".to_string() } else { names.join("-") });
+            self.sanitize = sanitize;
+        }
+        Ok(self)
+    }
+}
+
+// sorted, once each, and only ones that can run in the same binary
+fn check_sanitizers(sanitize: &mut Vec<Sanitizer>) -> Result<(), String> {
+    sanitize.sort();
+    sanitize.dedup();
+    for (a, b) in &[
+        (Sanitizer::Address, Sanitizer::Memory),
+        (Sanitizer::Address, Sanitizer::Thread),
+        (Sanitizer::Memory,  Sanitizer::Thread),
+        (Sanitizer::Memory,  Sanitizer::Leak),
+        (Sanitizer::Thread,  Sanitizer::Leak),
+    ] {
+        if sanitize.contains(a) && sanitize.contains(b) {
+            return Err(format!("the {} and {} sanitizers cannot be used together", a, b));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Sanitizer {
+    Address,
+    Undefined,
+    Memory,
+    Thread,
+    Leak,
+}
+
+impl std::str::FromStr for Sanitizer {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "address"   => Ok(Sanitizer::Address),
+            "undefined" => Ok(Sanitizer::Undefined),
+            "memory"    => Ok(Sanitizer::Memory),
+            "thread"    => Ok(Sanitizer::Thread),
+            "leak"      => Ok(Sanitizer::Leak),
+            _           => Err(format!("unknown sanitizer '{}', expected address, undefined, memory, thread or leak", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Sanitizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Sanitizer::Address      => write!(f, "address"),
+            Sanitizer::Undefined    => write!(f, "undefined"),
+            Sanitizer::Memory       => write!(f, "memory"),
+            Sanitizer::Thread       => write!(f, "thread"),
+            Sanitizer::Leak         => write!(f, "leak"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Fuzzer {
+    Afl,
+    Libfuzzer,
+    Honggfuzz,
+}
+
+impl std::str::FromStr for Fuzzer {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "afl"       => Ok(Fuzzer::Afl),
+            "libfuzzer" => Ok(Fuzzer::Libfuzzer),
+            "honggfuzz" => Ok(Fuzzer::Honggfuzz),
+            _           => Err(format!("unknown fuzzer '{}', expected afl, libfuzzer or honggfuzz", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Fuzzer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fuzzer::Afl         => write!(f, "afl"),
+            Fuzzer::Libfuzzer   => write!(f, "libfuzzer"),
+            Fuzzer::Honggfuzz   => write!(f, "honggfuzz"),
         }
     }
 }
@@ -80,12 +348,32 @@ impl std::fmt::Display for  Stage {
 
 pub struct Step {
     pub source: PathBuf,
-    pub args:   Vec<String>,
+    // everything but the input and output
+    pub flags:  Vec<String>,
 
     pub deps:   HashSet<PathBuf>,
     pub outp:   String,
 }
 
+/// how one artifact is compiled and linked, for tools that don't run zz
+#[derive(Clone)]
+pub struct Target {
+    pub name:   String,
+    pub typ:    super::project::ArtifactType,
+    pub cc:     String,
+    pub units:  Vec<Unit>,
+    pub lflags: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct Unit {
+    pub source: PathBuf,
+    pub flags:  Vec<String>,
+    pub output: String,
+    // the zz files and inline includes the source was emitted from
+    pub deps:   Vec<PathBuf>,
+}
+
 pub struct Make {
     pub artifact:   Artifact,
     pub steps:      Vec<Step>,
@@ -96,13 +384,23 @@ pub struct Make {
     pub lobjs:      Vec<String>,
     pub variant:    String,
     pub stage:      Stage,
+    /// put the emitted C into one file with one header instead of compiling it
+    pub amalgamate: bool,
+    project:        String,
+    version:        String,
+    headers:        Vec<String>,
+    cincludes:      Vec<String>,
+    /// c objects a header artifact cannot carry
+    foreign:        Vec<PathBuf>,
 }
 
 impl Make {
-    pub fn new(mut config: Config, variant: &str, stage: Stage, artifact: Artifact) -> Self {
+    pub fn new(mut config: Config, variant: &str, stage: Stage, artifact: Artifact) -> Result<Self, Error> {
 
 
-        let features = config.features(variant);
+        let features = config.features_for(variant, &artifact.features)?;
+        let project = config.project.name.clone();
+        let version = config.project.version.clone();
 
         let mut cflags : Vec<String> =
             match std::env::var("TARGET_CFLAGS").or(std::env::var("CFLAGS")) {
@@ -138,8 +436,15 @@ impl Make {
             .unwrap_or("ar".to_string());
 
 
-        if stage.fuzz {
-            cc = "afl-clang".to_string();
+        // libfuzzer ships with clang itself, the others wrap it
+        match stage.fuzz {
+            Some(Fuzzer::Afl) => {
+                cc = std::env::var("AFL_CC").unwrap_or("afl-clang".to_string());
+            }
+            Some(Fuzzer::Honggfuzz) => {
+                cc = std::env::var("HFUZZ_CC").unwrap_or("hfuzz-clang".to_string());
+            }
+            Some(Fuzzer::Libfuzzer) | None => (),
         }
 
 
@@ -161,9 +466,9 @@ impl Make {
             user_lflags.extend(feature.lflags.clone());
         }
 
-        for cinc in cincludes{
+        for cinc in &cincludes {
             cflags.push("-I".into());
-            cflags.push(cinc);
+            cflags.push(cinc.clone());
         }
 
         for pkg in &pkgconfig {
@@ -171,7 +476,7 @@ impl Make {
                 .arg("--cflags")
                 .arg(pkg)
                 .output()
-                .expect(&format!("failed to execute pkg-config --cflags {}", pkg));
+                .map_err(|e| Error::new(format!("failed to execute pkg-config --cflags {}: {}", pkg, e), Vec::new()).with_code(3))?;
 
             let flags = String::from_utf8_lossy(&flags.stdout);
             let flags = flags.split_whitespace();
@@ -183,7 +488,7 @@ impl Make {
                 .arg("--libs")
                 .arg(pkg)
                 .output()
-                .expect(&format!("failed to execute pkg-config --lflags {}", pkg));
+                .map_err(|e| Error::new(format!("failed to execute pkg-config --libs {}: {}", pkg, e), Vec::new()).with_code(3))?;
 
             let flags = String::from_utf8_lossy(&flags.stdout);
             let flags = flags.split_whitespace();
@@ -225,18 +530,45 @@ impl Make {
             cflags.push("-fstack-protector-strong".into());
         }
 
-        if stage.asan {
-            cflags.push("-fsanitize=address".into());
-            lflags.push("-fsanitize=address".into());
+        // clang source based coverage. lines are mapped back to zz through the #line directives
+        if stage.coverage {
+            cflags.push("-fprofile-instr-generate".into());
+            cflags.push("-fcoverage-mapping".into());
+            lflags.push("-fprofile-instr-generate".into());
+        }
+
+        if !stage.sanitize.is_empty() {
+            let names : Vec<String> = stage.sanitize.iter().map(|s| s.to_string()).collect();
+            cflags.push(format!("-fsanitize={}", names.join(",")));
+            lflags.push(format!("-fsanitize={}", names.join(",")));
+            // so a test fails on the first report, instead of printing it and carrying on
+            if stage.sanitize.contains(&Sanitizer::Undefined) {
+                cflags.push("-fno-sanitize-recover=undefined".into());
+            }
+            if stage.sanitize.contains(&Sanitizer::Memory) {
+                cflags.push("-fsanitize-memory-track-origins".into());
+            }
         }
 
-        if stage.fuzz{
+        // 32bit asan reserves a lot less virtual memory, which afl counts against its limit
+        if let Some(Fuzzer::Afl) = stage.fuzz {
             cflags.push("-m32".into());
             lflags.push("-m32".into());
         }
 
+        // only executables get the libfuzzer main, everything else just the instrumentation
+        if let Some(Fuzzer::Libfuzzer) = stage.fuzz {
+            cflags.push("-fsanitize=fuzzer-no-link".into());
+            match artifact.typ {
+                super::project::ArtifactType::Exe |
+                super::project::ArtifactType::Test => lflags.push("-fsanitize=fuzzer".into()),
+                _ => lflags.push("-fsanitize=fuzzer-no-link".into()),
+            }
+        }
 
-        if !cxx && !stage.debug {
+
+        // sanitizer reports need the frame pointer for their stack traces
+        if !cxx && !stage.debug && stage.sanitize.is_empty() {
             cflags.push("-fomit-frame-pointer".into());
             cflags.push("-fno-exceptions".into());
             cflags.push("-fno-asynchronous-unwind-tables".into());
@@ -250,6 +582,8 @@ impl Make {
 
         cflags.extend(user_cflags);
         lflags.extend(user_lflags);
+        cflags.extend(stage.cflags.iter().cloned());
+        lflags.extend(stage.lflags.iter().cloned());
 
         let mut m = Make {
             variant: variant.to_string(),
@@ -262,38 +596,47 @@ impl Make {
             lobjs: Vec::new(),
             cflags,
             steps: Vec::new(),
+            amalgamate: false,
+            project,
+            version,
+            headers: Vec::new(),
+            cincludes,
+            foreign: Vec::new(),
         };
 
         for c in cobjects {
             m.cobject(Path::new(&c));
         }
 
-        m
+        Ok(m)
     }
 
     pub fn cobject(&mut self, inp: &Path) {
+        if self.artifact.typ == super::project::ArtifactType::Header {
+            self.foreign.push(inp.into());
+            return;
+        }
+        if self.amalgamate {
+            diagnostics::warn(&Error::new(format!("{:?} is not part of the amalgamated {}, it only has zz modules", inp, self.artifact.name), Vec::new()));
+            return;
+        }
 
-        let mut args = self.cflags.clone();
-        args.push("-c".to_string());
-        args.push(inp.to_string_lossy().to_string());
-        args.push("-o".to_string());
+        let flags = self.cflags.clone();
 
         let mut hasher: MetroHash128 = MetroHash128::default();
-        hasher.write(args.join(" ").as_bytes());
+        hasher.write(format!("{} -c {} -o", flags.join(" "), inp.to_string_lossy()).as_bytes());
         let hash = hasher.finish128();    
 
         let outp = inp.to_string_lossy().replace(|c: char| !c.is_alphanumeric(), "_");
         let outp = format!("{}_{:x}{:x}", outp, hash.0, hash.1);
-        let outp = format!("./target/{}/c/", self.stage) + &outp + ".o";
-
-        args.push(outp.clone());
+        let outp = format!("{}/{}/c/", self.stage.target.display(), self.stage) + &outp + ".o";
 
         let mut sources = HashSet::new();
         sources.insert(inp.into());
 
         self.steps.push(Step{
             source: inp.into(),
-            args,
+            flags,
             deps: sources,
             outp: outp.clone(),
         });
@@ -302,6 +645,22 @@ impl Make {
     }
 
     pub fn build(&mut self, cf: &super::emitter::CFile) {
+        if self.artifact.typ == super::project::ArtifactType::Header {
+            // c headers in src/ are inlined where the zz modules include them
+            if cf.filepath.ends_with(".h") {
+                return;
+            }
+            self.lobjs.push(super::emitter::amalgamated_name(&self.stage, &cf.name, true));
+            return;
+        }
+        if self.amalgamate {
+            if cf.filepath.ends_with(".h") {
+                return;
+            }
+            self.lobjs.push(super::emitter::amalgamated_name(&self.stage, &cf.name, false));
+            self.headers.push(super::emitter::headername(&self.project, &self.stage, &cf.name));
+            return;
+        }
         let mut args = self.cflags.clone();
         args.push("-Werror=implicit-function-declaration".to_string());
         args.push("-Werror=incompatible-pointer-types".to_string());
@@ -317,24 +676,20 @@ impl Make {
 
         args.push("-Werror=pointer-sign".to_string());
         args.push("-Werror=int-to-pointer-cast".to_string());
-        args.push("-c".to_string());
-        args.push(cf.filepath.clone());
-        args.push("-o".to_string());
 
 
-        let mut b = args.join(" ").as_bytes().to_vec();
+        let mut b = format!("{} -c {} -o", args.join(" "), cf.filepath).as_bytes().to_vec();
         b.extend(self.cc.as_bytes());
 
         let mut hasher: MetroHash128 = MetroHash128::default();
         hasher.write(&b);
         let hash = hasher.finish128();
 
-        let outp = format!("./target/{}/zz/{}_{:x}{:x}.o", self.stage, cf.name.0.join("_"), hash.0, hash.1);
-        args.push(outp.clone());
+        let outp = format!("{}/{}/zz/{}_{:x}{:x}.o", self.stage.target.display(), self.stage, cf.name.0.join("_"), hash.0, hash.1);
 
         self.steps.push(Step{
             source: Path::new(&cf.filepath).into(),
-            args,
+            flags: args,
             deps: cf.sources.clone(),
             outp: outp.clone(),
         });
@@ -342,10 +697,32 @@ impl Make {
     }
 
 
-    pub fn link(mut self) {
+    pub fn target(&self) -> Target {
+        Target {
+            name:   self.artifact.name.clone(),
+            typ:    self.artifact.typ.clone(),
+            cc:     self.cc.clone(),
+            units:  self.steps.iter().map(|step| Unit {
+                source: step.source.clone(),
+                flags:  step.flags.clone(),
+                output: step.outp.clone(),
+                deps:   {
+                    let mut deps : Vec<PathBuf> = step.deps.iter().cloned().collect();
+                    deps.sort();
+                    deps
+                },
+            }).collect(),
+            lflags: self.lflags.clone(),
+        }
+    }
+
+    pub fn link(mut self) -> Result<(), Error> {
         if self.artifact.typ == super::project::ArtifactType::NodeModule {
             emitter_js::make_npm_module(&self);
-            return;
+            return Ok(());
+        }
+        if self.amalgamate && self.artifact.typ != super::project::ArtifactType::Header {
+            return self.link_amalgamated();
         }
 
         use rayon::prelude::*;
@@ -353,30 +730,38 @@ impl Make {
 
         let pb = Arc::new(Mutex::new(pbr::ProgressBar::new(self.steps.len() as u64)));
         pb.lock().unwrap().show_speed = false;
+        let abort = AtomicBool::new(false);
         self.steps.par_iter().for_each(|step|{
-            if ABORT.load(Ordering::Relaxed) {
+            if abort.load(Ordering::Relaxed) {
                 return;
             };
             pb.lock().unwrap().message(&format!("{} {:?} ", self.cc, step.source));
 
             if step.is_dirty() {
-                debug!("{} {:?}", self.cc, step.args);
+                let args = step.args();
+                debug!("{} {:?}", self.cc, args);
                 let status = Command::new(&self.cc)
                     .env("AFL_USE_ASAN", "1")
-                    .args(&step.args)
-                    .status()
-                    .expect("failed to execute cc");
-                if !status.success() {
-                    error!("cc: [{}] args: [{}]", self.cc, step.args.join(" "));
-                    ABORT.store(true, Ordering::Relaxed);
+                    .args(&args)
+                    .status();
+                match status {
+                    Ok(status) if status.success() => (),
+                    Ok(_) => {
+                        diagnostics::error(&Error::new(format!("cc: [{}] args: [{}]", self.cc, args.join(" ")), Vec::new()));
+                        abort.store(true, Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        diagnostics::error(&Error::new(format!("failed to execute {}: {}", self.cc, e), Vec::new()));
+                        abort.store(true, Ordering::Relaxed);
+                    }
                 }
             }
             pb.lock().unwrap().inc();
         });
 
-        if ABORT.load(Ordering::Relaxed) {
+        if abort.load(Ordering::Relaxed) {
             pb.lock().unwrap().finish_print(&format!("failed [{:?}] {}", self.artifact.typ, self.artifact.name));
-            std::process::exit(11);
+            return Err(Error::new(format!("could not compile {}", self.artifact.name), Vec::new()).with_code(11));
         }
 
         let mut cmd     = self.cc.clone();
@@ -384,44 +769,48 @@ impl Make {
 
         match self.artifact.typ {
             super::project::ArtifactType::Staticlib => {
-                std::fs::create_dir_all(format!("./target/{}/lib/", self.stage)).expect("create target dir");
+                std::fs::create_dir_all(format!("{}/{}/lib/", self.stage.target.display(), self.stage)).expect("create target dir");
                 cmd = self.ar.clone();
                 args = vec![
                     "rcs".to_string(),
-                    format!("./target/{}/lib/lib{}.a", self.stage, self.artifact.name)
+                    format!("{}/{}/lib/lib{}.a", self.stage.target.display(), self.stage, self.artifact.name)
                 ];
                 args.extend_from_slice(&self.lobjs);
 
                 if BUILD_RS.load(Ordering::Relaxed) {
                     println!("\n\ncargo:rustc-link-lib=static={}\n\n", self.artifact.name);
-                    println!("\n\ncargo:rustc-link-search=native={}/target/{}\n\n", std::env::current_dir().unwrap().display(), self.stage);
+                    println!("\n\ncargo:rustc-link-search=native={}/{}/lib\n\n", std::env::current_dir().unwrap().join(&self.stage.target).display(), self.stage);
                 }
 
             },
             super::project::ArtifactType::Lib => {
-                std::fs::create_dir_all(format!("./target/{}/lib/", self.stage)).expect("create target dir");
+                std::fs::create_dir_all(format!("{}/{}/lib/", self.stage.target.display(), self.stage)).expect("create target dir");
                 args.extend_from_slice(&self.lobjs);
                 args.extend_from_slice(&self.lflags);
                 args.push("-shared".into());
+                // zz install puts the full version behind it, and links the soname to that
+                if !cfg!(target_os = "macos") {
+                    args.push(format!("-Wl,-soname,{}", soname(&self.artifact.name, &self.version)));
+                }
                 args.push("-o".into());
-                args.push(format!("./target/{}/lib/lib{}.so", self.stage, self.artifact.name));
+                args.push(format!("{}/{}/lib/lib{}.so", self.stage.target.display(), self.stage, self.artifact.name));
             },
             super::project::ArtifactType::Exe => {
-                std::fs::create_dir_all(format!("./target/{}/bin/", self.stage)).expect("create target dir");
+                std::fs::create_dir_all(format!("{}/{}/bin/", self.stage.target.display(), self.stage)).expect("create target dir");
                 args.extend_from_slice(&self.lobjs);
                 args.extend_from_slice(&self.lflags);
                 args.push("-o".into());
-                args.push(format!("./target/{}/bin/{}", self.stage, self.artifact.name));
+                args.push(format!("{}/{}/bin/{}", self.stage.target.display(), self.stage, self.artifact.name));
             }
             super::project::ArtifactType::Test  => {
-                std::fs::create_dir_all(format!("./target/{}/bin/", self.stage)).expect("create target dir");
+                std::fs::create_dir_all(format!("{}/{}/bin/", self.stage.target.display(), self.stage)).expect("create target dir");
                 args.extend_from_slice(&self.lobjs);
                 args.extend_from_slice(&self.lflags);
                 args.push("-o".into());
-                args.push(format!("./target/{}/bin/{}", self.stage, self.artifact.name));
+                args.push(format!("{}/{}/bin/{}", self.stage.target.display(), self.stage, self.artifact.name));
             }
             super::project::ArtifactType::Header  => {
-                panic!("cannot link header yet");
+                return self.link_header();
             }
             super::project::ArtifactType::NodeModule => {
                 unreachable!();
@@ -438,17 +827,175 @@ impl Make {
             .env("AFL_USE_ASAN", "1")
             .args(&args)
             .status()
-            .expect("failed to execute linker");
+            .map_err(|e| {
+                let e = Error::new(format!("failed to execute {}: {}", cmd, e), Vec::new()).with_code(3);
+                diagnostics::error(&e);
+                e
+            })?;
         if !status.success() {
-            std::process::exit(status.code().unwrap_or(3));
+            let e = Error::new(format!("could not link {}", self.artifact.name), Vec::new())
+                .with_code(status.code().unwrap_or(3));
+            diagnostics::error(&e);
+            return Err(e);
+        }
+
+        // the soname is what programs linked against the library in target load it by
+        #[cfg(unix)]
+        if self.artifact.typ == super::project::ArtifactType::Lib && !cfg!(target_os = "macos") {
+            let link = format!("{}/{}/lib/{}", self.stage.target.display(), self.stage, soname(&self.artifact.name, &self.version));
+            std::fs::remove_file(&link).ok();
+            std::os::unix::fs::symlink(format!("lib{}.so", self.artifact.name), &link).map_err(|e| {
+                let e = Error::new(format!("cannot link {}: {}", link, e), Vec::new()).with_code(3);
+                diagnostics::error(&e);
+                e
+            })?;
         }
 
         pb.lock().unwrap().finish_print(&format!("finished [{:?}] {}", self.artifact.typ, self.artifact.name));
         println!("");
+        Ok(())
+    }
+}
+
+impl Make {
+    // one file with every module, stb style.
+    // all definitions are static inline, so any number of translation units can include it
+    fn link_header(self) -> Result<(), Error> {
+        if let Some(inp) = self.foreign.first() {
+            let e = Error::new(format!("{:?} cannot be part of header {}, only zz modules and the C headers they include can", inp, self.artifact.name), Vec::new());
+            diagnostics::error(&e);
+            return Err(e);
+        }
+
+        let outp = format!("{}/{}/include/{}.h", self.stage.target.display(), self.stage, self.artifact.name);
+        let guard = self.guard();
+
+        let mut out = format!("#ifndef ZZ_HEADER_{}_H\n#define ZZ_HEADER_{}_H\n", guard, guard);
+        out.push_str("#ifdef __cplusplus\nextern \"C\" {\n#endif\n");
+        concat(&mut out, &self.lobjs, &mut HashSet::new(), Some(&self.cincludes))?;
+        out.push_str("\n#ifdef __cplusplus\n}\n#endif\n#endif\n");
+
+        write(&outp, out)?;
+        println!("finished [Header] {}", outp);
+        Ok(())
+    }
+
+    // for toolchains that take exactly one C file.
+    // the modules come in the order they were built in, and each include only once
+    fn link_amalgamated(self) -> Result<(), Error> {
+        let outp = format!("{}/{}/{}.c", self.stage.target.display(), self.stage, self.artifact.name);
+        let mut out = String::new();
+        concat(&mut out, &self.lobjs, &mut HashSet::new(), None)?;
+        write(&outp, out)?;
+
+        let houtp = format!("{}/{}/{}.h", self.stage.target.display(), self.stage, self.artifact.name);
+        let guard = self.guard();
+        let mut out = format!("#ifndef ZZ_{}_H\n#define ZZ_{}_H\n", guard, guard);
+        concat(&mut out, &self.headers, &mut HashSet::new(), None)?;
+        out.push_str("\n#endif\n");
+        write(&houtp, out)?;
+
+        println!("finished [{:?}] {} {}", self.artifact.typ, outp, houtp);
+        Ok(())
+    }
+
+    fn guard(&self) -> String {
+        self.artifact.name.chars().map(|x| if x.is_ascii_alphanumeric() { x.to_ascii_uppercase() } else { '_' }).collect()
+    }
+}
+
+/// the name a shared library is loaded by, which changes with the major version
+pub fn soname(name: &str, version: &str) -> String {
+    format!("lib{}.so.{}", name, version.split('.').next().unwrap_or("0"))
+}
+
+/// with local include directories, C headers found in them or next to the including file are inlined, each only once
+fn concat(out: &mut String, sections: &[String], includes: &mut HashSet<String>, local: Option<&[String]>) -> Result<(), Error> {
+    for section in sections {
+        splice(out, Path::new(section), includes, local)?;
+    }
+    Ok(())
+}
+
+fn splice(out: &mut String, section: &Path, includes: &mut HashSet<String>, local: Option<&[String]>) -> Result<(), Error> {
+    let s = std::fs::read_to_string(section).map_err(|e| {
+        let e = Error::new(format!("cannot read {}: {}", section.display(), e), Vec::new()).with_code(3);
+        diagnostics::error(&e);
+        e
+    })?;
+    out.push_str(&format!("\n/* {} */\n", section.display()));
+    for line in s.lines() {
+        if line.trim_start().starts_with("#include") {
+            if let Some(local) = local {
+                let path = local_include(line, section, local).map_err(|message| {
+                    let e = Error::new(message, Vec::new());
+                    diagnostics::error(&e);
+                    e
+                })?;
+                if let Some(path) = path {
+                    if includes.insert(path.to_string_lossy().to_string()) {
+                        splice(out, &path, includes, Some(local))?;
+                        out.push_str(&format!("\n/* end of {} */\n", path.display()));
+                    }
+                    continue;
+                }
+            }
+            if !includes.insert(line.trim().to_string()) {
+                continue;
+            }
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    Ok(())
+}
+
+/// the file a quoted include, or an include from one of the project's include directories, refers to.
+/// system includes are none, but a quoted include that cannot be found is an error
+pub fn local_include(line: &str, from: &Path, local: &[String]) -> Result<Option<PathBuf>, String> {
+    let rest = line.trim_start().trim_start_matches("#include").trim();
+    let (name, quoted) = match rest.chars().next() {
+        Some('"')   => (rest[1..].split('"').next().unwrap_or(""), true),
+        Some('<')   => (rest[1..].split('>').next().unwrap_or(""), false),
+        _           => return Ok(None),
+    };
+
+    let mut candidates = Vec::new();
+    if quoted {
+        candidates.push(from.parent().unwrap_or(Path::new(".")).join(name));
+    }
+    candidates.extend(local.iter().map(|dir| Path::new(dir).join(name)));
+
+    for candidate in candidates {
+        if candidate.is_file() {
+            return Ok(Some(std::fs::canonicalize(&candidate).unwrap_or(candidate)));
+        }
+    }
+    if quoted {
+        return Err(format!("cannot find \"{}\" included from {}, it is not next to it or in the cincludes", name, from.display()));
     }
+    Ok(None)
+}
+
+fn write(outp: &str, out: String) -> Result<(), Error> {
+    std::fs::write(outp, out).map_err(|e| {
+        let e = Error::new(format!("cannot write {}: {}", outp, e), Vec::new()).with_code(3);
+        diagnostics::error(&e);
+        e
+    })
 }
 
 impl Step {
+    /// the compiler command line, after the compiler itself
+    pub fn args(&self) -> Vec<String> {
+        let mut args = self.flags.clone();
+        args.push("-c".to_string());
+        args.push(self.source.to_string_lossy().to_string());
+        args.push("-o".to_string());
+        args.push(self.outp.clone());
+        args
+    }
+
     fn is_dirty(&self) -> bool {
         let itarget = match std::fs::metadata(&self.outp) {
             Ok(v)  => v,
@@ -17,60 +17,63 @@ pub static ERRORS_AS_JSON : AtomicBool = AtomicBool::new(false);
 
 
 
-pub fn parse(n: &Path, features: &HashMap<String, bool>, stage: &Stage) -> Module
+pub fn parse(n: &Path, features: &HashMap<String, bool>, stage: &Stage) -> Result<Module, super::Error>
 {
-    match p(&n, features, stage){
-        Err(e) => {
-            let e = e.with_path(&n.to_string_lossy());
-            if ERRORS_AS_JSON.load(Ordering::SeqCst) {
-
-
-                let mut j = JsonError::default();
-                j.message       = format!("syntax error:\n{}", e);
-                j.level         = "error".to_string();
-                j.file_name     = n.to_string_lossy().to_string();
+    p(&n, features, stage)
+}
 
-                match e.line_col {
-                    pest::error::LineColLocation::Span((l1,c1),(l2,c2)) => {
-                        j.line_start    = l1;
-                        j.column_start  = c1;
-                        j.line_end      = l2;
-                        j.column_end    = c2;
-                    },
-                    pest::error::LineColLocation::Pos((l1,c1)) => {
-                        j.line_start    = l1;
-                        j.column_start  = c1;
-                        j.line_end      = l1;
-                        j.column_end    = c1;
-                    }
-                };
-                println!("{}", serde_json::to_string(&j).unwrap());
-            } else {
-                error!("syntax error\n{}", e);
-            }
-            std::process::exit(9);
-        }
-        Ok(md) => {
-            md
-        }
-    }
+/// whether code is a complete module with a fn main, rather than a list of statements
+pub fn declares_main(code: &str) -> bool {
+    let file = match ZZParser::parse(Rule::file, code) {
+        Ok(mut v) => v.next().unwrap(),
+        Err(_) => return false,
+    };
+    file.into_inner().any(|decl| {
+        decl.as_rule() == Rule::function &&
+            decl.into_inner().find(|p| p.as_rule() == Rule::ident).map(|p| p.as_str() == "main").unwrap_or(false)
+    })
 }
 
-fn p(n: &Path, features: &HashMap<String, bool> , stage: &Stage) -> Result<Module, pest::error::Error<Rule>> {
+fn p(n: &Path, features: &HashMap<String, bool> , stage: &Stage) -> Result<Module, super::Error> {
 
     let mut module = Module::default();
     module.source = n.to_path_buf();
     module.sources.insert(n.canonicalize().unwrap());
     module.name.push(n.file_stem().expect(&format!("stem {:?}", n)).to_string_lossy().into());
 
-    let mut f = std::fs::File::open(n).expect(&format!("cannot open file {:?}", n));
     let mut file_str = String::new();
-    f.read_to_string(&mut file_str).expect(&format!("read {:?}", n));
+    if let Err(e) = std::fs::File::open(n).and_then(|mut f| f.read_to_string(&mut file_str)) {
+        return Err(super::Error::new(format!("cannot read {:?}: {}", n, e), Vec::new()));
+    }
     let file_str = Box::leak(Box::new(file_str));
-    let mut file = ZZParser::parse(Rule::file, file_str)?;
+    let mut file = match ZZParser::parse(Rule::file, file_str) {
+        Ok(v) => v,
+        Err(e) => {
+            let (start, end) = match e.location {
+                pest::error::InputLocation::Pos(p)      => (p, p),
+                pest::error::InputLocation::Span(span)  => span,
+            };
+            let loc = Location{
+                file: n.to_string_lossy().into(),
+                span: pest::Span::new(file_str, start, end).unwrap(),
+            };
+            let rules = |r: &Vec<Rule>| r.iter().map(|r| format!("{:?}", r)).collect::<Vec<_>>().join(", ");
+            let message = match &e.variant {
+                pest::error::ErrorVariant::CustomError{message} => message.clone(),
+                pest::error::ErrorVariant::ParsingError{positives, ..} if !positives.is_empty() => {
+                    format!("expected {}", rules(positives))
+                }
+                pest::error::ErrorVariant::ParsingError{negatives, ..} => {
+                    format!("unexpected {}", rules(negatives))
+                }
+            };
+            return Err(error("syntax error", &[(loc, message)]));
+        }
+    };
     let mut doccomments = String::new();
 
     for decl in PP::new(n, features.clone(), stage.clone(), file.next().unwrap().into_inner()) {
+        let decl = decl?;
         match decl.as_rule() {
             Rule::doccomment => {
                 let mut s = decl.as_str().to_string();
@@ -105,7 +108,7 @@ fn p(n: &Path, features: &HashMap<String, bool> , stage: &Stage) -> Result<Modul
                             }
                         }
                         Rule::block if body.is_none() => {
-                            body = Some(parse_block((file_str, n), features, stage, part));
+                            body = Some(parse_block((file_str, n), features, stage, part)?);
                         },
                         e => panic!("unexpected rule {:?} in macro ", e),
                     }
@@ -160,7 +163,7 @@ fn p(n: &Path, features: &HashMap<String, bool> , stage: &Stage) -> Result<Modul
                         Rule::ret_arg => {
                             let part = part.into_inner().next().unwrap();
                             ret = Some(AnonArg{
-                                typed: parse_anon_type((file_str, n), part),
+                                typed: parse_anon_type((file_str, n), part)?,
                             });
                         },
                         Rule::fn_attr => {
@@ -181,7 +184,7 @@ fn p(n: &Path, features: &HashMap<String, bool> , stage: &Stage) -> Result<Modul
                                 if arg.as_rule() == Rule::vararg {
                                     vararg = true;
                                 } else {
-                                    let TypedName{typed, name, tags} = parse_named_type((file_str, n), arg);
+                                    let TypedName{typed, name, tags} = parse_named_type((file_str, n), arg)?;
 
                                     args.push(NamedArg{
                                         name,
@@ -194,14 +197,14 @@ fn p(n: &Path, features: &HashMap<String, bool> , stage: &Stage) -> Result<Modul
                         },
                         Rule::call_assert => {
                             let part = part.into_inner().next().unwrap();
-                            callassert.push(parse_expr((file_str, n), part));
+                            callassert.push(parse_expr((file_str, n), part)?);
                         },
                         Rule::call_effect => {
                             let part = part.into_inner().next().unwrap();
-                            calleffect.push(parse_expr((file_str, n), part));
+                            calleffect.push(parse_expr((file_str, n), part)?);
                         },
                         Rule::block => {
-                            body = Some(parse_block((file_str, n), features, stage, part));
+                            body = Some(parse_block((file_str, n), features, stage, part)?);
                         },
                         Rule::fn_vattr => {
                             let mut part = part.into_inner();
@@ -275,6 +278,8 @@ fn p(n: &Path, features: &HashMap<String, bool> , stage: &Stage) -> Result<Modul
                 let mut loc    = None;
 
                 for part in PP::new(n, features.clone(), stage.clone(), decl) {
+
+                    let part = part?;
                     match part.as_rule() {
                         Rule::key_shared => {
                             vis = Visibility::Shared;
@@ -301,11 +306,10 @@ fn p(n: &Path, features: &HashMap<String, bool> , stage: &Stage) -> Result<Modul
                                             file: n.to_string_lossy().into(),
                                             span: part.as_span(),
                                         };
-                                        emit_error(
+                                        return Err(error(
                                             "enums must be positive integer literals",
                                             &[(loc.clone(), format!("{}", e))]
-                                        );
-                                        std::process::exit(9);
+                                        ));
                                     },
                                     Ok(v) => v,
                                 });
@@ -340,6 +344,7 @@ fn p(n: &Path, features: &HashMap<String, bool> , stage: &Stage) -> Result<Modul
 
                 let decl = decl.into_inner();
                 for part in PP::new(n,features.clone(), stage.clone(), decl) {
+                    let part = part?;
                     match part.as_rule() {
                         Rule::ident => {
                             loc  = Location{
@@ -351,9 +356,16 @@ fn p(n: &Path, features: &HashMap<String, bool> , stage: &Stage) -> Result<Modul
                         Rule::testfield => {
                             let mut part = part.into_inner();
                             let fname   = part.next().unwrap().as_str().to_string();
-                            let _op      = part.next().unwrap().as_str().to_string();
-                            let expr    = parse_expr((file_str, n), part.next().unwrap());
-                            fields.push((fname,expr));
+                            let op      = match part.next().unwrap().as_str() {
+                                "!="    => InfixOperator::Nequals,
+                                "<"     => InfixOperator::Lessthan,
+                                ">"     => InfixOperator::Morethan,
+                                "<="    => InfixOperator::Lesseq,
+                                ">="    => InfixOperator::Moreeq,
+                                _       => InfixOperator::Equals,
+                            };
+                            let expr    = parse_expr((file_str, n), part.next().unwrap())?;
+                            fields.push((fname, op, expr));
                         }
                         e => panic!("unexpected rule {:?} in testcase", e),
 
@@ -383,6 +395,8 @@ fn p(n: &Path, features: &HashMap<String, bool> , stage: &Stage) -> Result<Modul
                 let mut union  = false;
 
                 for part in PP::new(n,features.clone(), stage.clone(), decl) {
+
+                    let part = part?;
                     match part.as_rule() {
                         Rule::tail => {
                             tail = Tail::Dynamic;
@@ -419,14 +433,14 @@ fn p(n: &Path, features: &HashMap<String, bool> , stage: &Stage) -> Result<Modul
 
                             let mut part = part.into_inner();
 
-                            let TypedName{typed, name, tags} = parse_named_type((file_str, n), part.next().unwrap());
+                            let TypedName{typed, name, tags} = parse_named_type((file_str, n), part.next().unwrap())?;
 
                             let array = match part.next() {
                                 None => None,
                                 Some(array) => {
                                     match array.into_inner().next() {
                                         Some(expr) => {
-                                            Some(Some(parse_expr((file_str, n), expr)))
+                                            Some(Some(parse_expr((file_str, n), expr)?))
                                         },
                                         None => {
                                             Some(None)
@@ -554,35 +568,33 @@ fn p(n: &Path, features: &HashMap<String, bool> , stage: &Stage) -> Result<Modul
                         }
                         Rule::key_shared =>  {
                             if let Rule::istatic = rule {
-                                let e = pest::error::Error::<Rule>::new_from_span(pest::error::ErrorVariant::CustomError {
-                                    message: format!("cannot change visibility of static variable"),
-                                }, part.as_span());
-                                error!("{} : {}", n.to_string_lossy(), e);
-                                std::process::exit(9);
+                                return Err(error("cannot change visibility of static variable", &[(Location{
+                                    file: n.to_string_lossy().into(),
+                                    span: part.as_span(),
+                                }, "static variables are always private")]));
                             } else {
                                 vis = Visibility::Shared;
                             }
                         }
                         Rule::exported => {
                             if let Rule::istatic = rule {
-                                let e = pest::error::Error::<Rule>::new_from_span(pest::error::ErrorVariant::CustomError {
-                                    message: format!("cannot change visibility of static variable"),
-                                }, part.as_span());
-                                error!("{} : {}", n.to_string_lossy(), e);
-                                std::process::exit(9);
+                                return Err(error("cannot change visibility of static variable", &[(Location{
+                                    file: n.to_string_lossy().into(),
+                                    span: part.as_span(),
+                                }, "static variables are always private")]));
                             } else {
                                 vis = Visibility::Export;
                             }
                         },
                         Rule::named_type => {
-                            typed = Some(parse_named_type((file_str, n), part));
+                            typed = Some(parse_named_type((file_str, n), part)?);
                         },
                         Rule::expr if expr.is_none() => {
-                            expr = Some(parse_expr((file_str, n), part));
+                            expr = Some(parse_expr((file_str, n), part)?);
                         }
                         Rule::array => {
                             if let Some(expr) = part.into_inner().next() {
-                                array = Some(Some(parse_expr((file_str, n), expr)));
+                                array = Some(Some(parse_expr((file_str, n), expr)?));
                             } else {
                                 array = Some(None);
                             }
@@ -596,10 +608,9 @@ fn p(n: &Path, features: &HashMap<String, bool> , stage: &Stage) -> Result<Modul
 
                     Rule::constant => {
                         for (_,tag) in tags.0 {
-                            emit_error("syntax error", &[(
+                            return Err(error("syntax error", &[(
                                        tag.iter().next().unwrap().1.clone(),
-                                       "anonymous type cannot have storage tags (yet)")]);
-                            std::process::exit(9);
+                                       "anonymous type cannot have storage tags (yet)")]));
                         }
 
                         module.locals.push(Local{
@@ -640,7 +651,7 @@ fn p(n: &Path, features: &HashMap<String, bool> , stage: &Stage) -> Result<Modul
     Ok(module)
 }
 
-pub(crate) fn parse_expr(n: (&'static str, &Path), decl: pest::iterators::Pair<'static, Rule>) -> Expression {
+pub(crate) fn parse_expr(n: (&'static str, &Path), decl: pest::iterators::Pair<'static, Rule>) -> Result<Expression, super::Error> {
     match decl.as_rule() {
         Rule::expr  => { }
         Rule::expr_to_precedence_2 => {}
@@ -681,7 +692,9 @@ pub(crate) fn parse_expr(n: (&'static str, &Path), decl: pest::iterators::Pair<'
 
     ]);
 
-    let reduce = |lhs: Expression, op: pest::iterators::Pair<'static, Rule>, rhs: Expression | {
+    let reduce = |lhs: Result<Expression, super::Error>, op: pest::iterators::Pair<'static, Rule>, rhs: Result<Expression, super::Error> | {
+        let lhs = lhs?;
+        let rhs = rhs?;
 
         let loc = Location{
             file: n.1.to_string_lossy().into(),
@@ -691,56 +704,53 @@ pub(crate) fn parse_expr(n: (&'static str, &Path), decl: pest::iterators::Pair<'
         if op.as_rule()  == Rule::memberaccess {
             if let Expression::Name(typed) = &rhs {
                 if let Type::Other(n) = &typed.t {
-                    return Expression::MemberAccess{
+                    return Ok(Expression::MemberAccess{
                         lhs: Box::new(lhs),
                         rhs: n.to_string(),
                         op:  ".".to_string(),
                         loc: loc.clone(),
-                    };
+                    });
                 }
             }
-            emit_error(format!("ICE: unexpected rhs {:?}", rhs), &[
+            return Err(error(format!("ICE: unexpected rhs {:?}", rhs), &[
                        (loc.clone(), "in this memberaccess ")
-            ]);
-            std::process::exit(9);
+            ]));
         } else if op.as_rule()  == Rule::ptraccess {
             if let Expression::Name(typed) = &rhs {
                 if let Type::Other(n) = &typed.t {
-                    return Expression::MemberAccess{
+                    return Ok(Expression::MemberAccess{
                         lhs: Box::new(lhs),
                         rhs: n.to_string(),
                         op:  "->".to_string(),
                         loc: loc.clone(),
-                    };
+                    });
                 }
             }
-            emit_error(format!("ICE: unexpected rhs {:?}", rhs), &[
+            return Err(error(format!("ICE: unexpected rhs {:?}", rhs), &[
                        (loc.clone(), "in this ptraccess ")
-            ]);
-            std::process::exit(9);
+            ]));
         } else if op.as_rule()  == Rule::callstart {
             if let Expression::Call{loc, args, .. }  = &rhs {
-                return Expression::Call{
+                return Ok(Expression::Call{
                     loc:            loc.clone(),
                     name:           Box::new(lhs),
                     args:           args.clone(),
                     expanded:       false,
                     emit:           EmitBehaviour::Default,
-                };
+                });
             }
-            emit_error(format!("ICE: unexpected rhs {:?}", rhs), &[
+            return Err(error(format!("ICE: unexpected rhs {:?}", rhs), &[
                        (loc.clone(), "in this call ")
-            ]);
-            std::process::exit(9);
+            ]));
         } else if op.as_rule()  == Rule::arraystart {
-            return Expression::ArrayAccess {
+            return Ok(Expression::ArrayAccess {
                 loc:    loc.clone(),
                 lhs:    Box::new(lhs),
                 rhs:    Box::new(rhs),
-            };
+            });
         }
 
-        Expression::Infix {
+        Ok(Expression::Infix {
             loc:    loc.clone(),
             lhs:    Box::new(lhs),
             rhs:    Box::new(rhs),
@@ -764,26 +774,25 @@ pub(crate) fn parse_expr(n: (&'static str, &Path), decl: pest::iterators::Pair<'
                 Rule::bitand    => crate::ast::InfixOperator::Bitand,
                 Rule::bitor     => crate::ast::InfixOperator::Bitor,
                 _ => {
-                    emit_error(format!("ICE: unexpected operator {}", op), &[
+                    return Err(error(format!("ICE: unexpected operator {}", op), &[
                         (loc.clone(), "in this infix")
-                    ]);
-                    std::process::exit(9);
+                    ]));
                 }
             },
-        }
+        })
     };
     climber.climb(decl.into_inner(), |pair|parse_expr_inner(n, pair), reduce)
 }
 
 
-pub(crate) fn parse_expr_inner(n: (&'static str, &Path), expr: pest::iterators::Pair<'static, Rule>) -> Expression {
+pub(crate) fn parse_expr_inner(n: (&'static str, &Path), expr: pest::iterators::Pair<'static, Rule>) -> Result<Expression, super::Error> {
     let loc = Location{
         file: n.1.to_string_lossy().into(),
         span: expr.as_span(),
     };
 
     let asrule = expr.as_rule();
-    match asrule {
+    Ok(match asrule {
         Rule::unarypre => {
             let mut expr = expr.into_inner();
             let part    = expr.next().unwrap();
@@ -793,10 +802,9 @@ pub(crate) fn parse_expr_inner(n: (&'static str, &Path), expr: pest::iterators::
                 Rule::increment => crate::ast::PrefixOperator::Increment,
                 Rule::decrement => crate::ast::PrefixOperator::Decrement,
                 _ => {
-                    emit_error("ICE: unexpected operator", &[
+                    return Err(error("ICE: unexpected operator", &[
                                (loc.clone(), "in this expr")
-                    ]);
-                    std::process::exit(9);
+                    ]));
                 }
             };
             let part   = expr.next().unwrap();
@@ -815,7 +823,7 @@ pub(crate) fn parse_expr_inner(n: (&'static str, &Path), expr: pest::iterators::
                     })
                 },
                 Rule::expr_to_precedence_2 => {
-                    parse_expr(n, part)
+                    parse_expr(n, part)?
                 }
                 e => panic!("unexpected rule {:?} in unary pre lhs", e),
             };
@@ -843,7 +851,7 @@ pub(crate) fn parse_expr_inner(n: (&'static str, &Path), expr: pest::iterators::
                     })
                 },
                 Rule::expr => {
-                    parse_expr(n, part)
+                    parse_expr(n, part)?
                 }
                 e => panic!("unexpected rule {:?} in unary post lhs", e),
             };
@@ -853,10 +861,9 @@ pub(crate) fn parse_expr_inner(n: (&'static str, &Path), expr: pest::iterators::
                 Rule::increment => crate::ast::PostfixOperator::Increment,
                 Rule::decrement => crate::ast::PostfixOperator::Decrement,
                 _ => {
-                    emit_error("ICE: unexpected operator", &[
+                    return Err(error("ICE: unexpected operator", &[
                                (loc.clone(), "in this expr")
-                    ]);
-                    std::process::exit(9);
+                    ]));
                 }
             };
 
@@ -869,9 +876,9 @@ pub(crate) fn parse_expr_inner(n: (&'static str, &Path), expr: pest::iterators::
         Rule::cast => {
             let mut expr = expr.into_inner();
             let part  = expr.next().unwrap();
-            let into = parse_anon_type(n, part);
+            let into = parse_anon_type(n, part)?;
             let part  = expr.next().unwrap();
-            let expr = parse_expr(n, part);
+            let expr = parse_expr(n, part)?;
             Expression::Cast{
                 loc,
                 into,
@@ -899,7 +906,7 @@ pub(crate) fn parse_expr_inner(n: (&'static str, &Path), expr: pest::iterators::
             } else {
                 val.remove(0);
                 val.pop();
-                unescape(&val, &loc)
+                unescape(&val, &loc)?
             };
 
             Expression::LiteralString {
@@ -911,7 +918,7 @@ pub(crate) fn parse_expr_inner(n: (&'static str, &Path), expr: pest::iterators::
             let mut val = expr.as_str().to_string();
             val.remove(0);
             val.pop();
-            let v = unescape(&val, &loc);
+            let v = unescape(&val, &loc)?;
 
             Expression::LiteralChar {
                 v: v[0],
@@ -925,7 +932,7 @@ pub(crate) fn parse_expr_inner(n: (&'static str, &Path), expr: pest::iterators::
             }
         },
         Rule::expr => {
-            parse_expr(n, expr)
+            parse_expr(n, expr)?
         },
         Rule::deref | Rule::takeref => {
             let op = match expr.as_rule() {
@@ -950,7 +957,7 @@ pub(crate) fn parse_expr_inner(n: (&'static str, &Path), expr: pest::iterators::
                     })
                 },
                 Rule::expr_to_precedence_2 => {
-                    parse_expr(n, part)
+                    parse_expr(n, part)?
                 }
                 e => panic!("unexpected rule {:?} in deref lhs", e),
             };
@@ -961,7 +968,7 @@ pub(crate) fn parse_expr_inner(n: (&'static str, &Path), expr: pest::iterators::
             }
         },
         Rule::call => {
-            parse_call(n, expr)
+            parse_call(n, expr)?
         },
         Rule::array_init => {
             let mut fields = Vec::new();
@@ -969,7 +976,7 @@ pub(crate) fn parse_expr_inner(n: (&'static str, &Path), expr: pest::iterators::
             for part in expr {
                 match part.as_rule()  {
                     Rule::expr => {
-                        let expr = parse_expr(n, part);
+                        let expr = parse_expr(n, part)?;
                         fields.push(Box::new(expr));
                     }
                     e => panic!("unexpected rule {:?} in struct init", e),
@@ -985,7 +992,7 @@ pub(crate) fn parse_expr_inner(n: (&'static str, &Path), expr: pest::iterators::
             let mut expr = expr.into_inner();
             let part  = expr.next().unwrap();
 
-            let typed = parse_anon_type(n, part);
+            let typed = parse_anon_type(n, part)?;
 
             let mut fields = Vec::new();
             for part in expr {
@@ -993,7 +1000,7 @@ pub(crate) fn parse_expr_inner(n: (&'static str, &Path), expr: pest::iterators::
                     Rule::struct_init_field => {
                         let mut part = part.into_inner();
                         let name = part.next().unwrap().as_str().to_string();
-                        let expr = parse_expr(n, part.next().unwrap());
+                        let expr = parse_expr(n, part.next().unwrap())?;
                         fields.push((name, Box::new(expr)));
                     }
                     e => panic!("unexpected rule {:?} in struct init", e),
@@ -1008,7 +1015,7 @@ pub(crate) fn parse_expr_inner(n: (&'static str, &Path), expr: pest::iterators::
             }
         }
         e => panic!("unexpected rule {:?} in expr", e),
-    }
+    })
 }
 
 pub(crate) fn parse_statement(
@@ -1018,7 +1025,7 @@ pub(crate) fn parse_statement(
     stm:        pest::iterators::Pair<'static, Rule>,
     into:       &mut Vec<Box<Statement>>,
     current_if_statement: &mut Option<usize>,
-) {
+) -> Result<(), super::Error> {
 
     let loc = Location{
         file: n.1.to_string_lossy().into(),
@@ -1028,7 +1035,7 @@ pub(crate) fn parse_statement(
         Rule::mark_stm => {
             let mut stm = stm.into_inner();
             let part    = stm.next().unwrap();
-            let lhs     = parse_expr(n, part);
+            let lhs     = parse_expr(n, part)?;
             let part    = stm.next().unwrap();
             let mut part = part.into_inner();
             let key   = part.next().unwrap().as_str().into();
@@ -1060,7 +1067,7 @@ pub(crate) fn parse_statement(
             }));
         },
         Rule::block => {
-            into.push(Box::new(Statement::Block(Box::new(parse_block(n, features, stage, stm)))))
+            into.push(Box::new(Statement::Block(Box::new(parse_block(n, features, stage, stm)?))))
         },
         Rule::return_stm  => {
             let mut stm = stm.into_inner();
@@ -1070,7 +1077,7 @@ pub(crate) fn parse_statement(
                 a => { panic!("expected key_return instead of {:?}", a );}
             };
             let expr = if let Some(expr) = stm.next() {
-                Some(parse_expr(n, expr))
+                Some(parse_expr(n, expr)?)
             } else {
                 None
             };
@@ -1080,7 +1087,7 @@ pub(crate) fn parse_statement(
             }));
         },
         Rule::expr => {
-            let expr = parse_expr(n, stm);
+            let expr = parse_expr(n, stm)?;
             into.push(Box::new(Statement::Expr{
                 expr,
                 loc: loc.clone(),
@@ -1089,9 +1096,9 @@ pub(crate) fn parse_statement(
         Rule::while_stm => {
             let mut stm = stm.into_inner();
             let part    = stm.next().unwrap();
-            let expr    = parse_expr(n, part);
+            let expr    = parse_expr(n, part)?;
             let part    = stm.next().unwrap();
-            let body    = parse_block(n, features, stage, part);
+            let body    = parse_block(n, features, stage, part)?;
             into.push(Box::new(Statement::While {
                 expr,
                 body,
@@ -1100,9 +1107,9 @@ pub(crate) fn parse_statement(
         Rule::if_stm => {
             let mut stm = stm.into_inner();
             let part    = stm.next().unwrap();
-            let expr    = parse_expr(n, part);
+            let expr    = parse_expr(n, part)?;
             let part    = stm.next().unwrap();
-            let body    = parse_block(n, features, stage, part);
+            let body    = parse_block(n, features, stage, part)?;
             *current_if_statement = Some(into.len());
             into.push(Box::new(Statement::If{
                 branches: vec![(loc.clone(), Some(expr), body)],
@@ -1111,15 +1118,14 @@ pub(crate) fn parse_statement(
         Rule::elseif_stm => {
             let mut stm = stm.into_inner();
             let part    = stm.next().unwrap();
-            let expr    = parse_expr(n, part);
+            let expr    = parse_expr(n, part)?;
             let part    = stm.next().unwrap();
-            let body    = parse_block(n, features, stage, part);
+            let body    = parse_block(n, features, stage, part)?;
             match *current_if_statement {
                 None => {
-                    emit_error("else without if", &[
+                    return Err(error("else without if", &[
                         (loc.clone(), "this else branch does not follow an if condition")
-                    ]);
-                    std::process::exit(9);
+                    ]));
                 }
                 Some(c) => {
                     if let Statement::If{ref mut branches} = *into[c] {
@@ -1131,13 +1137,12 @@ pub(crate) fn parse_statement(
         Rule::else_stm => {
             let mut stm = stm.into_inner();
             let part    = stm.next().unwrap();
-            let body    = parse_block(n, features, stage, part);
+            let body    = parse_block(n, features, stage, part)?;
             match *current_if_statement {
                 None => {
-                    emit_error("else without if", &[
+                    return Err(error("else without if", &[
                         (loc.clone(), "this else branch does not follow an if condition")
-                    ]);
-                    std::process::exit(9);
+                    ]));
                 }
                 Some(c) => {
                     if let Statement::If{ref mut branches} = *into[c] {
@@ -1166,18 +1171,18 @@ pub(crate) fn parse_statement(
                         cur += 1;
                     },
                     Rule::block if cur == 3 && block.is_none() => {
-                        block = Some(parse_block(n, features, stage, part));
+                        block = Some(parse_block(n, features, stage, part)?);
                     },
                     _ if cur == 1 => {
                         let mut cif = None;
-                        parse_statement(n, features, stage, part, &mut expr1, &mut cif);
+                        parse_statement(n, features, stage, part, &mut expr1, &mut cif)?;
                     },
                     _ if cur == 2 => {
-                        expr2 = Some(parse_expr(n, part));
+                        expr2 = Some(parse_expr(n, part)?);
                     },
                     _ if cur == 3 => {
                         let mut cif = None;
-                        parse_statement(n, features, stage, part, &mut expr3, &mut cif);
+                        parse_statement(n, features, stage, part, &mut expr3, &mut cif)?;
                     },
                     e => panic!("unexpected rule {:?} in for ", e),
                 }
@@ -1199,14 +1204,14 @@ pub(crate) fn parse_statement(
             for part in stm {
                 match part.as_rule() {
                     Rule::named_type => {
-                        typed = Some(parse_named_type(n, part));
+                        typed = Some(parse_named_type(n, part)?);
                     },
                     Rule::expr => {
-                        assign = Some(parse_expr(n, part));
+                        assign = Some(parse_expr(n, part)?);
                     }
                     Rule::array => {
                         if let Some(expr) = part.into_inner().next() {
-                            array = Some(Some(parse_expr(n, expr)));
+                            array = Some(Some(parse_expr(n, expr)?));
                         } else {
                             array = Some(None);
                         }
@@ -1235,7 +1240,7 @@ pub(crate) fn parse_statement(
             for part in stm {
                 match part.as_rule() {
                     Rule::expr if lhs.is_none() => {
-                        lhs = Some(parse_expr(n, part));
+                        lhs = Some(parse_expr(n, part)?);
                     }
                     Rule::assignop => {
                         op = Some(match part.into_inner().next().unwrap().as_rule() {
@@ -1245,15 +1250,14 @@ pub(crate) fn parse_statement(
                             Rule::assignsub    => AssignOperator::Sub,
                             Rule::assigneq     => AssignOperator::Eq,
                             _ => {
-                                emit_error("ICE: unexpected operator", &[
+                                return Err(error("ICE: unexpected operator", &[
                                     (loc.clone(), "in this assign expr")
-                                ]);
-                                std::process::exit(9);
+                                ]));
                             }
                         });
                     }
                     Rule::expr if rhs.is_none() => {
-                        rhs = Some(parse_expr(n, part));
+                        rhs = Some(parse_expr(n, part)?);
                     }
                     e => panic!("unexpected rule {:?} in assign", e),
                 }
@@ -1269,7 +1273,7 @@ pub(crate) fn parse_statement(
         Rule::switch_stm => {
             let mut stm  = stm.into_inner();
             let mut default = None;
-            let expr = parse_expr(n, stm.next().unwrap());
+            let expr = parse_expr(n, stm.next().unwrap())?;
 
             let mut cases = Vec::new();
 
@@ -1278,20 +1282,19 @@ pub(crate) fn parse_statement(
                 let ppart = part.next().unwrap();
                 if ppart.as_rule() == Rule::key_default {
                     if default.is_some() {
-                        emit_error("multiple default cases", &[
+                        return Err(error("multiple default cases", &[
                             (loc.clone(), "in this switch")
-                        ]);
-                        std::process::exit(9);
+                        ]));
                     } else {
-                        default = Some(parse_block(n, features,  stage,part.next().unwrap()));
+                        default = Some(parse_block(n, features,  stage,part.next().unwrap())?);
                     }
                 } else {
                     let mut case_cond = Vec::new();
                     for case in ppart.into_inner() {
-                        case_cond.push(parse_expr(n, case));
+                        case_cond.push(parse_expr(n, case)?);
                     }
 
-                    let block = parse_block(n, features,  stage,part.next().unwrap());
+                    let block = parse_block(n, features,  stage,part.next().unwrap())?;
                     cases.push((case_cond,block));
                 }
             }
@@ -1304,7 +1307,7 @@ pub(crate) fn parse_statement(
             }))
         },
         Rule::unsafe_block => {
-            into.push(Box::new(Statement::Unsafe(Box::new(parse_block(n, features, stage, stm.into_inner().next().unwrap())))));
+            into.push(Box::new(Statement::Unsafe(Box::new(parse_block(n, features, stage, stm.into_inner().next().unwrap())?))));
         },
         Rule::cblock => {
             let stm = stm.into_inner().next().unwrap();
@@ -1319,6 +1322,7 @@ pub(crate) fn parse_statement(
         },
         e => panic!("unexpected rule {:?} in block", e),
     }
+    Ok(())
 }
 
 pub(crate) fn parse_block(
@@ -1326,7 +1330,7 @@ pub(crate) fn parse_block(
         features:   &HashMap<String,bool>,
         stage:      &Stage,
         decl:       pest::iterators::Pair<'static, Rule>
-) -> Block {
+) -> Result<Block, super::Error> {
     match decl.as_rule() {
         Rule::block => { }
         _ => { panic!("parse_block called with {:?}", decl); }
@@ -1340,13 +1344,14 @@ pub(crate) fn parse_block(
     let mut statements = Vec::new();
     let mut cif_state = None;
     for stm in PP::new(n.1, features.clone(), stage.clone(), decl.into_inner()) {
-        parse_statement(n, features, stage, stm, &mut statements, &mut cif_state)
+        let stm = stm?;
+        parse_statement(n, features, stage, stm, &mut statements, &mut cif_state)?;
     }
-    Block{
+    Ok(Block{
         statements,
         end,
         expanded: false,
-    }
+    })
 }
 
 
@@ -1359,7 +1364,7 @@ pub(crate) struct TypedName {
     tags:   Tags,
 }
 
-pub(crate) fn parse_named_type(n: (&'static str, &Path), decl: pest::iterators::Pair<'static, Rule>) -> TypedName {
+pub(crate) fn parse_named_type(n: (&'static str, &Path), decl: pest::iterators::Pair<'static, Rule>) -> Result<TypedName, super::Error> {
     match decl.as_rule() {
         Rule::named_type => { }
         _ => { panic!("parse_named_type called with {:?}", decl); }
@@ -1411,10 +1416,9 @@ pub(crate) fn parse_named_type(n: (&'static str, &Path), decl: pest::iterators::
                     file: n.1.to_string_lossy().into(),
                     span: name_part.as_span(),
                 };
-                emit_error("syntax error", &[
+                return Err(error("syntax error", &[
                     (loc.clone(), "llegal use of keyword 'return'"),
-                ]);
-                std::process::exit(9);
+                ]));
             }
             name
         }
@@ -1423,10 +1427,9 @@ pub(crate) fn parse_named_type(n: (&'static str, &Path), decl: pest::iterators::
                 file: n.1.to_string_lossy().into(),
                 span: name_part.as_span(),
             };
-            emit_error("syntax error", &[
+            return Err(error("syntax error", &[
                 (loc.clone(), "expected a name")
-            ]);
-            std::process::exit(9);
+            ]));
         }
     };
 
@@ -1460,7 +1463,7 @@ pub(crate) fn parse_named_type(n: (&'static str, &Path), decl: pest::iterators::
 
 
 
-    TypedName {
+    Ok(TypedName {
         name,
         typed: Typed {
             t:   Type::Other(typename),
@@ -1469,10 +1472,10 @@ pub(crate) fn parse_named_type(n: (&'static str, &Path), decl: pest::iterators::
             tail,
         },
         tags,
-    }
+    })
 }
 
-pub(crate) fn parse_anon_type(n: (&'static str, &Path), decl: pest::iterators::Pair<'static, Rule>) -> Typed {
+pub(crate) fn parse_anon_type(n: (&'static str, &Path), decl: pest::iterators::Pair<'static, Rule>) -> Result<Typed, super::Error> {
     match decl.as_rule() {
         Rule::anon_type => { }
         _ => { panic!("parse_anon_type called with {:?}", decl); }
@@ -1533,16 +1536,15 @@ pub(crate) fn parse_anon_type(n: (&'static str, &Path), decl: pest::iterators::P
     }
 
     for (_,tag) in tags.0 {
-        emit_error("syntax error", &[
+        return Err(error("syntax error", &[
             (tag.iter().next().unwrap().1.clone(), "anonymous type cannot have storage tags (yet)"),
-        ]);
-        std::process::exit(9);
+        ]));
     }
 
-    Typed {
+    Ok(Typed {
         t: Type::Other(name),
         loc, ptr, tail,
-    }
+    })
 }
 
 
@@ -1594,7 +1596,7 @@ pub(crate) fn parse_importname(decl: pest::iterators::Pair<Rule>) -> (Name, Vec<
     (Name(v), locals)
 }
 
-fn parse_call(n: (&'static str, &Path), expr: pest::iterators::Pair<'static, Rule>) -> Expression {
+fn parse_call(n: (&'static str, &Path), expr: pest::iterators::Pair<'static, Rule>) -> Result<Expression, super::Error> {
     let loc = Location{
         file: n.1.to_string_lossy().into(),
         span: expr.as_span(),
@@ -1615,14 +1617,14 @@ fn parse_call(n: (&'static str, &Path), expr: pest::iterators::Pair<'static, Rul
         match part.as_rule() {
             Rule::call_args => {
                 args = part.into_inner().into_iter().map(|arg|{
-                    Box::new(parse_expr(n, arg))
-                }).collect();
+                    Ok(Box::new(parse_expr(n, arg)?))
+                }).collect::<Result<_, super::Error>>()?;
             },
             e => panic!("unexpected rule {:?} in function call", e),
         }
     };
 
-    Expression::Call{
+    Ok(Expression::Call{
         name : Box::new(Expression::Literal{
             v: "#error ICE this was supposed to be removed by pre climber pass".to_string(),
             loc: loc.clone()
@@ -1631,7 +1633,7 @@ fn parse_call(n: (&'static str, &Path), expr: pest::iterators::Pair<'static, Rul
         args,
         expanded:       false,
         emit:           EmitBehaviour::Default,
-    }
+    })
 }
 
 use serde::{Serialize};
@@ -1652,15 +1654,43 @@ pub fn emit_error<'a, S1, S2, I>(message: S1, v: I)
           S2: std::string::ToString + 'a,
           I:  std::iter::IntoIterator<Item=&'a (Location, S2)>,
 {
+    let details = v.into_iter().map(|(loc, message)| (loc.clone(), message.to_string())).collect();
+    super::diagnostics::error(&super::Error::new(message.to_string(), details));
+}
+
+/// like emit_error, but for the caller to return instead of reporting it here
+pub fn error<'a, S1, S2, I>(message: S1, v: I) -> super::Error
+    where S1: std::string::ToString,
+          S2: std::string::ToString + 'a,
+          I:  std::iter::IntoIterator<Item=&'a (Location, S2)>,
+{
+    let details = v.into_iter().map(|(loc, message)| (loc.clone(), message.to_string())).collect();
+    super::Error::new(message.to_string(), details)
+}
+
+pub fn emit_warn<'a, S1, S2, I>(message: S1, v: I)
+    where S1: std::string::ToString,
+          S2: std::string::ToString + 'a,
+          I:  std::iter::IntoIterator<Item=&'a (Location, S2)>,
+{
+    let details = v.into_iter().map(|(loc, message)| (loc.clone(), message.to_string())).collect();
+    super::diagnostics::warn(&super::Error::new(message.to_string(), details));
+}
+
+/// how the cli always reported errors: through log, or as json lines for editors
+pub fn log_error(e: &super::Error) {
     if ERRORS_AS_JSON.load(Ordering::SeqCst) {
         let mut j = JsonError::default();
-        j.message   = message.to_string();
+        j.message   = e.message.clone();
         j.level     = "error".to_string();
         j.file_name = "<anon>".to_string();
 
+        if e.details.is_empty() {
+            println!("{}", serde_json::to_string(&j).unwrap());
+        }
 
         let mut first  = true;
-        for (loc, message) in v.into_iter() {
+        for (loc, message) in &e.details {
             j.file_name     = loc.file.clone();
             j.line_start    = loc.span.start_pos().line_col().0;
             j.column_start  = loc.span.start_pos().line_col().1;
@@ -1677,14 +1707,12 @@ pub fn emit_error<'a, S1, S2, I>(message: S1, v: I)
             j.message   = message.to_string();
             println!("{}", serde_json::to_string(&j).unwrap());
         }
-
-
         return;
     }
 
 
-    let mut s : String = message.to_string();
-    for (loc, message)  in v.into_iter() {
+    let mut s : String = e.message.clone();
+    for (loc, message)  in &e.details {
         let e = pest::error::Error::<Rule>::new_from_span(pest::error::ErrorVariant::CustomError {
             message: message.to_string(),
         }, loc.span.clone()).with_path(&loc.file);
@@ -1693,18 +1721,14 @@ pub fn emit_error<'a, S1, S2, I>(message: S1, v: I)
     error!("{}", s);
 }
 
-pub fn emit_warn<'a, S1, S2, I>(message: S1, v: I)
-    where S1: std::string::ToString,
-          S2: std::string::ToString + 'a,
-          I:  std::iter::IntoIterator<Item=&'a (Location, S2)>,
-{
+pub fn log_warn(e: &super::Error) {
     if ERRORS_AS_JSON.load(Ordering::SeqCst) {
         let mut j = JsonError::default();
-        j.message   = message.to_string();
+        j.message   = e.message.clone();
         j.level     = "warn".to_string();
         j.file_name = "<anon>".to_string();
 
-        if let Some((loc,_)) = v.into_iter().next() {
+        if let Some((loc,_)) = e.details.first() {
             j.file_name     = loc.file.clone();
             j.line_start    = loc.span.start_pos().line_col().0;
             j.column_start  = loc.span.start_pos().line_col().1;
@@ -1716,8 +1740,8 @@ pub fn emit_warn<'a, S1, S2, I>(message: S1, v: I)
         return;
     }
 
-    let mut s : String = message.to_string();
-    for (loc, message)  in v.into_iter() {
+    let mut s : String = e.message.clone();
+    for (loc, message)  in &e.details {
         let e = pest::error::Error::<Rule>::new_from_span(pest::error::ErrorVariant::CustomError {
             message: message.to_string(),
         }, loc.span.clone()).with_path(&loc.file);
@@ -1746,7 +1770,7 @@ pub fn emit_debug<'a, S1, S2, I>(message: S1, v: I)
     debug!("{}", s);
 }
 
-fn unescape(s: &str, loc: &Location) -> Vec<u8> {
+fn unescape(s: &str, loc: &Location) -> Result<Vec<u8>, super::Error> {
     let mut result = Vec::with_capacity(s.len());
     let mut chars = s.chars();
     while let Some(ch) = chars.next() {
@@ -1758,10 +1782,9 @@ fn unescape(s: &str, loc: &Location) -> Vec<u8> {
                     Some('x') => {
                         let value = chars.by_ref().take(2).fold(0, |acc, c| acc * 16 + c.to_digit(16).unwrap());
                         if value > 255 {
-                            emit_error("octal value too big for char", &[
+                            return Err(error("octal value too big for char", &[
                                 (loc.clone(), "in this literal string")
-                            ]);
-                            std::process::exit(9);
+                            ]));
                         }
                         value as u8
                     }
@@ -1777,16 +1800,15 @@ fn unescape(s: &str, loc: &Location) -> Vec<u8> {
                     Some('"') => '"' as u8,
                     Some('\'') => '\'' as u8,
                     _ => {
-                        emit_error("unsupported escape character", &[
+                        return Err(error("unsupported escape character", &[
                             (loc.clone(), "in this literal string")
-                        ]);
-                        std::process::exit(9);
+                        ]));
                     }
                 }
             }
         )
     }
-    result
+    Ok(result)
 }
 
 
@@ -1,23 +1,128 @@
 use pest;
-use super::parser::{Rule, emit_warn, emit_error};
+use super::parser::{Rule, emit_warn, error};
 use super::ast;
 use super::name::Name;
+use super::Error;
 use std::path::{Path, PathBuf};
 use std::collections::HashMap;
-use crate::make::Stage;
+use crate::make::{Sanitizer, Stage};
 
 pub struct PP {
     decl:       pest::iterators::Pairs<'static, Rule>,
     n:          PathBuf,
-    stack:      Vec<bool>,
+    // whether the current branch is on, and whether any branch of its #if was
+    stack:      Vec<(bool, bool)>,
     features:   HashMap<String,bool>,
     stage:      Stage,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Bool(bool),
     String(String),
+    Int(i64),
+}
+
+/// what os(), arch(), pointer_width() and endian() test.
+/// the TARGET triple if one is set for cross compiling, otherwise the machine zz runs on
+#[derive(Debug)]
+pub struct Platform {
+    pub os:             String,
+    /// unix, windows or nothing
+    pub family:         String,
+    pub arch:           String,
+    pub pointer_width:  i64,
+    pub endian:         String,
+}
+
+impl Platform {
+    pub fn get() -> &'static Platform {
+        static PLATFORM: std::sync::OnceLock<Platform> = std::sync::OnceLock::new();
+        PLATFORM.get_or_init(|| {
+            match std::env::var("TARGET") {
+                Ok(triple) if !triple.is_empty() => Platform::from_triple(&triple),
+                _ => Platform::host(),
+            }
+        })
+    }
+
+    fn host() -> Platform {
+        Platform {
+            os:             std::env::consts::OS.to_string(),
+            family:         std::env::consts::FAMILY.to_string(),
+            arch:           std::env::consts::ARCH.to_string(),
+            pointer_width:  if cfg!(target_pointer_width = "64") { 64 } else if cfg!(target_pointer_width = "16") { 16 } else { 32 },
+            endian:         if cfg!(target_endian = "big") { "big" } else { "little" }.to_string(),
+        }
+    }
+
+    /// triples as rust and llvm spell them, like x86_64-unknown-linux-gnu or thumbv7em-none-eabihf.
+    /// names follow rust's cfg values, so arm64 is aarch64 and i686 is x86
+    pub fn from_triple(triple: &str) -> Platform {
+        let mut parts = triple.split('-');
+        let raw = parts.next().unwrap_or("");
+        let rest : Vec<&str> = parts.collect();
+        let has = |name: &str| rest.iter().any(|p| p.starts_with(name));
+
+        let arch = match raw {
+            "i386" | "i486" | "i586" | "i686" => "x86",
+            "amd64"                         => "x86_64",
+            "arm64" | "aarch64_be"          => "aarch64",
+            "ppc" | "powerpcle"             => "powerpc",
+            "ppc64" | "ppc64le" | "powerpc64le" => "powerpc64",
+            a if a.starts_with("arm") || a.starts_with("thumb") => "arm",
+            a if a.starts_with("riscv64")   => "riscv64",
+            a if a.starts_with("riscv32")   => "riscv32",
+            a if a.starts_with("mips64")    => "mips64",
+            a if a.starts_with("mips")      => "mips",
+            a => a,
+        }.to_string();
+
+        let os = if has("android") {
+            "android"
+        } else if has("linux") {
+            "linux"
+        } else if has("darwin") || has("macos") {
+            "macos"
+        } else if has("ios") {
+            "ios"
+        } else if has("windows") || has("mingw") || has("win32") {
+            "windows"
+        } else {
+            ["freebsd", "netbsd", "openbsd", "dragonfly", "solaris", "illumos", "emscripten", "wasi"].iter()
+                .find(|os| has(os)).copied().unwrap_or("none")
+        }.to_string();
+
+        let family = match os.as_str() {
+            "windows" => "windows",
+            "none" | "wasi" => "",
+            _ => "unix",
+        }.to_string();
+
+        let pointer_width = match arch.as_str() {
+            _ if rest.last().map(|e| e.ends_with("x32")).unwrap_or(false) => 32,
+            "x86_64" | "aarch64" | "riscv64" | "mips64" | "powerpc64" | "s390x" | "sparc64" | "wasm64" | "loongarch64" => 64,
+            "avr" | "msp430" => 16,
+            _ => 32,
+        };
+
+        let endian = if raw.ends_with("le") || raw.ends_with("el") {
+            "little"
+        } else if raw.ends_with("eb") || raw.ends_with("_be") ||
+            ["mips", "mips64", "powerpc", "powerpc64", "ppc", "ppc64", "s390x", "sparc", "sparc64", "m68k"].contains(&raw) {
+            "big"
+        } else {
+            "little"
+        }.to_string();
+
+        Platform {
+            os,
+            family,
+            arch,
+            pointer_width,
+            endian,
+        }
+    }
 }
 
 
@@ -32,18 +137,57 @@ impl PP {
         }
     }
 
-    pub fn eval(&self, termish: pest::iterators::Pair<'static, Rule>)  -> Value {
-        let loc = ast::Location{
+    fn location(&self, pair: &pest::iterators::Pair<'static, Rule>) -> ast::Location {
+        ast::Location{
             file: self.n.to_string_lossy().into(),
-            span: termish.as_span(),
+            span: pair.as_span(),
+        }
+    }
+
+    pub fn eval(&self, expr: pest::iterators::Pair<'static, Rule>)  -> Result<Value, Error> {
+        let loc = self.location(&expr);
+        let mut expr = expr.into_inner();
+        let lhs = self.term(expr.next().unwrap())?;
+        let op = match expr.next() {
+            None => return Ok(lhs),
+            Some(op) => op.as_rule(),
         };
-        let expr = termish.into_inner().next().unwrap();
-        match expr.as_rule() {
+        let rhs = self.term(expr.next().unwrap())?;
+
+        Ok(match (op, &lhs, &rhs) {
+            (Rule::equals,  a, b) => Value::Bool(a == b),
+            (Rule::nequals, a, b) => Value::Bool(a != b),
+            (Rule::moreeq,   Value::Int(a), Value::Int(b)) => Value::Bool(a >= b),
+            (Rule::lesseq,   Value::Int(a), Value::Int(b)) => Value::Bool(a <= b),
+            (Rule::lessthan, Value::Int(a), Value::Int(b)) => Value::Bool(a < b),
+            (Rule::morethan, Value::Int(a), Value::Int(b)) => Value::Bool(a > b),
+            _ => {
+                return Err(error("only integers can be compared by size", &[
+                       (loc, format!("this is {:?} and {:?}", lhs, rhs)),
+                ]));
+            }
+        })
+    }
+
+    fn term(&self, expr: pest::iterators::Pair<'static, Rule>)  -> Result<Value, Error> {
+        let loc = self.location(&expr);
+        Ok(match expr.as_rule() {
             Rule::number_literal => {
-                if expr.as_str() == "0" {
-                    Value::Bool(false)
+                let s = expr.as_str();
+                let v = if let Some(hex) = s.strip_prefix("0x") {
+                    i64::from_str_radix(hex, 16)
+                } else if let Some(bits) = s.strip_prefix("0b") {
+                    i64::from_str_radix(bits, 2)
                 } else {
-                    Value::Bool(true)
+                    s.parse()
+                };
+                match v {
+                    Ok(v) => Value::Int(v),
+                    Err(_) => {
+                        return Err(error("only integers can be used in preprocessor directive", &[
+                               (loc, "used here"),
+                        ]));
+                    }
                 }
             },
             Rule::string_literal => {
@@ -53,167 +197,181 @@ impl PP {
                 Value::String(s)
 
             },
+            Rule::bool_literal => {
+                Value::Bool(expr.as_str() == "true")
+            },
+            Rule::pp_expr => {
+                self.eval(expr)?
+            },
             Rule::pp_call => {
                 let mut expr = expr.into_inner();
                 let name = Name::from(expr.next().unwrap().as_str());
-                let args = match expr.next() {
-                    Some(args) => {
-                        args.into_inner().into_iter().map(|arg|{
-                            self.eval(arg)
-                        }).collect()
-                    },
-                    None => {
-                        Vec::new()
-                    }
-                };
-
-                match name.0.join("::").as_str() {
-                    "feature" => {
-                        if args.len() != 1 {
-                            emit_error("wrong number of arguments to feature. expected 1", &[
-                                   (loc, "called here"),
-                            ]);
-                            std::process::exit(9);
-                        }
-
-                        let s = match &args[0] {
-                            Value::String(s) => s,
-                            _ => {
-                                emit_error("argument to feature must be a string", &[
-                                           (loc, "called here"),
-                                ]);
-                                std::process::exit(9);
-                            },
-                        };
+                let args = expr.map(|arg|self.eval(arg)).collect::<Result<Vec<Value>, Error>>()?;
+                self.call(loc, &name.0.join("::"), args)?
+            },
+            _ => panic!("unexpected rule {:?} in preprocessor", expr.as_rule()),
+        })
+    }
 
-                        match self.features.get(s) {
-                            None => {
-                                emit_warn("undefined feature defaults to false", &[
-                                          (loc, "avoid this warning by defining it explicitly in zz.toml"),
-                                ]);
-                                Value::Bool(false)
-                            },
-                            Some(v) => Value::Bool(*v),
-                        }
+    fn call(&self, loc: ast::Location, name: &str, args: Vec<Value>) -> Result<Value, Error> {
+        Ok(match name {
+            "feature" => {
+                if args.len() != 1 {
+                    return Err(error("wrong number of arguments to feature. expected 1", &[
+                           (loc, "called here"),
+                    ]));
+                }
 
-                    },
-                    "def" => {
-                        if args.len() != 1 {
-                            emit_error("wrong number of arguments to def. expected 1", &[
+                let s = match &args[0] {
+                    Value::String(s) => s,
+                    _ => {
+                        return Err(error("argument to feature must be a string", &[
                                    (loc, "called here"),
-                            ]);
-                            std::process::exit(9);
-                        }
+                        ]));
+                    },
+                };
 
-                        match &args[0] {
-                            Value::String(s) if s == "debug" => Value::Bool(true),
-                            _ => {
-                                emit_warn("undefined def defaults to false", &[
-                                       (loc, "avoid this warning by defining it explicitly"),
-                                ]);
-                                Value::Bool(false)
-                            }
-                        }
+                match self.features.get(s) {
+                    None => {
+                        return Err(error(&format!("undefined feature '{}'", s), &[
+                                   (loc, "define it in [features] in zz.toml"),
+                        ]));
                     },
-                    "target" => {
-                        if args.len() != 2 {
-                            emit_error("wrong number of arguments to def. expected 2", &[
-                                   (loc, "called here"),
-                            ]);
-                            std::process::exit(9);
-                        }
+                    Some(v) => Value::Bool(*v),
+                }
+
+            },
+            "def" => {
+                if args.len() != 1 {
+                    return Err(error("wrong number of arguments to def. expected 1", &[
+                           (loc, "called here"),
+                    ]));
+                }
 
-                        match &args[0] {
-                            Value::String(s) if s == "asan" => {
-                                match &args[1] {
-                                    Value::Bool(s)  => {
-                                        Value::Bool(s == &self.stage.asan)
-                                    }
-                                    _ => {
-                                        emit_warn("invalid attribute value defaults to false", &[
-                                                  (loc, "target attribute needs to be a string"),
-                                        ]);
-                                        Value::Bool(false)
-                                    }
-                                }
-                            },
-                            Value::String(s) if s == "debug" => {
-                                match &args[1] {
-                                    Value::Bool(s)  => {
-                                        Value::Bool(s == &self.stage.debug)
-                                    }
-                                    _ => {
-                                        emit_warn("invalid attribute value defaults to false", &[
-                                                  (loc, "target attribute needs to be a string"),
-                                        ]);
-                                        Value::Bool(false)
-                                    }
-                                }
-                            },
-                            Value::String(s)  => {
-                                emit_warn("undefined target attribute defaults to false", &[
-                                          (loc, format!("{} is not a known target attribute", s)),
-                                ]);
-                                Value::Bool(false)
-                            },
-                            _ => {
-                                emit_warn("invalid target attribute defaults to false", &[
-                                          (loc, "target attribute needs to be a string"),
-                                ]);
-                                Value::Bool(false)
-                            }
+                match &args[0] {
+                    Value::String(s) if self.stage.defines.contains_key(s) => {
+                        let v = &self.stage.defines[s];
+                        match v.as_str() {
+                            "true"  => Value::Bool(true),
+                            "false" => Value::Bool(false),
+                            _ => v.parse().map(Value::Int).unwrap_or_else(|_| Value::String(v.clone())),
                         }
                     },
-                    n => {
-                        emit_error(format!("function '{}' not available in preprocessor directive",n),  &[
-                               (loc, "used here"),
+                    Value::String(s) if s == "debug" => Value::Bool(true),
+                    _ => {
+                        emit_warn("undefined def defaults to false", &[
+                               (loc, "avoid this warning by defining it in [defines] in zz.toml or with -D"),
                         ]);
-                        std::process::exit(9);
+                        Value::Bool(false)
                     }
                 }
+            },
+            "os" | "arch" | "pointer_width" | "endian" => {
+                let platform = Platform::get();
+                let v = match name {
+                    "os"    => Value::String(platform.os.clone()),
+                    "arch"  => Value::String(platform.arch.clone()),
+                    "pointer_width" => Value::Int(platform.pointer_width),
+                    _       => Value::String(platform.endian.clone()),
+                };
+                match args.as_slice() {
+                    // without an argument, the value itself, to compare with
+                    [] => v,
+                    [Value::String(s)] if name == "os" && !s.is_empty() && s == &platform.family => Value::Bool(true),
+                    [a] => Value::Bool(a == &v),
+                    _ => {
+                        return Err(error(format!("wrong number of arguments to {}. expected 0 or 1", name), &[
+                               (loc, "called here"),
+                        ]));
+                    }
+                }
+            },
+            "all" | "any" => {
+                let v = args.iter().map(|arg| self.truth(&loc, arg)).collect::<Result<Vec<bool>, Error>>()?;
+                Value::Bool(if name == "all" { v.into_iter().all(|v| v) } else { v.into_iter().any(|v| v) })
+            },
+            "not" => {
+                if args.len() != 1 {
+                    return Err(error("wrong number of arguments to not. expected 1", &[
+                           (loc, "called here"),
+                    ]));
+                }
+                Value::Bool(!self.truth(&loc, &args[0])?)
+            },
+            "target" => {
+                if args.len() != 2 {
+                    return Err(error("wrong number of arguments to target. expected 2", &[
+                           (loc, "called here"),
+                    ]));
+                }
 
-
+                let is = match &args[0] {
+                    Value::String(s) if s == "asan"      => self.stage.sanitize.contains(&Sanitizer::Address),
+                    Value::String(s) if s == "debug"     => self.stage.debug,
+                    Value::String(s) if s == "contracts" => self.stage.contracts,
+                    Value::String(s)  => {
+                        emit_warn("undefined target attribute defaults to false", &[
+                                  (loc, format!("{} is not a known target attribute", s)),
+                        ]);
+                        return Ok(Value::Bool(false));
+                    },
+                    _ => {
+                        emit_warn("invalid target attribute defaults to false", &[
+                                  (loc, "target attribute needs to be a string"),
+                        ]);
+                        return Ok(Value::Bool(false));
+                    }
+                };
+                match &args[1] {
+                    Value::Bool(b) => Value::Bool(*b == is),
+                    Value::Int(i)  => Value::Bool((*i != 0) == is),
+                    _ => {
+                        emit_warn("invalid attribute value defaults to false", &[
+                                  (loc, "target attribute value needs to be a boolean"),
+                        ]);
+                        Value::Bool(false)
+                    }
+                }
             },
-            _ => {
-                emit_error(format!("{:?} expression cannot (yet) be used in preprocessor directive", expr.as_rule()), &[
+            n => {
+                return Err(error(format!("function '{}' not available in preprocessor directive",n),  &[
                        (loc, "used here"),
-                ]);
-                std::process::exit(9);
+                ]));
             }
-        }
+        })
     }
 
-    fn push(&mut self, loc: ast::Location, v: Value) {
+    // like in C, any integer but 0 is true
+    fn truth(&self, loc: &ast::Location, v: &Value) -> Result<bool, Error> {
         match v {
-            Value::Bool(false) => {
-                self.stack.push(false);
+            Value::Bool(b) => Ok(*b),
+            Value::Int(i)  => Ok(*i != 0),
+            Value::String(_) => {
+                Err(error("preprocessor directive must evaluate to boolean", &[
+                       (loc.clone(), format!("this expression = '{:?}'", v)),
+                ]))
             }
-            Value::Bool(true) => {
-                self.stack.push(true);
-            },
-            _ => {
-                emit_error("preprocessor directive must evaluate to boolean", &[
-                       (loc, format!("this expression = '{:?}'", v)),
-                ]);
-                std::process::exit(9);
-            }
-
         }
     }
 
-    fn pop(&mut self, loc: &ast::Location) -> bool {
+    fn push(&mut self, loc: ast::Location, v: Value) -> Result<(), Error> {
+        let v = self.truth(&loc, &v)?;
+        self.stack.push((v, v));
+        Ok(())
+    }
+
+    fn pop(&mut self, loc: &ast::Location) -> Result<(bool, bool), Error> {
         if self.stack.len() < 1 {
-            emit_error("missing preceeding #if directive", &[
+            return Err(error("missing preceeding #if directive", &[
                 (loc.clone(), "here"),
-            ]);
-            std::process::exit(9);
+            ]));
         }
-        self.stack.pop().unwrap()
+        Ok(self.stack.pop().unwrap())
     }
 }
 
 impl Iterator for PP {
-    type Item = pest::iterators::Pair<'static, Rule>;
+    type Item = Result<pest::iterators::Pair<'static, Rule>, Error>;
     fn next(&mut self) -> Option<Self::Item> {
         let decl = match self.decl.next() {
             None => return None,
@@ -228,42 +386,58 @@ impl Iterator for PP {
             };
             match decl.as_rule() {
                 Rule::ppelif  => {
-                    let previous = self.pop(&loc);
-                    if previous {
-                        self.push(loc, Value::Bool(previous));
+                    let (_, taken) = match self.pop(&loc) {
+                        Ok(v) => v,
+                        Err(e) => return Some(Err(e)),
+                    };
+                    if taken {
+                        self.stack.push((false, true));
                     } else {
                         let expr = decl.into_inner().next().unwrap();
                         let loc = ast::Location{
                             file: self.n.to_string_lossy().into(),
                             span: expr.as_span(),
                         };
-                        self.push(loc, self.eval(expr));
+                        if let Err(e) = self.eval(expr).and_then(|v| self.push(loc, v)) {
+                            return Some(Err(e));
+                        }
                     }
                 },
+                // nested in a branch that is off, nothing is evaluated and every branch stays off
+                Rule::ppif if self.stack.last().map(|b| !b.0).unwrap_or(false) => {
+                    self.stack.push((false, true));
+                },
                 Rule::ppif => {
                     let expr = decl.into_inner().next().unwrap();
                     let loc = ast::Location{
                         file: self.n.to_string_lossy().into(),
                         span: expr.as_span(),
                     };
-                    self.push(loc, self.eval(expr));
+                    if let Err(e) = self.eval(expr).and_then(|v| self.push(loc, v)) {
+                        return Some(Err(e));
+                    }
                 },
                 Rule::ppelse => {
-                    let invert = Value::Bool(!self.pop(&loc));
-                    self.push(loc, invert);
+                    let (_, taken) = match self.pop(&loc) {
+                        Ok(v) => v,
+                        Err(e) => return Some(Err(e)),
+                    };
+                    self.stack.push((!taken, true));
                 },
                 Rule::ppendif => {
-                    self.pop(&loc);
+                    if let Err(e) = self.pop(&loc) {
+                        return Some(Err(e));
+                    }
                 },
                 _ => panic!("unexpected rule {:?} in preprocessor", decl.as_rule()),
             }
             return self.next();
         }
 
-        if let Some(false) = self.stack.last() {
+        if let Some((false, _)) = self.stack.last() {
             return self.next();
         } else {
-            return Some(decl);
+            return Some(Ok(decl));
         }
     }
 }
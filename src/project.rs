@@ -1,9 +1,10 @@
 use serde::{Serialize, Deserialize};
 use std::fs::File;
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::collections::HashMap;
 use toml::Value;
+use super::Error;
 
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -29,6 +30,9 @@ pub struct Artifact {
     #[serde(rename = "type")]
     pub typ:        ArtifactType,
     pub indexjs:    Option<String>,
+    /// enabled for this artifact in addition to the variant's
+    #[serde(default)]
+    pub features:   Vec<String>,
 }
 
 
@@ -39,6 +43,7 @@ impl Default for Artifact {
             main:       String::new(),
             typ:        ArtifactType::Lib,
             indexjs:    None,
+            features:   Vec::new(),
         }
     }
 }
@@ -56,6 +61,12 @@ pub struct Feature {
     pub cflags:     Vec<String>,
     #[serde(default)]
     pub lflags:     Vec<String>,
+    /// other features this one turns on
+    #[serde(default)]
+    pub features:   Vec<String>,
+    /// optional dependencies this one turns on
+    #[serde(default)]
+    pub dependencies: Vec<String>,
 }
 
 #[derive(Clone, Default, Serialize, Deserialize)]
@@ -76,6 +87,39 @@ pub struct Project {
     pub lflags:     Vec<String>,
 }
 
+/// [profile.<name>] changes a built in stage of the same name, or defines a new one.
+/// anything left out stays as the stage it inherits from has it
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Profile {
+    /// the built in stage a new profile starts from. test by default
+    pub inherits:   Option<String>,
+    /// the level passed as -O, a number or a string such as "s"
+    pub optimize:   Option<Value>,
+    pub lto:        Option<bool>,
+    pub asan:       Option<bool>,
+    /// replaces the sanitizers of the stage: address, undefined, memory, thread, leak
+    pub sanitize:   Option<Vec<String>>,
+    pub debug:      Option<bool>,
+    pub pic:        Option<bool>,
+    /// symbolic execution. without it nothing is proven, only emitted
+    pub prove:      Option<bool>,
+    #[serde(default)]
+    pub cflags:     Vec<String>,
+    #[serde(default)]
+    pub lflags:     Vec<String>,
+}
+
+/// [build] runs a generator before anything is parsed.
+/// what it writes to the directory in ZZ_GEN_DIR is loaded like src/, .zz as modules and .h as C modules
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Build {
+    /// a directory with a zz project, which is built and run with zz run, or else a shell command
+    pub generator:  String,
+    /// files and directories the generator reads. it only runs again when one of them changed
+    #[serde(default)]
+    pub inputs:     Vec<String>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub enum Dependency {
     V(String)
@@ -92,6 +136,16 @@ pub struct Config {
     pub variants:       HashMap<String, Vec<String>>,
 
     pub dependencies:   Option<HashMap<String, Value>>,
+
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub profile:        HashMap<String, Profile>,
+
+    /// values for #if def(), unless given with -D
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub defines:        HashMap<String, Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build:          Option<Build>,
 }
 
 pub fn init() {
@@ -105,6 +159,9 @@ pub fn init() {
         dependencies:   Some(HashMap::new()),
         features:       None,
         variants:       HashMap::new(),
+        profile:        HashMap::new(),
+        defines:        HashMap::new(),
+        build:          None,
     };
     c.variants.insert("default".to_string(), Vec::new());
 
@@ -138,16 +195,31 @@ export fn main() -> int {{
     println!("project '{}' created", c.project.name);
 }
 
-pub fn load_cwd() -> (PathBuf, Config) {
+fn fail<T>(message: String) -> Result<T, Error> {
+    Err(Error::new(message, Vec::new()))
+}
+
+/// where the quoted text first appears in the zz.toml at root, to point an error at it
+pub fn locate(root: &Path, text: &str) -> Option<super::ast::Location> {
+    let file = root.join("zz.toml");
+    let content = std::fs::read_to_string(&file).ok()?;
+    let start = content.find(&format!("\"{}\"", text))?;
+    let content : &'static str = Box::leak(content.into_boxed_str());
+    Some(super::ast::Location{
+        file: file.to_string_lossy().into(),
+        span: pest::Span::new(content, start, start + text.len() + 2)?,
+    })
+}
+
+pub fn load_cwd() -> Result<(PathBuf, Config), Error> {
     let mut search = std::env::current_dir().unwrap();
     loop {
         if !search.join("zz.toml").exists() {
             search = match search.parent() {
                 Some(v) => v.into(),
                 None => {
-                    error!("error: could not find \"zz.toml\" in {:?} or any parent directory",
-                              std::env::current_dir().unwrap());
-                    std::process::exit(9);
+                    return fail(format!("could not find \"zz.toml\" in {:?} or any parent directory",
+                              std::env::current_dir().unwrap()));
                 }
             }
         } else {
@@ -157,26 +229,39 @@ pub fn load_cwd() -> (PathBuf, Config) {
     load(&search)
 }
 
-pub fn load(search: &std::path::Path) -> (PathBuf, Config) {
+pub fn load(search: &std::path::Path) -> Result<(PathBuf, Config), Error> {
 
-    let mut f = File::open(&search.join("zz.toml")).expect(&format!("cannot open {:?}", search));
+    let mut f = match File::open(&search.join("zz.toml")) {
+        Ok(f) => f,
+        Err(e) => return fail(format!("cannot open {:?}: {}", search.join("zz.toml"), e)),
+    };
     let mut s = String::new();
-    f.read_to_string(&mut s).expect(&format!("cannot read {:?}", search));
-    let mut c : Config = toml::from_str(&mut s).expect(&format!("cannot read {:?}", search));
+    if let Err(e) = f.read_to_string(&mut s) {
+        return fail(format!("cannot read {:?}: {}", search.join("zz.toml"), e));
+    }
+    if is_workspace(&s) {
+        return fail(format!("{:?} is a workspace, run this in one of its members", search));
+    }
+    let mut c : Config = match toml::from_str(&mut s) {
+        Ok(c) => c,
+        Err(e) => return fail(format!("cannot read {:?}: {}", search.join("zz.toml"), e)),
+    };
 
 
     // implicit features
     if !c.variants.contains_key("default") {
         c.variants.insert("default".to_string(), Default::default());
     }
-    for (_,features) in &c.variants {
-        for f in features {
-            if c.features.is_none() {
-                c.features = Some(HashMap::new());
-            }
-            if !c.features.as_mut().unwrap().contains_key(f) {
-                c.features.as_mut().unwrap().insert(f.to_string(), Default::default());
-            }
+    let artifact_features = c.artifacts.iter().flatten().map(|a| &a.features);
+    let mut implicit : Vec<String> = c.variants.values().chain(artifact_features).flatten().cloned().collect();
+    implicit.sort();
+    implicit.dedup();
+    for f in &implicit {
+        if c.features.is_none() {
+            c.features = Some(HashMap::new());
+        }
+        if !c.features.as_mut().unwrap().contains_key(f) {
+            c.features.as_mut().unwrap().insert(f.to_string(), Default::default());
         }
     }
 
@@ -202,7 +287,7 @@ pub fn load(search: &std::path::Path) -> (PathBuf, Config) {
         c.artifacts = Some(a);
     }
 
-    if let Ok(dd) = std::fs::read_dir("./tests/") {
+    if let Ok(dd) = std::fs::read_dir(search.join("tests")) {
         for entry in dd {
             let entry = entry.unwrap();
             let path  = entry.path();
@@ -221,31 +306,269 @@ pub fn load(search: &std::path::Path) -> (PathBuf, Config) {
         }
     }
 
-    (search.into(), c)
+    // features can only turn on dependencies that are off without them
+    for (n, feature) in c.features.iter().flatten() {
+        for dep in &feature.dependencies {
+            match c.dependencies.as_ref().and_then(|deps| deps.get(dep)) {
+                Some(Value::Table(t)) if t.get("optional") == Some(&Value::Boolean(true)) => (),
+                _ => return fail(format!("feature {} enables \"{}\", which is not an optional dependency", n, dep)),
+            }
+        }
+    }
+
+    Ok((search.into(), c))
 }
 
 
 impl Config {
-    pub fn features(&self, variant: &str) -> HashMap<String, (bool, Feature)> {
+    pub fn features(&self, variant: &str) -> Result<HashMap<String, (bool, Feature)>, Error> {
+        self.features_for(variant, &[])
+    }
 
-        match self.variants.get(variant) {
+    /// every feature of the project, and whether the variant together with extra turns it on,
+    /// directly or through other features
+    pub fn features_for(&self, variant: &str, extra: &[String]) -> Result<HashMap<String, (bool, Feature)>, Error> {
+        let v = match self.variants.get(variant) {
             None => {
-                error!("variant {} not defined", variant);
-                std::process::exit(9);
+                return fail(format!("variant {} not defined", variant));
             },
-            Some(v) => {
-                let mut r = HashMap::new();
-                if let Some(features) = &self.features {
-                    for (n,feature) in features {
-                        r.insert(n.to_string(), (false, feature.clone()));
-                    }
-                }
-                for v in v {
-                    r.get_mut(v).unwrap().0 = true;
+            Some(v) => v,
+        };
+
+        let mut r = HashMap::new();
+        if let Some(features) = &self.features {
+            for (n,feature) in features {
+                r.insert(n.to_string(), (false, feature.clone()));
+            }
+        }
+
+        let mut need : Vec<String> = v.iter().chain(extra).cloned().collect();
+        while let Some(n) = need.pop() {
+            let feature = match r.get_mut(&n) {
+                Some(v) => v,
+                None => return fail(format!("feature {} is not defined in [features]", n)),
+            };
+            if feature.0 {
+                continue;
+            }
+            feature.0 = true;
+            need.extend(feature.1.features.iter().cloned());
+        }
+        Ok(r)
+    }
+
+    /// names of the dependencies to build with these features.
+    /// a dependency declared as a table is only built when it is optional and an enabled feature names it
+    pub fn dependencies(&self, features: &HashMap<String, (bool, Feature)>) -> Vec<String> {
+        let deps = match &self.dependencies {
+            Some(v) => v,
+            None => return Vec::new(),
+        };
+        // load made sure these are all optional
+        let enabled : Vec<&String> = features.values().filter(|(on, _)| *on)
+            .flat_map(|(_, feature)| feature.dependencies.iter()).collect();
+
+        let mut r : Vec<String> = deps.iter().filter_map(|(name, dep)| match dep {
+            Value::String(_) => Some(name.clone()),
+            Value::Table(t) if t.get("optional") == Some(&Value::Boolean(true)) => {
+                if enabled.contains(&name) { Some(name.clone()) } else { None }
+            }
+            _ => None,
+        }).collect();
+        r.sort();
+        r
+    }
+}
+
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Workspace {
+    pub members:    Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct WorkspaceConfig {
+    workspace:      Workspace,
+    project:        Option<Value>,
+}
+
+/// a workspace root with its members in dependency order
+pub struct Members {
+    pub root:       PathBuf,
+    // project name and directory
+    pub members:    Vec<(String, PathBuf)>,
+}
+
+fn is_workspace(toml: &str) -> bool {
+    match toml::from_str::<Value>(toml) {
+        Ok(Value::Table(t)) => t.contains_key("workspace"),
+        _ => false,
+    }
+}
+
+// the directory of the closest zz.toml, starting at dir
+fn find_toml(dir: &Path) -> Option<PathBuf> {
+    let mut search = dir.to_path_buf();
+    loop {
+        if search.join("zz.toml").exists() {
+            return Some(search);
+        }
+        search = search.parent()?.into();
+    }
+}
+
+/// the workspace that the current directory is the root of, if any
+pub fn workspace_cwd() -> Result<Option<Members>, Error> {
+    let dir = match find_toml(&std::env::current_dir().unwrap()) {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    match std::fs::read_to_string(dir.join("zz.toml")) {
+        Ok(s) if is_workspace(&s) => Ok(Some(load_workspace(&dir, &s)?)),
+        _ => Ok(None),
+    }
+}
+
+/// the workspace that the project at root is a member of, if any
+pub fn workspace_of(root: &Path) -> Result<Option<Members>, Error> {
+    match member_of(root) {
+        Some((wsroot, s)) => Ok(Some(load_workspace(&wsroot, &s)?)),
+        None => Ok(None),
+    }
+}
+
+// the root and zz.toml of the workspace listing root as a member.
+// only reads the list of members, not their zz.toml
+fn member_of(root: &Path) -> Option<(PathBuf, String)> {
+    let root = root.canonicalize().ok()?;
+    let mut search = root.parent()?.to_path_buf();
+    loop {
+        search = find_toml(&search)?;
+        let s = std::fs::read_to_string(search.join("zz.toml")).ok()?;
+        if is_workspace(&s) {
+            if let Ok(c) = toml::from_str::<WorkspaceConfig>(&s) {
+                if c.workspace.members.iter().any(|m| search.join(m).canonicalize().ok().as_ref() == Some(&root)) {
+                    return Some((search, s));
                 }
-                r
             }
         }
+        search = search.parent()?.to_path_buf();
+    }
+}
+
+fn load_workspace(root: &Path, toml: &str) -> Result<Members, Error> {
+    let c : WorkspaceConfig = match toml::from_str(toml) {
+        Ok(c) => c,
+        Err(e) => return fail(format!("cannot read {:?}: {}", root.join("zz.toml"), e)),
+    };
+    if c.project.is_some() {
+        return fail(format!("{:?} has both [workspace] and [project], move the project into a member", root.join("zz.toml")));
+    }
+
+    let mut members = Vec::new();
+    for member in &c.workspace.members {
+        let dir = match root.join(member).canonicalize() {
+            Ok(v) if v.join("zz.toml").exists() => v,
+            _ => {
+                return fail(format!("workspace member \"{}\" has no zz.toml", member));
+            }
+        };
+        let (_, config) = load(&dir)?;
+        let deps : Vec<String> = config.dependencies.iter().flatten().map(|(n,_)|n.clone()).collect();
+        members.push((config.project.name, dir, deps));
+    }
+
+    // dependencies first
+    let mut ordered : Vec<(String, PathBuf)> = Vec::new();
+    while !members.is_empty() {
+        let ready = members.iter().position(|(_, _, deps)| {
+            deps.iter().all(|d| ordered.iter().any(|(n,_)| n == d) || !members.iter().any(|(n,_,_)| n == d))
+        });
+        match ready {
+            Some(i) => {
+                let (name, dir, _) = members.remove(i);
+                ordered.push((name, dir));
+            }
+            None => {
+                let names : Vec<String> = members.iter().map(|(n,_,_)|n.clone()).collect();
+                return fail(format!("workspace members depend on each other in a cycle: {}", names.join(", ")));
+            }
+        }
+    }
+
+    Ok(Members {
+        root:       root.canonicalize().unwrap_or(root.to_path_buf()),
+        members:    ordered,
+    })
+}
+
+/// the workspace the project in the current directory is a member of, if any
+pub fn workspace() -> Result<Option<Members>, Error> {
+    match find_toml(&std::env::current_dir().unwrap()) {
+        Some(root) => workspace_of(&root),
+        None => Ok(None),
+    }
+}
+
+/// where build output of the project or workspace at root goes. members of a workspace share the one at its root
+pub fn target_dir(root: &Path) -> PathBuf {
+    match member_of(root) {
+        Some((wsroot, _)) => wsroot.join("target"),
+        None => root.join("target"),
+    }
+}
+
+
+/// resolved dependencies shared by all members of a workspace
+#[derive(Default, Serialize, Deserialize)]
+pub struct Lock {
+    #[serde(default)]
+    pub dependency: Vec<Locked>,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+pub struct Locked {
+    pub name:       String,
+    pub version:    String,
+    // relative to the workspace root if inside it
+    pub path:       String,
+}
+
+impl Lock {
+    pub fn load(wsroot: &Path) -> Lock {
+        std::fs::read_to_string(wsroot.join("zz.lock")).ok()
+            .and_then(|s| toml::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn get(&self, wsroot: &Path, name: &str) -> Option<PathBuf> {
+        let l = self.dependency.iter().find(|l| l.name == name)?;
+        let p = wsroot.join(&l.path);
+        if p.join("zz.toml").exists() {
+            Some(p)
+        } else {
+            None
+        }
+    }
+
+    /// record where a dependency was found, writing zz.lock only if that changed anything
+    pub fn update(wsroot: &Path, name: &str, version: &str, found: &Path) {
+        let mut lock = Lock::load(wsroot);
+        let path = found.strip_prefix(wsroot).unwrap_or(found).to_string_lossy().to_string();
+        let locked = Locked {
+            name:       name.to_string(),
+            version:    version.to_string(),
+            path,
+        };
+        if lock.dependency.contains(&locked) {
+            return;
+        }
+        lock.dependency.retain(|l| l.name != name);
+        lock.dependency.push(locked);
+        lock.dependency.sort_by(|a, b| a.name.cmp(&b.name));
 
+        let s = format!("# generated by zz, shared by all members of the workspace\n\n{}",
+                        toml::to_string(&lock).expect("cannot serialize zz.lock"));
+        std::fs::write(wsroot.join("zz.lock"), s).expect("cannot write zz.lock");
     }
 }
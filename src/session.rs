@@ -0,0 +1,166 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use super::diagnostics::{self, Diagnostics};
+use super::make::{Stage, Target};
+use super::{ast, project, BuildSet, Error};
+
+// the stage running in this process. it owns the current directory and the diagnostics sink
+static TURN: Mutex<()> = Mutex::new(());
+
+/// the compiler as a library.
+///
+/// every stage reports its errors and warnings to the diagnostics sink as they happen,
+/// then returns the first error instead of ending the process.
+/// stages of all sessions in a process take turns: one waits until the other returned.
+///
+/// while a stage runs, the current directory of the whole process is the project root,
+/// and the diagnostics of the whole process go to this session's sink.
+/// other threads of the program see both, so they should not depend on the current directory
+/// or log through zz while a stage runs.
+pub struct Session {
+    root:       PathBuf,
+    variant:    String,
+    stage:      Stage,
+    slow:       bool,
+    artifacts:  HashSet<String>,
+    sink:       Arc<dyn Diagnostics>,
+}
+
+impl Session {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Session {
+            root:       root.into(),
+            variant:    "default".to_string(),
+            stage:      Stage::test(),
+            slow:       false,
+            artifacts:  HashSet::new(),
+            sink:       Arc::new(diagnostics::Log),
+        }
+    }
+
+    pub fn variant(mut self, variant: &str) -> Self {
+        self.variant = variant.to_string();
+        self
+    }
+
+    pub fn stage(mut self, stage: Stage) -> Self {
+        self.stage = stage;
+        self
+    }
+
+    /// prove and emit one module at a time
+    pub fn slow(mut self, slow: bool) -> Self {
+        self.slow = slow;
+        self
+    }
+
+    /// build only this artifact. may be given more than once. without any, build them all
+    pub fn artifact(mut self, name: &str) -> Self {
+        self.artifacts.insert(name.to_string());
+        self
+    }
+
+    pub fn diagnostics(mut self, sink: Arc<dyn Diagnostics>) -> Self {
+        self.sink = sink;
+        self
+    }
+
+    /// the project's zz.toml, and where it was found
+    pub fn load(&self) -> Result<(PathBuf, project::Config), Error> {
+        self.run(project::load_cwd)
+    }
+
+    /// parse a single module of the project
+    pub fn parse(&self, file: &Path) -> Result<ast::Module, Error> {
+        let file = self.root.join(file);
+        self.run(|| {
+            let (_, project) = project::load_cwd()?;
+            let features = project.features(&self.variant)?.into_iter().map(|(n,(e,_))|(n,e)).collect();
+            super::parser::parse(&file, &features, &self.stage)
+        })
+    }
+
+    /// prove and emit, without compiling the C
+    pub fn check(&self) -> Result<(), Error> {
+        self.build(BuildSet::Check).map(|_| ())
+    }
+
+    pub fn build(&self, buildset: BuildSet) -> Result<Vec<Target>, Error> {
+        self.run(|| {
+            let select = if self.artifacts.is_empty() { None } else { Some(&self.artifacts) };
+            super::pipeline(buildset, &self.variant, self.stage.clone(), self.slow, select)
+        })
+    }
+
+    fn run<T, F: FnOnce() -> Result<T, Error>>(&self, f: F) -> Result<T, Error> {
+        // a stage that panicked left nothing behind that the next one would trip over
+        let _turn = TURN.lock().unwrap_or_else(|e| e.into_inner());
+
+        let root = self.root.canonicalize()
+            .map_err(|e| self.report(Error::new(format!("cannot open {:?}: {}", self.root, e), Vec::new())))?;
+        let cwd = std::env::current_dir()
+            .map_err(|e| self.report(Error::new(format!("cannot get current directory: {}", e), Vec::new())))?;
+        std::env::set_current_dir(&root)
+            .map_err(|e| self.report(Error::new(format!("cannot change to {:?}: {}", root, e), Vec::new())))?;
+
+        let first = Arc::new(First {
+            sink:   self.sink.clone(),
+            first:  Mutex::new(None),
+        });
+        let restore = Restore {
+            cwd,
+            sink: diagnostics::set(Some(first.clone())),
+        };
+
+        let r = f();
+        drop(restore);
+
+        let reported = first.first.lock().unwrap().take();
+        r.map_err(|e| {
+            let code = e.code;
+            match reported {
+                Some(reported) => reported.with_code(code),
+                None => self.report(e),
+            }
+        })
+    }
+
+    fn report(&self, e: Error) -> Error {
+        self.sink.error(&e);
+        e
+    }
+}
+
+// what a stage changed in the process, put back even if it panics
+struct Restore {
+    cwd:    PathBuf,
+    sink:   Option<Arc<dyn Diagnostics>>,
+}
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        diagnostics::set(self.sink.take());
+        std::env::set_current_dir(&self.cwd).ok();
+    }
+}
+
+// passes everything on, remembering the first error for the stage to return
+struct First {
+    sink:   Arc<dyn Diagnostics>,
+    first:  Mutex<Option<Error>>,
+}
+
+impl Diagnostics for First {
+    fn error(&self, e: &Error) {
+        let mut first = self.first.lock().unwrap();
+        if first.is_none() {
+            *first = Some(e.clone());
+        }
+        drop(first);
+        self.sink.error(e);
+    }
+    fn warn(&self, e: &Error) {
+        self.sink.warn(e);
+    }
+}
@@ -740,7 +740,7 @@ impl Solver {
         }
     }
 
-    pub fn new(module_name: String, hints: &HashMap<String, String>) -> Self {
+    pub fn new(module_name: String, hints: &HashMap<String, String>, ssa: &std::path::Path) -> Self {
 
         //Config::set_global_param_value(":model.partial", "true");
         //Config::set_global_param_value(":parallel.enable", "true");
@@ -757,8 +757,8 @@ impl Solver {
         };
 
 
-        std::fs::create_dir_all("./target/ssa/").unwrap();
-        let outfile = format!("./target/ssa/{}.smt2", module_name);
+        std::fs::create_dir_all(ssa).unwrap();
+        let outfile = format!("{}/{}.smt2", ssa.display(), module_name);
 
 
         let mut solver = rsmt2::Solver::new(conf, Rsmt2Junk).unwrap();
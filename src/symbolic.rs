@@ -3151,7 +3151,7 @@ impl Symbolic {
         ]));
     }
 
-    fn new(module_name: &Name, hints: &HashMap<String, String>) -> Self {
+    fn new(module_name: &Name, hints: &HashMap<String, String>, ssa: &std::path::Path) -> Self {
         Symbolic {
             stack:  vec![
                 Scope {
@@ -3161,7 +3161,7 @@ impl Symbolic {
                 }
             ],
             memory:  Default::default(),
-            ssa:     Solver::new(module_name.0.join("_"), hints),
+            ssa:     Solver::new(module_name.0.join("_"), hints, ssa),
             builtin: Default::default(),
             defs:    HashMap::new(),
             current_module_name:    module_name.human_name(),
@@ -3327,7 +3327,8 @@ impl Symbolic {
 }
 
 
-pub fn execute(module: &mut flatten::Module) -> bool {
+/// ssa is where the solver input of every function is written to
+pub fn execute(module: &mut flatten::Module, ssa: &std::path::Path) -> bool {
     use rayon::prelude::*;
 
     let mut defs        = Vec::new();
@@ -3345,7 +3346,7 @@ pub fn execute(module: &mut flatten::Module) -> bool {
 
     // execute one in serial on the borrowed module to get modifications to globals
     if let Some((at, name, _, hints)) = function_at.pop() {
-        let mut sym = Symbolic::new(&Name::from(&name), &hints);
+        let mut sym = Symbolic::new(&Name::from(&name), &hints, ssa);
         if let Err(e) = sym.execute_module(module, at) {
             parser::emit_error(e.message.clone(), &e.details);
             return false;
@@ -3353,7 +3354,7 @@ pub fn execute(module: &mut flatten::Module) -> bool {
     }
 
     let repl = function_at.into_par_iter().map(|(at, name, mut module, hints)|{
-        let mut sym = Symbolic::new(&Name::from(&name), &hints);
+        let mut sym = Symbolic::new(&Name::from(&name), &hints, ssa);
         match sym.execute_module(&mut module, at) {
             Err(e) => {
                 parser::emit_error(e.message.clone(), &e.details);
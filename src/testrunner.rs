@@ -0,0 +1,874 @@
+use rayon::prelude::*;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+
+/// one testcase of a test artifact, as written by the emitter into target/<stage>/testcases/
+#[derive(Clone, Default)]
+pub struct Case {
+    pub name:       String,
+    pub stdin:      Option<Vec<u8>>,
+    pub stdout:     Option<Output>,
+    pub stderr:     Option<Output>,
+    // without exit or signal, the test must exit with 0
+    pub exit:       Option<(Op, i32)>,
+    pub signal:     Option<(Op, i32)>,
+    pub args:       Vec<String>,
+    pub env:        Vec<(String, String)>,
+}
+
+/// the testop of a testcase field
+#[derive(Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+}
+
+/// expected stdout or stderr.
+/// == must match exactly, != must differ, < must be contained and > is a regex the output must match
+#[derive(Clone)]
+pub struct Output {
+    pub op:     Op,
+    pub v:      Vec<u8>,
+    pub source: Option<Source>,
+}
+
+/// where an expected output is declared
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Source {
+    // the byte span of a string literal
+    Inline{file: String, start: usize, end: usize},
+    // stdout = file("path")
+    Snapshot{path: String},
+}
+
+/// a test binary together with the cases to run it with
+pub struct Job {
+    pub artifact:   String,
+    pub exe:        PathBuf,
+    pub cases:      Vec<Case>,
+}
+
+pub struct Options {
+    // per case
+    pub timeout:        Option<Duration>,
+    // for the whole run
+    pub global_timeout: Option<Duration>,
+    pub fail_fast:      bool,
+    // parallel tests, defaults to the number of cpus
+    pub jobs:           Option<usize>,
+}
+
+#[derive(Clone, PartialEq)]
+pub enum Outcome {
+    Pass,
+    Fail(Failure),
+    Timeout,
+    // not run, or killed, because of --fail-fast or the global timeout
+    Skipped,
+}
+
+#[derive(Clone, PartialEq)]
+pub enum Failure {
+    Exit{got: i32, expected: String},
+    // died by a signal, either unexpected or not the expected one
+    Signal{got: i32, expected: Option<String>},
+    // exited normally while a signal was expected
+    NoSignal{exit: i32, expected: String},
+    Output{stream: &'static str, message: String},
+    Spawn(String),
+    // started, but could not be waited for
+    Wait(String),
+}
+
+/// what happened when running one case
+pub struct Run {
+    pub outcome:    Outcome,
+    pub duration:   Duration,
+    pub exit:       Option<i32>,
+    pub signal:     Option<i32>,
+    pub stdout:     Vec<u8>,
+    pub stderr:     Vec<u8>,
+}
+
+pub struct TestResult {
+    pub artifact:   String,
+    pub case:       Case,
+    pub run:        Run,
+}
+
+pub fn load_cases(casedir: &Path) -> Vec<Case> {
+    let mut cases = Vec::new();
+    let dir = match std::fs::read_dir(casedir) {
+        Ok(v) => v,
+        Err(_) => return cases,
+    };
+    for entry in dir {
+        let entry = match entry {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        let path = entry.path();
+
+        // every field is optional
+        let stdin  = read_stripped(&path.join("stdin"));
+        let stdout = read_output(&path, "stdout");
+        let stderr = read_output(&path, "stderr");
+
+        let exit = std::fs::read_to_string(path.join("exit")).ok()
+            .map(|v| (read_op(&path, "exit"), v.trim().parse().unwrap_or(0)));
+        let signal = std::fs::read_to_string(path.join("signal")).ok()
+            .map(|v| (read_op(&path, "signal"), signal_number(v.trim())));
+
+        let args = read_list(&path.join("args"));
+        let env  = read_list(&path.join("env")).into_iter().map(|kv| match kv.find('=') {
+            Some(i) => (kv[..i].to_string(), kv[i+1..].to_string()),
+            None    => (kv, String::new()),
+        }).collect();
+
+        cases.push(Case{
+            name: entry.file_name().to_string_lossy().to_string(),
+            stdin,
+            stdout,
+            stderr,
+            exit,
+            signal,
+            args,
+            env,
+        });
+    }
+    cases.sort_by(|a, b| a.name.cmp(&b.name));
+    cases
+}
+
+fn read_stripped(path: &Path) -> Option<Vec<u8>> {
+    let mut f = std::fs::File::open(path).ok()?;
+    let mut v = Vec::new();
+    f.read_to_end(&mut v).ok()?;
+    v.retain(|&i|i!=b'\r');
+    Some(v)
+}
+
+fn read_output(path: &Path, field: &str) -> Option<Output> {
+    let v = read_stripped(&path.join(field))?;
+    let source = std::fs::read_to_string(path.join(format!("{}.src", field))).ok()
+        .and_then(|s| serde_json::from_str(&s).ok());
+    Some(Output{
+        op: read_op(path, field),
+        v,
+        source,
+    })
+}
+
+fn read_op(path: &Path, field: &str) -> Op {
+    match std::fs::read_to_string(path.join(format!("{}.op", field))) {
+        Ok(op) => Op::from(op.trim()),
+        Err(_) => Op::Eq,
+    }
+}
+
+// \0 terminated strings, which may be empty
+fn read_list(path: &Path) -> Vec<String> {
+    match std::fs::read(path) {
+        Ok(v) => {
+            let mut r : Vec<String> = v.split(|&c| c == 0).map(|s| String::from_utf8_lossy(s).to_string()).collect();
+            // after the last terminator
+            r.pop();
+            r
+        }
+        Err(_) => Vec::new(),
+    }
+}
+
+fn signal_number(s: &str) -> i32 {
+    if let Ok(v) = s.parse() {
+        return v;
+    }
+    match s.trim_start_matches("SIG") {
+        "HUP"   => 1,
+        "INT"   => 2,
+        "QUIT"  => 3,
+        "ILL"   => 4,
+        "TRAP"  => 5,
+        "ABRT"  => 6,
+        "BUS"   => 7,
+        "FPE"   => 8,
+        "KILL"  => 9,
+        "SEGV"  => 11,
+        "PIPE"  => 13,
+        "ALRM"  => 14,
+        "TERM"  => 15,
+        _ => {
+            warn!("unknown signal {}", s);
+            0
+        }
+    }
+}
+
+/// run every case of every job in parallel, reporting each result as it completes.
+/// results are returned in job and case order.
+pub fn run(jobs: &[Job], opts: &Options) -> Vec<TestResult> {
+    let deadline = opts.global_timeout.map(|t| Instant::now() + t);
+    let cancel   = AtomicBool::new(false);
+
+    let work : Vec<(&Job, &Case)> = jobs.iter()
+        .flat_map(|job| job.cases.iter().map(move |case| (job, case)))
+        .collect();
+
+    let mut pool = rayon::ThreadPoolBuilder::new();
+    if let Some(jobs) = opts.jobs {
+        pool = pool.num_threads(jobs);
+    }
+    let pool = pool.build().expect("cannot create test thread pool");
+
+    pool.install(|| work.par_iter().map(|(job, case)| {
+        if cancel.load(Ordering::Relaxed) {
+            return TestResult{
+                artifact:   job.artifact.clone(),
+                case:       (*case).clone(),
+                run:        Run::new(Outcome::Skipped, Duration::from_secs(0)),
+            };
+        }
+
+        let case_deadline = match (opts.timeout.map(|t| Instant::now() + t), deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+
+        let run = run_case(&job.exe, case, case_deadline, &cancel);
+
+        // the global deadline ran out, nothing else gets to start
+        if deadline.map(|d| Instant::now() >= d).unwrap_or(false) {
+            cancel.store(true, Ordering::Relaxed);
+        }
+
+        match &run.outcome {
+            Outcome::Pass       => info!("PASS {} {} in {}ms", job.artifact, case.name, run.duration.as_millis()),
+            Outcome::Fail(why)  => {
+                if run.stderr.is_empty() {
+                    error!("FAIL {} {} {}", job.artifact, case.name, why);
+                } else {
+                    error!("FAIL {} {} {}\nstderr:\n{}", job.artifact, case.name, why, String::from_utf8_lossy(&run.stderr));
+                }
+            }
+            Outcome::Timeout    => error!("TIMEOUT {} {} after {}ms", job.artifact, case.name, run.duration.as_millis()),
+            Outcome::Skipped    => (),
+        }
+        if opts.fail_fast && !matches!(run.outcome, Outcome::Pass | Outcome::Skipped) {
+            cancel.store(true, Ordering::Relaxed);
+        }
+
+        TestResult{
+            artifact:   job.artifact.clone(),
+            case:       (*case).clone(),
+            run,
+        }
+    }).collect())
+}
+
+impl Run {
+    fn new(outcome: Outcome, duration: Duration) -> Self {
+        Run {
+            outcome,
+            duration,
+            exit:   None,
+            signal: None,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        }
+    }
+}
+
+/// run a single case, until it exits, the deadline runs out, or cancel is set.
+/// it is polled quickly at first, so short runs are still timed precisely enough for benchmarks.
+pub fn run_case(exe: &Path, case: &Case, deadline: Option<Instant>, cancel: &AtomicBool) -> Run {
+    let start = Instant::now();
+    let mut cmd = Command::new(exe);
+    cmd.args(&case.args)
+        .envs(case.env.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // in its own process group, so a timeout also kills whatever the test started
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    let mut child = match cmd.spawn() {
+        Ok(v) => v,
+        Err(e) => return Run::new(Outcome::Fail(Failure::Spawn(format!("{:?}: {}", exe, e))), start.elapsed()),
+    };
+
+    // feed stdin and drain the outputs on the side so a chatty test can't block on a full pipe
+    let mut stdin = child.stdin.take().unwrap();
+    let input = case.stdin.clone().unwrap_or_default();
+    let writer = std::thread::spawn(move || {
+        stdin.write_all(&input).ok();
+    });
+    let drain = |mut r: Box<dyn Read + Send>| std::thread::spawn(move || {
+        let mut v = Vec::new();
+        r.read_to_end(&mut v).ok();
+        v.retain(|&i|i!=b'\r');
+        v
+    });
+    let stdout = drain(Box::new(child.stdout.take().unwrap()));
+    let stderr = drain(Box::new(child.stderr.take().unwrap()));
+
+    let mut poll = Duration::from_micros(50);
+    let status = loop {
+        let outcome = match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if cancel.load(Ordering::Relaxed) => Outcome::Skipped,
+            Ok(None) if deadline.map(|d| Instant::now() >= d).unwrap_or(false) => Outcome::Timeout,
+            Ok(None) => {
+                std::thread::sleep(poll);
+                poll = (poll * 2).min(Duration::from_millis(5));
+                continue;
+            }
+            Err(e) => Outcome::Fail(Failure::Wait(format!("{:?}: {}", exe, e))),
+        };
+        kill(&mut child);
+        child.wait().ok();
+        writer.join().ok();
+        let mut run = Run::new(outcome, start.elapsed());
+        run.stdout = stdout.join().unwrap_or_default();
+        run.stderr = stderr.join().unwrap_or_default();
+        return run;
+    };
+    let mut run = Run::new(Outcome::Pass, start.elapsed());
+    writer.join().ok();
+    run.stdout = stdout.join().unwrap_or_default();
+    run.stderr = stderr.join().unwrap_or_default();
+    run.exit   = status.code();
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        run.signal = status.signal();
+    }
+
+    if let Some(failure) = check(case, &run) {
+        run.outcome = Outcome::Fail(failure);
+    }
+    run
+}
+
+fn kill(child: &mut std::process::Child) {
+    #[cfg(unix)]
+    unsafe {
+        libc::killpg(child.id() as libc::pid_t, libc::SIGKILL);
+    }
+    child.kill().ok();
+}
+
+fn check(case: &Case, run: &Run) -> Option<Failure> {
+    match (run.exit, run.signal, &case.signal) {
+        (_, Some(got), Some((op, expected))) => {
+            if !op.cmp(got, *expected) {
+                return Some(Failure::Signal{got, expected: Some(expectation(*op, *expected))});
+            }
+        }
+        (_, Some(got), None) => {
+            return Some(Failure::Signal{got, expected: None});
+        }
+        (Some(exit), None, Some((op, expected))) => {
+            return Some(Failure::NoSignal{exit, expected: expectation(*op, *expected)});
+        }
+        (Some(got), None, None) => {
+            let (op, expected) = case.exit.unwrap_or((Op::Eq, 0));
+            if !op.cmp(got, expected) {
+                return Some(Failure::Exit{got, expected: expectation(op, expected)});
+            }
+        }
+        (None, None, _) => {
+            return Some(Failure::Signal{got: 0, expected: None});
+        }
+    }
+
+    if let Some(expect) = &case.stdout {
+        if let Some(message) = expect.check(&run.stdout) {
+            return Some(Failure::Output{stream: "stdout", message});
+        }
+    }
+    if let Some(expect) = &case.stderr {
+        if let Some(message) = expect.check(&run.stderr) {
+            return Some(Failure::Output{stream: "stderr", message});
+        }
+    }
+    None
+}
+
+fn expectation(op: Op, v: i32) -> String {
+    match op {
+        Op::Eq  => format!("{}", v),
+        op      => format!("{} {}", op, v),
+    }
+}
+
+impl Op {
+    fn cmp(self, got: i32, expected: i32) -> bool {
+        match self {
+            Op::Eq => got == expected,
+            Op::Ne => got != expected,
+            Op::Lt => got <  expected,
+            Op::Gt => got >  expected,
+            Op::Le => got <= expected,
+            Op::Ge => got >= expected,
+        }
+    }
+}
+
+impl From<&str> for Op {
+    fn from(s: &str) -> Self {
+        match s {
+            "!="    => Op::Ne,
+            "<"     => Op::Lt,
+            ">"     => Op::Gt,
+            "<="    => Op::Le,
+            ">="    => Op::Ge,
+            _       => Op::Eq,
+        }
+    }
+}
+
+impl std::fmt::Display for Op {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Gt => ">",
+            Op::Le => "<=",
+            Op::Ge => ">=",
+        })
+    }
+}
+
+impl Output {
+    /// why the output does not match, if it doesn't
+    pub fn check(&self, got: &[u8]) -> Option<String> {
+        let expected = String::from_utf8_lossy(&self.v);
+        match self.op {
+            Op::Ne => {
+                if got == self.v.as_slice() {
+                    return Some(format!("must not be <{}>", expected));
+                }
+            }
+            Op::Lt => {
+                if !self.v.is_empty() && !got.windows(self.v.len()).any(|w| w == self.v.as_slice()) {
+                    return Some(format!("does not contain <{}>, got:\n{}", expected, String::from_utf8_lossy(got)));
+                }
+            }
+            Op::Gt => {
+                let re = match regex::bytes::Regex::new(&expected) {
+                    Ok(v) => v,
+                    Err(e) => return Some(format!("has an invalid regex: {}", e)),
+                };
+                if !re.is_match(got) {
+                    return Some(format!("does not match /{}/, got:\n{}", expected, String::from_utf8_lossy(got)));
+                }
+            }
+            _ => {
+                if got != self.v.as_slice() {
+                    return Some(format!("differs from expected:\n{}", unified_diff(&expected, &String::from_utf8_lossy(got))));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// a line based unified diff with 3 lines of context
+pub fn unified_diff(expected: &str, got: &str) -> String {
+    const CONTEXT: usize = 3;
+
+    let a : Vec<&str> = expected.split_inclusive('\n').collect();
+    let b : Vec<&str> = got.split_inclusive('\n').collect();
+    let ops = diff_lines(&a, &b);
+
+    let changes : Vec<usize> = ops.iter().enumerate().filter(|(_, (k, _))| *k != ' ').map(|(i, _)| i).collect();
+    let mut r = String::from("--- expected\n+++ actual\n");
+    let mut i = 0;
+    while i < changes.len() {
+        let start = changes[i].saturating_sub(CONTEXT);
+        let mut last = changes[i];
+        // hunks closer than twice the context are merged
+        while i + 1 < changes.len() && changes[i + 1] - last <= 2 * CONTEXT + 1 {
+            i += 1;
+            last = changes[i];
+        }
+        let end = (last + CONTEXT + 1).min(ops.len());
+
+        let a_start = ops[..start].iter().filter(|(k, _)| *k != '+').count();
+        let b_start = ops[..start].iter().filter(|(k, _)| *k != '-').count();
+        let a_len   = ops[start..end].iter().filter(|(k, _)| *k != '+').count();
+        let b_len   = ops[start..end].iter().filter(|(k, _)| *k != '-').count();
+        r.push_str(&format!("@@ -{},{} +{},{} @@\n",
+            if a_len == 0 { a_start } else { a_start + 1 }, a_len,
+            if b_len == 0 { b_start } else { b_start + 1 }, b_len));
+
+        for (k, line) in &ops[start..end] {
+            r.push(*k);
+            r.push_str(line);
+            if !line.ends_with('\n') {
+                r.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+        i += 1;
+    }
+    r
+}
+
+fn diff_lines<'a>(a: &[&'a str], b: &[&'a str]) -> Vec<(char, &'a str)> {
+    let (n, m) = (a.len(), b.len());
+    let mut r = Vec::new();
+
+    // too big for the lcs table, everything changed
+    if n.saturating_mul(m) > 4_000_000 {
+        r.extend(a.iter().map(|l| ('-', *l)));
+        r.extend(b.iter().map(|l| ('+', *l)));
+        return r;
+    }
+
+    let w = m + 1;
+    let mut lcs = vec![0u32; (n + 1) * w];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i * w + j] = if a[i] == b[j] {
+                lcs[(i + 1) * w + j + 1] + 1
+            } else {
+                lcs[(i + 1) * w + j].max(lcs[i * w + j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if a[i] == b[j] {
+            r.push((' ', a[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[(i + 1) * w + j] >= lcs[i * w + j + 1] {
+            r.push(('-', a[i]));
+            i += 1;
+        } else {
+            r.push(('+', b[j]));
+            j += 1;
+        }
+    }
+    r.extend(a[i..].iter().map(|l| ('-', *l)));
+    r.extend(b[j..].iter().map(|l| ('+', *l)));
+    r
+}
+
+/// rewrite every exactly matched stdout and stderr that differs from what the test printed,
+/// either in the string literal of the testcase or in its snapshot file.
+/// the blessed cases are checked again against their new expectations.
+pub fn bless(results: &mut [TestResult]) {
+    let mut edits : BTreeMap<String, Vec<(usize, usize, Vec<u8>)>> = BTreeMap::new();
+
+    for r in results.iter_mut() {
+        if matches!(r.run.outcome, Outcome::Pass | Outcome::Timeout | Outcome::Skipped | Outcome::Fail(Failure::Spawn(_)) | Outcome::Fail(Failure::Wait(_))) {
+            continue;
+        }
+        let mut changed = false;
+        for (stream, expect, got) in [("stdout", &mut r.case.stdout, &r.run.stdout), ("stderr", &mut r.case.stderr, &r.run.stderr)].iter_mut() {
+            let got : &[u8] = got;
+            let expect = match expect {
+                Some(e) if e.op == Op::Eq && e.v.as_slice() != got => e,
+                _ => continue,
+            };
+            let source = match &expect.source {
+                Some(v) => v,
+                None => {
+                    warn!("cannot bless {} {} {}: expected output is not a string or file()", r.artifact, r.case.name, stream);
+                    continue;
+                }
+            };
+
+            info!("BLESS {} {} {}\n{}", r.artifact, r.case.name, stream,
+                unified_diff(&String::from_utf8_lossy(&expect.v), &String::from_utf8_lossy(got)));
+
+            match source {
+                Source::Snapshot{path} => {
+                    std::fs::write(path, got).expect(&format!("cannot write {}", path));
+                }
+                Source::Inline{file, start, end} => {
+                    edits.entry(file.clone()).or_default().push((*start, *end, literal(got)));
+                }
+            }
+            expect.v = got.to_vec();
+            changed = true;
+        }
+        if changed {
+            r.run.outcome = match check(&r.case, &r.run) {
+                Some(failure) => Outcome::Fail(failure),
+                None => Outcome::Pass,
+            };
+        }
+    }
+
+    // back to front, so earlier offsets stay valid
+    for (file, mut edits) in edits {
+        edits.sort_by_key(|e| std::cmp::Reverse(e.0));
+        edits.dedup_by(|a, b| a.0 == b.0);
+        let mut src = std::fs::read(&file).expect(&format!("cannot read {}", file));
+        for (start, end, lit) in edits {
+            src.splice(start..end, lit);
+        }
+        std::fs::write(&file, src).expect(&format!("cannot write {}", file));
+    }
+}
+
+/// a zz string literal with the exact bytes of v
+pub fn literal(v: &[u8]) -> Vec<u8> {
+    let mut r = vec![b'"'];
+    for &c in v {
+        match c {
+            b'"'    => r.extend_from_slice(b"\\\""),
+            b'\\'   => r.extend_from_slice(b"\\\\"),
+            b'\n'   => r.extend_from_slice(b"\\n"),
+            b'\t'   => r.extend_from_slice(b"\\t"),
+            b'\r'   => r.extend_from_slice(b"\\r"),
+            0x20..=0x7e => r.push(c),
+            // the parser reads literals one char per byte
+            _ => r.extend_from_slice(format!("\\x{:02x}", c).as_bytes()),
+        }
+    }
+    r.push(b'"');
+    r
+}
+
+/// print the final tally and return whether everything passed
+pub fn summary(results: &[TestResult], elapsed: Duration) -> bool {
+    let passed  = results.iter().filter(|r| r.run.outcome == Outcome::Pass).count();
+    let failed  = results.iter().filter(|r| matches!(r.run.outcome, Outcome::Fail(_))).count();
+    let timeout = results.iter().filter(|r| r.run.outcome == Outcome::Timeout).count();
+    let skipped = results.iter().filter(|r| r.run.outcome == Outcome::Skipped).count();
+
+    for r in results {
+        match r.run.outcome {
+            Outcome::Fail(_)    => println!("    failed:    {} {}", r.artifact, r.case.name),
+            Outcome::Timeout    => println!("    timed out: {} {}", r.artifact, r.case.name),
+            _ => (),
+        }
+    }
+    println!("\ntest result: {}. {} passed; {} failed; {} timed out; {} skipped; finished in {:.2}s\n",
+        if failed + timeout + skipped == 0 { "ok" } else { "FAILED" },
+        passed, failed, timeout, skipped, elapsed.as_secs_f64());
+
+    failed + timeout + skipped == 0
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::Exit{got, expected}    => write!(f, "exit: {} instead of: {}", got, expected),
+            Failure::Signal{got, expected: None}        => write!(f, "died by signal {}", got),
+            Failure::Signal{got, expected: Some(e)}     => write!(f, "died by signal {} instead of: {}", got, e),
+            Failure::NoSignal{exit, expected}           => write!(f, "exit: {} instead of signal: {}", exit, expected),
+            Failure::Output{stream, message}            => write!(f, "{} {}", stream, message),
+            Failure::Spawn(e)               => write!(f, "cannot execute {}", e),
+            Failure::Wait(e)                => write!(f, "cannot wait for {}", e),
+        }
+    }
+}
+
+impl Outcome {
+    fn kind(&self) -> &'static str {
+        match self {
+            Outcome::Pass                       => "pass",
+            Outcome::Fail(Failure::Exit{..})    => "exit",
+            Outcome::Fail(Failure::Signal{..})  => "signal",
+            Outcome::Fail(Failure::NoSignal{..})=> "nosignal",
+            Outcome::Fail(Failure::Output{stream, ..}) => stream,
+            Outcome::Fail(Failure::Spawn(_))    => "spawn",
+            Outcome::Fail(Failure::Wait(_))     => "wait",
+            Outcome::Timeout                    => "timeout",
+            Outcome::Skipped                    => "skipped",
+        }
+    }
+
+    // junit distinguishes between a test that failed its expectations and one that crashed
+    fn is_error(&self) -> bool {
+        matches!(self, Outcome::Fail(Failure::Signal{expected: None, ..}) | Outcome::Fail(Failure::Spawn(_))
+                 | Outcome::Fail(Failure::Wait(_)) | Outcome::Timeout)
+    }
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    passed:     bool,
+    duration:   f64,
+    tests:      Vec<JsonTest<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonTest<'a> {
+    artifact:           &'a str,
+    case:               &'a str,
+    outcome:            &'static str,
+    reason:             Option<String>,
+    duration:           f64,
+    exit:               Option<i32>,
+    expected_exit:      Option<String>,
+    signal:             Option<i32>,
+    expected_signal:    Option<String>,
+    stdout:             String,
+    expected_stdout:    Option<JsonOutput>,
+    stderr:             String,
+    expected_stderr:    Option<JsonOutput>,
+}
+
+#[derive(Serialize)]
+struct JsonOutput {
+    op:     String,
+    v:      String,
+}
+
+impl From<&Output> for JsonOutput {
+    fn from(o: &Output) -> Self {
+        JsonOutput {
+            op: o.op.to_string(),
+            v:  String::from_utf8_lossy(&o.v).to_string(),
+        }
+    }
+}
+
+/// write the results as json, one entry per testcase
+pub fn report_json(path: &Path, results: &[TestResult], elapsed: Duration) -> std::io::Result<()> {
+    let report = JsonReport {
+        passed:     results.iter().all(|r| r.run.outcome == Outcome::Pass),
+        duration:   elapsed.as_secs_f64(),
+        tests:      results.iter().map(|r| JsonTest {
+            artifact:           &r.artifact,
+            case:               &r.case.name,
+            outcome:            r.run.outcome.kind(),
+            reason:             reason(&r.run.outcome),
+            duration:           r.run.duration.as_secs_f64(),
+            exit:               r.run.exit,
+            expected_exit:      match (r.case.exit, r.case.signal) {
+                (Some((op, v)), _)  => Some(expectation(op, v)),
+                (None, Some(_))     => None,
+                (None, None)        => Some(expectation(Op::Eq, 0)),
+            },
+            signal:             r.run.signal,
+            expected_signal:    r.case.signal.map(|(op, v)| expectation(op, v)),
+            stdout:             String::from_utf8_lossy(&r.run.stdout).to_string(),
+            expected_stdout:    r.case.stdout.as_ref().map(JsonOutput::from),
+            stderr:             String::from_utf8_lossy(&r.run.stderr).to_string(),
+            expected_stderr:    r.case.stderr.as_ref().map(JsonOutput::from),
+        }).collect(),
+    };
+    let f = std::fs::File::create(path)?;
+    serde_json::to_writer_pretty(f, &report).map_err(std::io::Error::from)
+}
+
+/// write the results as junit xml, with one testsuite per test artifact
+pub fn report_junit(path: &Path, results: &[TestResult], elapsed: Duration) -> std::io::Result<()> {
+    let mut f = std::io::BufWriter::new(std::fs::File::create(path)?);
+
+    let count = |rs: &[&TestResult], o: fn(&Outcome) -> bool| rs.iter().filter(|r| o(&r.run.outcome)).count();
+
+    let mut suites : Vec<(&str, Vec<&TestResult>)> = Vec::new();
+    for r in results {
+        match suites.last_mut() {
+            Some((name, rs)) if *name == r.artifact.as_str() => rs.push(r),
+            _ => suites.push((&r.artifact, vec![r])),
+        }
+    }
+
+    let all : Vec<&TestResult> = results.iter().collect();
+    write!(f, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n")?;
+    write!(f, "<testsuites name=\"zz\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+        all.len(),
+        count(&all, |o| matches!(o, Outcome::Fail(_)) && !o.is_error()),
+        count(&all, |o| o.is_error()),
+        count(&all, |o| o == &Outcome::Skipped),
+        elapsed.as_secs_f64())?;
+
+    for (name, rs) in &suites {
+        let time : f64 = rs.iter().map(|r| r.run.duration.as_secs_f64()).sum();
+        write!(f, "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+            xml_escape(name),
+            rs.len(),
+            count(rs, |o| matches!(o, Outcome::Fail(_)) && !o.is_error()),
+            count(rs, |o| o.is_error()),
+            count(rs, |o| o == &Outcome::Skipped),
+            time)?;
+
+        for r in rs {
+            write!(f, "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+                xml_escape(name), xml_escape(&r.case.name), r.run.duration.as_secs_f64())?;
+            if r.run.outcome == Outcome::Pass {
+                write!(f, "/>\n")?;
+                continue;
+            }
+            write!(f, ">\n")?;
+            // the first line is the message, diffs go into the body
+            let reason = reason(&r.run.outcome).unwrap_or_default();
+            let mut lines = reason.splitn(2, '\n');
+            let message = xml_escape(lines.next().unwrap_or_default());
+            let body = lines.next().map(xml_escape);
+            let tag = if r.run.outcome.is_error() { "error" } else { "failure" };
+            match (&r.run.outcome, body) {
+                (Outcome::Skipped, _) => {
+                    write!(f, "      <skipped/>\n")?;
+                }
+                (o, Some(body)) => {
+                    write!(f, "      <{} type=\"{}\" message=\"{}\">{}</{}>\n", tag, o.kind(), message, body, tag)?;
+                }
+                (o, None) => {
+                    write!(f, "      <{} type=\"{}\" message=\"{}\"/>\n", tag, o.kind(), message)?;
+                }
+            }
+            if !r.run.stdout.is_empty() {
+                write!(f, "      <system-out>{}</system-out>\n", xml_escape(&String::from_utf8_lossy(&r.run.stdout)))?;
+            }
+            if !r.run.stderr.is_empty() {
+                write!(f, "      <system-err>{}</system-err>\n", xml_escape(&String::from_utf8_lossy(&r.run.stderr)))?;
+            }
+            write!(f, "    </testcase>\n")?;
+        }
+        write!(f, "  </testsuite>\n")?;
+    }
+    write!(f, "</testsuites>\n")?;
+    f.flush()
+}
+
+fn reason(outcome: &Outcome) -> Option<String> {
+    match outcome {
+        Outcome::Pass       => None,
+        Outcome::Fail(why)  => Some(why.to_string()),
+        Outcome::Timeout    => Some("timed out".to_string()),
+        Outcome::Skipped    => Some("skipped".to_string()),
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut r = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&'     => r.push_str("&amp;"),
+            '<'     => r.push_str("&lt;"),
+            '>'     => r.push_str("&gt;"),
+            '"'     => r.push_str("&quot;"),
+            '\''    => r.push_str("&apos;"),
+            // not representable in xml 1.0
+            c if (c as u32) < 0x20 && c != '\n' && c != '\t' && c != '\r' => {
+                r.push_str(&format!("\\x{:02x}", c as u32));
+            }
+            c => r.push(c),
+        }
+    }
+    r
+}
@@ -0,0 +1,146 @@
+use super::emitter::CFile;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+use super::project::target_dir;
+
+const POLL: Duration = Duration::from_millis(300);
+
+/// rerun zz with args every time a source changes. never returns.
+///
+/// each run is a fresh process, so errors that end the pipeline don't end the watch.
+/// a rerun still loads and checks the whole project, and leaves it to the build caches
+/// to skip proving and compiling modules the change did not affect.
+pub fn watch(root: &Path, stage: &str, args: &[String]) -> ! {
+    let exe = std::env::current_exe().expect("self path");
+    let mut changed : BTreeSet<PathBuf> = BTreeSet::new();
+    loop {
+        print!("\x1b[2J\x1b[H");
+        if !changed.is_empty() {
+            for file in &changed {
+                println!("changed {}", display(root, file));
+            }
+            let modules = affected(root, stage, &changed);
+            if !modules.is_empty() {
+                println!("affects {}", modules.into_iter().collect::<Vec<_>>().join(", "));
+            }
+            println!();
+        }
+        std::io::stdout().flush().ok();
+
+        let status = Command::new(&exe).args(args).current_dir(root).status();
+        match status {
+            Ok(s) if s.success() => println!("\nzz {}: ok", args.join(" ")),
+            Ok(s) => println!("\nzz {}: failed ({})", args.join(" "), s.code().map(|c|c.to_string()).unwrap_or("signal".to_string())),
+            Err(e) => println!("\nzz {}: {}", args.join(" "), e),
+        }
+        println!("watching for changes...");
+
+        let paths  = paths(root);
+        let before = scan(&paths);
+        changed = loop {
+            std::thread::sleep(POLL);
+            let now = scan(&paths);
+            if now != before {
+                // editors write in several steps, wait until it settled
+                let mut settled = now;
+                loop {
+                    std::thread::sleep(POLL);
+                    let again = scan(&paths);
+                    if again == settled {
+                        break;
+                    }
+                    settled = again;
+                }
+                break diff(&before, &settled);
+            }
+        };
+    }
+}
+
+// the project sources, its manifest, the inputs of its generator, and every dependency.
+// for a workspace, the sources and manifests of every member
+fn paths(root: &Path) -> Vec<PathBuf> {
+    let mut r = vec![root.join("src"), root.join("tests"), root.join("zz.toml")];
+    if let Ok(Some(ws)) = super::project::workspace_cwd() {
+        for (_, dir) in ws.members {
+            r.extend(vec![dir.join("src"), dir.join("tests"), dir.join("zz.toml")]);
+        }
+        return r;
+    }
+    // a broken zz.toml is reported by the build, not here
+    if let Ok((root, config)) = super::project::load_cwd() {
+        r.extend(config.build.map(|b| super::gen::inputs(&root, &b)).unwrap_or_default());
+    }
+    r.extend(super::dependency_dirs().unwrap_or_default());
+    r
+}
+
+fn scan(paths: &[PathBuf]) -> BTreeMap<PathBuf, SystemTime> {
+    let mut r = BTreeMap::new();
+    let mut todo : Vec<PathBuf> = paths.to_vec();
+    while let Some(path) = todo.pop() {
+        let meta = match std::fs::metadata(&path) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        if meta.is_dir() {
+            let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            if name == "target" || name.starts_with('.') {
+                continue;
+            }
+            if let Ok(dir) = std::fs::read_dir(&path) {
+                todo.extend(dir.filter_map(|e| e.ok()).map(|e| e.path()));
+            }
+        } else if let Ok(modified) = meta.modified() {
+            r.insert(path, modified);
+        }
+    }
+    r
+}
+
+fn diff(before: &BTreeMap<PathBuf, SystemTime>, after: &BTreeMap<PathBuf, SystemTime>) -> BTreeSet<PathBuf> {
+    let mut r = BTreeSet::new();
+    for (path, modified) in after {
+        if before.get(path) != Some(modified) {
+            r.insert(path.clone());
+        }
+    }
+    for path in before.keys() {
+        if !after.contains_key(path) {
+            r.insert(path.clone());
+        }
+    }
+    r
+}
+
+// modules whose last build read any of the changed files.
+// this is only shown to the user, the rerun does not depend on it
+fn affected(root: &Path, stage: &str, changed: &BTreeSet<PathBuf>) -> BTreeSet<String> {
+    let changed : BTreeSet<PathBuf> = changed.iter().map(|p| p.canonicalize().unwrap_or(p.clone())).collect();
+    let mut r = BTreeSet::new();
+    let dir = match std::fs::read_dir(target_dir(root).join(stage).join("zz")) {
+        Ok(v) => v,
+        Err(_) => return r,
+    };
+    for entry in dir.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if !path.to_string_lossy().ends_with(".buildcache") {
+            continue;
+        }
+        let cf : CFile = match std::fs::read_to_string(&path).ok().and_then(|s| serde_json::from_str(&s).ok()) {
+            Some(v) => v,
+            None => continue,
+        };
+        if cf.sources.iter().any(|s| changed.contains(&s.canonicalize().unwrap_or(s.clone()))) {
+            r.insert(cf.name.human_name());
+        }
+    }
+    r
+}
+
+fn display(root: &Path, p: &Path) -> String {
+    p.strip_prefix(root).unwrap_or(p).to_string_lossy().to_string()
+}
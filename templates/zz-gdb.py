@@ -0,0 +1,69 @@
+# generated by zz, do not edit.
+# load with: (gdb) source target/<stage>/zz-gdb.py
+
+import gdb
+import json
+
+ZZ = json.loads(r"""{{ data }}""")
+
+STRUCTS = {}
+for s in ZZ["structs"]:
+    STRUCTS[s["c_name"]] = s
+    STRUCTS[s["c_name"] + "_t"] = s
+ENUMS = dict((e["c_name"], e) for e in ZZ["enums"])
+
+
+def tail_value(val, field, n):
+    elem = val[field].type.strip_typedefs().target()
+    return val[field].address.cast(elem.array(n - 1).pointer()).dereference()
+
+
+class StructPrinter(object):
+    def __init__(self, info, val):
+        self.info = info
+        self.val = val
+
+    def to_string(self):
+        return self.info["zz_name"]
+
+    def children(self):
+        for f in self.info["fields"]:
+            name = f["name"]
+            v = self.val[name]
+            if name == self.info["tail"] and self.info["tail_len"] is not None:
+                v = tail_value(self.val, name, self.info["tail_len"])
+            if f["string"] and v.type.strip_typedefs().code == gdb.TYPE_CODE_ARRAY:
+                n = v.type.strip_typedefs().range()[1] + 1
+                if n > 0:
+                    raw = v.address.cast(gdb.lookup_type("char").pointer()).string("latin-1", "replace", n)
+                    v = raw.split("\0")[0]
+            yield name, v
+
+
+class EnumPrinter(object):
+    def __init__(self, info, val):
+        self.info = info
+        self.val = val
+
+    def to_string(self):
+        v = int(self.val)
+        for (name, value) in self.info["variants"]:
+            if value == v:
+                return "%s::%s" % (self.info["zz_name"], name)
+        return "%s(%d)" % (self.info["zz_name"], v)
+
+
+def lookup(val):
+    t = val.type.unqualified()
+    stripped = t.strip_typedefs()
+    for name in (t.name, stripped.tag, stripped.name):
+        if name is None:
+            continue
+        if stripped.code == gdb.TYPE_CODE_ENUM and name in ENUMS:
+            return EnumPrinter(ENUMS[name], val)
+        if stripped.code in (gdb.TYPE_CODE_STRUCT, gdb.TYPE_CODE_UNION) and name in STRUCTS:
+            return StructPrinter(STRUCTS[name], val)
+    return None
+
+
+gdb.pretty_printers.append(lookup)
@@ -0,0 +1,65 @@
+# generated by zz, do not edit.
+# load with: (lldb) command script import target/<stage>/zz-lldb.py
+
+import lldb
+import json
+
+ZZ = json.loads(r"""{{ data }}""")
+
+STRUCTS = dict((s["c_name"], s) for s in ZZ["structs"])
+ENUMS = dict((e["c_name"], e) for e in ZZ["enums"])
+
+
+def lookup(table, valobj):
+    t = valobj.GetType().GetUnqualifiedType()
+    for name in (t.GetName(), t.GetCanonicalType().GetName()):
+        name = name.replace("struct ", "").replace("union ", "")
+        if name in table:
+            return table[name]
+        if name.endswith("_t") and name[:-2] in table:
+            return table[name[:-2]]
+    return None
+
+
+def read_string(valobj, n):
+    error = lldb.SBError()
+    data = valobj.GetProcess().ReadMemory(valobj.GetLoadAddress(), n, error)
+    if not error.Success():
+        return None
+    return '"%s"' % data.split(b"\0")[0].decode("latin-1")
+
+
+def struct_summary(valobj, internal_dict):
+    info = lookup(STRUCTS, valobj)
+    if info is None:
+        return None
+    fields = []
+    for f in info["fields"]:
+        child = valobj.GetChildMemberWithName(f["name"])
+        n = child.GetByteSize()
+        if f["name"] == info["tail"] and info["tail_len"] is not None:
+            n = info["tail_len"] * max(1, child.GetType().GetArrayElementType().GetByteSize())
+        if f["string"] and n > 0:
+            v = read_string(child, n)
+        else:
+            v = child.GetSummary() or child.GetValue()
+        fields.append("%s = %s" % (f["name"], v))
+    return "%s { %s }" % (info["zz_name"], ", ".join(fields))
+
+
+def enum_summary(valobj, internal_dict):
+    info = lookup(ENUMS, valobj)
+    if info is None:
+        return None
+    v = valobj.GetValueAsSigned()
+    for (name, value) in info["variants"]:
+        if value == v:
+            return "%s::%s" % (info["zz_name"], name)
+    return "%s(%d)" % (info["zz_name"], v)
+
+
+def __lldb_init_module(debugger, internal_dict):
+    for s in ZZ["structs"]:
+        debugger.HandleCommand("type summary add -F %s.struct_summary %s" % (__name__, s["c_name"]))
+    for e in ZZ["enums"]:
+        debugger.HandleCommand("type summary add -F %s.enum_summary %s" % (__name__, e["c_name"]))
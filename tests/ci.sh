@@ -11,6 +11,11 @@ do
     cd $i
     ../../../target/release/zz clean;
     if ../../../target/release/zz run;  then
+        # projects can check more than the exit code of main
+        if [ -f check.sh ] && ! ZZ=$THIS/../target/release/zz sh check.sh; then
+            echo "$i" failed check.sh
+            exit 1
+        fi
         echo "$i" passed
     else
         echo "$i" failed
@@ -0,0 +1,5 @@
+#!/bin/sh
+# src/vec.zz is what bindgen generates from src/vec.h
+set -e
+cd src
+$ZZ bindgen vec.h -a ../annotations.toml | diff -u vec.zz -
@@ -0,0 +1,38 @@
+#include "vec.h"
+
+vec_status vec_push(vec_t *v, int item) {
+    if (v->len >= VEC_MAX) {
+        return VEC_FULL;
+    }
+    v->items[v->len++] = item;
+    return VEC_OK;
+}
+
+vec_len_t vec_len(const vec_t *v) {
+    return v->len;
+}
+
+vec_small_t vec_first(const vec_t *v) {
+    if (v->len == 0) {
+        return -1;
+    }
+    return (vec_small_t)v->items[0];
+}
+
+int vec_split(const char *s, char **out, size_t r) {
+    int n = 0;
+    if (r == 0) {
+        return 0;
+    }
+    out[n++] = (char*)s;
+    for (; *s && (size_t)n < r; s++) {
+        if (*s == ',') {
+            out[n++] = (char*)s + 1;
+        }
+    }
+    return n;
+}
+
+vec_errno vec_error(void) {
+    return VEC_EUNKNOWN;
+}
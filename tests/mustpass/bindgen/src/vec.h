@@ -0,0 +1,39 @@
+#ifndef VEC_H
+#define VEC_H 1
+
+#include <stddef.h>
+
+#define VEC_MAX     64
+#define VEC_MASK    0xffU
+#define VEC_EMPTY
+
+typedef unsigned int vec_len_t;
+typedef signed char vec_small_t;
+
+typedef enum {
+    VEC_OK = 0,
+    VEC_FULL,
+} vec_status;
+
+/* not representable in zz, so vec_error is not imported */
+typedef enum {
+    VEC_EUNKNOWN = -1,
+} vec_errno;
+
+enum vec_shape {
+    VEC_FLAT  = VEC_MAX,
+    VEC_ROUND,
+};
+
+typedef struct {
+    vec_len_t   len;
+    int         items[VEC_MAX];
+} vec_t;
+
+vec_status vec_push(vec_t *v, int item);
+vec_len_t vec_len(const vec_t *v);
+vec_small_t vec_first(const vec_t *v);
+int vec_split(const char *s, char **out, size_t r);
+vec_errno vec_error(void);
+
+#endif
@@ -0,0 +1,9 @@
+#!/bin/sh
+# the contracts stage catches the call from C that the proof could not see
+set -e
+
+if $ZZ run --contracts 2> target/contracts.err; then
+    echo "the broken where clause was not caught"
+    exit 1
+fi
+grep -q 'contracts_main_half: precondition violated: (a > 0)' target/contracts.err
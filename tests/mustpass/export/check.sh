@@ -0,0 +1,14 @@
+#!/bin/sh
+# the exported Makefile builds from a copy, with nothing of the project around it
+set -e
+$ZZ export make --release -o target/exported
+
+if grep -q "$PWD" target/exported/Makefile; then
+    exit 1
+fi
+
+copy=$(mktemp -d)
+trap 'rm -rf "$copy"' EXIT
+cp -r target/exported/. "$copy"
+make -C "$copy" CC=${CC:-cc}
+"$copy/bin/export"
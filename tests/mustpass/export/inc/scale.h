@@ -0,0 +1 @@
+#define EXPORT_SCALE 2
@@ -0,0 +1 @@
+#define POINT_ORIGIN 1
@@ -0,0 +1,6 @@
+#include "twice.h"
+#include <scale.h>
+
+int twice(int x) {
+    return x * EXPORT_SCALE;
+}
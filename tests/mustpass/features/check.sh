@@ -0,0 +1,5 @@
+#!/bin/sh
+# the server test passes only with the feature its artifact turns on,
+# the feature that one implies, and the optional dependency it brings in
+set -e
+$ZZ test server
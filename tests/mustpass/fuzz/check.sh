@@ -0,0 +1,18 @@
+#!/bin/sh
+# the libfuzzer harness: main of the test is renamed, and every input is its stdin
+set -e
+
+# linking needs clang's libfuzzer, but the C is emitted before that
+$ZZ fuzz echo -e libfuzzer --max-time 1 || true
+c=target/fuzz-libfuzzer/zz/fuzz_tests_echo.c
+if grep -q '^int main' $c; then
+    echo "main of the test was not renamed"
+    exit 1
+fi
+
+${CC:-cc} -o target/harness $c driver.c
+out=$(./target/harness first second)
+if [ "$out" != "$(printf 'got first\ngot second')" ]; then
+    echo "harness did not read the inputs from stdin: $out"
+    exit 1
+fi
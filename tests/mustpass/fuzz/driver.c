@@ -0,0 +1,13 @@
+// stands in for libfuzzer: hands each argument to the harness as one input
+#include <stdint.h>
+#include <stddef.h>
+#include <string.h>
+
+int LLVMFuzzerTestOneInput(uint8_t const *data, size_t size);
+
+int main(int argc, char **argv) {
+    for (int i = 1; i < argc; i++) {
+        LLVMFuzzerTestOneInput((uint8_t const *)argv[i], strlen(argv[i]));
+    }
+    return 0;
+}
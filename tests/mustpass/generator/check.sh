@@ -0,0 +1,41 @@
+#!/bin/sh
+# the script of the generator is an input too, and running it again
+# only touches the files that changed, and removes the ones it no longer writes
+set -e
+
+tmp=$(mktemp -d)
+trap 'rm -rf $tmp' EXIT
+cp -r zz.toml src tools $tmp/
+cd $tmp
+
+$ZZ build
+test -f target/test/gen/table.zz
+touch target/before
+
+sleep 1
+touch tools/gen.sh
+$ZZ build 2> target/gen.err
+grep -q generating target/gen.err
+if [ target/test/gen/table.zz -nt target/before ] || [ target/test/gen.changed -nt target/before ]; then
+    echo "the generator wrote the same again, but it counted as a change"
+    exit 1
+fi
+
+sleep 1
+touch tools/extra tools/gen.sh
+$ZZ build
+test -f target/test/gen/extra.zz
+if [ target/test/gen/table.zz -nt target/before ]; then
+    echo "table.zz was written again, but did not change"
+    exit 1
+fi
+
+sleep 1
+rm tools/extra
+touch tools/gen.sh
+$ZZ build
+if [ -e target/test/gen/extra.zz ]; then
+    echo "extra.zz is no longer generated, but is still there"
+    exit 1
+fi
+test ! -e target/test/gen.tmp
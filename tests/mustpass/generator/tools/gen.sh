@@ -0,0 +1,7 @@
+#!/bin/sh
+# a count of the lines in table.txt, and extra.zz while tools/extra exists
+set -e
+printf 'pub fn count() -> int {\n    return %d;\n}\n' $(wc -l < tools/table.txt) > "$ZZ_GEN_DIR/table.zz"
+if [ -f tools/extra ]; then
+    printf 'pub fn extra() -> int {\n    return 1;\n}\n' > "$ZZ_GEN_DIR/extra.zz"
+fi
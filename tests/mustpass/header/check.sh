@@ -0,0 +1,21 @@
+#!/bin/sh
+# the header artifact carries the local C headers it includes, so it builds on its own
+set -e
+$ZZ build --release
+
+rm -rf target/consumer
+mkdir -p target/consumer
+cp target/release/include/vec.h target/consumer/
+if grep -q "#include \"" target/consumer/vec.h; then
+    exit 1
+fi
+cat > target/consumer/consumer.c <<EOF
+#include "vec.h"
+int main() {
+    Vec v = {1, 2};
+    return header_vec_sum(&v) != 7;
+}
+EOF
+cd target/consumer
+${CC:-cc} consumer.c -o consumer
+./consumer
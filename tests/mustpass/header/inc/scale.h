@@ -0,0 +1 @@
+#define VEC_SCALE 2
@@ -0,0 +1 @@
+#define VEC_ORIGIN 1
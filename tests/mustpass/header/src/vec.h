@@ -0,0 +1,6 @@
+#include "sub/origin.h"
+
+typedef struct {
+    int x;
+    int y;
+} Vec;
@@ -0,0 +1,26 @@
+#!/bin/sh
+# -D replaces what [defines] has, and changing it builds again
+set -e
+
+ret=0
+$ZZ run -D LEVEL=1 || ret=$?
+if [ $ret -ne 1 ]; then
+    echo "-D LEVEL=1 exited with $ret, not 1"
+    exit 1
+fi
+
+ret=0
+$ZZ run -D BACKEND=kqueue || ret=$?
+if [ $ret -ne 3 ]; then
+    echo "-D BACKEND=kqueue exited with $ret, not 3"
+    exit 1
+fi
+
+ret=0
+$ZZ run -D LEVEL=4 || ret=$?
+if [ $ret -ne 2 ]; then
+    echo "-D LEVEL=4 exited with $ret, not 2"
+    exit 1
+fi
+
+$ZZ run
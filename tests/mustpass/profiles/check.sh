@@ -0,0 +1,6 @@
+#!/bin/sh
+# a profile of its own, built into its own directory
+set -e
+
+$ZZ run --profile size
+test -x target/size/bin/profiles
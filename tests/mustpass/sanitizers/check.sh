@@ -0,0 +1,14 @@
+#!/bin/sh
+# other sanitizers, each built into a directory of its own
+set -e
+
+$ZZ test sanitizers --sanitize=undefined
+test -x target/test-undefined/bin/tests_sanitizers
+$ZZ test sanitizers --sanitize=none
+test -x target/test-nosan/bin/tests_sanitizers
+
+if $ZZ test sanitizers --sanitize=address,memory 2> target/combined.err; then
+    echo "address and memory sanitizers were combined"
+    exit 1
+fi
+grep -q 'cannot be used together' target/combined.err
@@ -0,0 +1,20 @@
+#!/bin/sh
+# C programs linked against the library in target, and against the installed one, find it by its soname
+set -e
+mkdir -p target
+
+cat > target/consumer.c <<EOF
+int sharedlib_answer();
+int main() { return sharedlib_answer() != 42; }
+EOF
+
+$ZZ build --release
+test -L target/release/lib/libsharedlib.so.1
+${CC:-cc} target/consumer.c -o target/consumer -Ltarget/release/lib -lsharedlib
+LD_LIBRARY_PATH=target/release/lib ./target/consumer
+
+$ZZ install --prefix $PWD/target/prefix
+test -L target/prefix/lib/libsharedlib.so.1
+test -f target/prefix/lib/libsharedlib.so.1.2.3
+${CC:-cc} target/consumer.c -o target/installed -Ltarget/prefix/lib -lsharedlib
+LD_LIBRARY_PATH=target/prefix/lib ./target/installed
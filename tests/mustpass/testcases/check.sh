@@ -0,0 +1,29 @@
+#!/bin/sh
+# testcase fields, reports, --bless and killing a hanging test with everything it started
+set -e
+
+$ZZ test fields --report junit=target/junit.xml --report json=target/report.json
+grep -q 'classname="tests_fields" name="args"' target/junit.xml
+grep -q '"case": "abort"' target/report.json
+
+# the hanging test leaves a child with its stdout open, which would block the run if it survived
+if timeout 60 $ZZ test hang --timeout 1; then
+    echo "hang did not time out"
+    exit 1
+fi
+
+# bless a copy, so the checked in expectations stay as they are
+tmp=$(mktemp -d)
+trap 'rm -rf $tmp' EXIT
+cp -r zz.toml src tests $tmp/
+cd $tmp
+printf 'wrong' > tests/echo.out
+sed -i 's/"\[one\]\[\]\[two words\]\\n"/"wrong"/' tests/fields.zz
+if $ZZ test fields; then
+    echo "wrong expectations passed"
+    exit 1
+fi
+$ZZ test fields --bless
+$ZZ test fields
+grep -q '"\[one\]\[\]\[two words\]\\n"' tests/fields.zz
+printf '[echo]\nhello' | cmp - tests/echo.out
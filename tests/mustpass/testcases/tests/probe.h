@@ -0,0 +1,33 @@
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+
+// prints every argument in brackets, so empty ones are visible,
+// then does what the first one says
+static int probe(int argc, const char * const *argv) {
+    for (int i = 1; i < argc; i++) {
+        printf("[%s]", argv[i]);
+    }
+    printf("\n");
+    fflush(stdout);
+
+    const char *what = argc > 1 ? argv[1] : "";
+    if (strcmp(what, "env") == 0) {
+        const char *v = getenv("PROBE");
+        fprintf(stderr, "warning: PROBE is %s\n", v ? v : "unset");
+    } else if (strcmp(what, "echo") == 0) {
+        char buf[64];
+        size_t n = fread(buf, 1, sizeof(buf), stdin);
+        fwrite(buf, 1, n, stdout);
+    } else if (strcmp(what, "abort") == 0) {
+        abort();
+    } else if (strcmp(what, "hang") == 0) {
+        // the child keeps stdout open, so the run only ends when both are killed
+        if (fork() == 0) {
+            sleep(1000);
+        }
+        sleep(1000);
+    }
+    return argc - 1;
+}